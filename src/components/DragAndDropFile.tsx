@@ -42,9 +42,12 @@ export default function DragAndDropFile({ onFilesSelected }: DragAndDropFileProp
         "application/msword",
         "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
         "text/plain",
+        "text/csv",
         "image/jpeg",
         "image/jpg",
         "image/png",
+        "image/svg+xml",
+        "image/webp",
         "image/gif",
       ];
 
@@ -15,7 +15,11 @@ export const examConfigs: Record<string, ExamConfig> = {
       'Category Certificate (if applicable)',
       'Passport Size Photo',
       'Signature'
-    ]
+    ],
+    presets: {
+      photo: { width: 200, height: 230, quality: 90 },
+      signature: { width: 140, height: 60, contrast: 1.5 },
+    }
   },
   jee: {
     name: 'JEE',
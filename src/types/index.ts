@@ -4,7 +4,66 @@ export interface ExamConfig {
   maxSizes: {
     [format: string]: number; // in bytes
   };
+  maxDimensions?: {
+    [format: string]: number | [number, number]; // longest edge, or [width, height], in pixels
+  };
+  defaultQuality?: {
+    [format: string]: number; // starting JPEG/AVIF quality, 1-100
+  };
   requirements: string[];
+  validationRules?: ValidationRules;
+  presets?: {
+    [name: string]: ConversionPreset; // e.g. "photo", "signature"
+  };
+  roles?: {
+    [folder: string]: ExamRole; // ZIP folders for convert_zip_for_exam, e.g. "photo", "signature"
+  };
+}
+
+export interface ExamRole {
+  formats?: string[]; // output formats this folder takes; any when empty
+  matchNames?: string[]; // words in the input name marking it for this folder; the folder name when empty
+  filename?: string; // output name, using {base}, {ext}, {index} and {format}
+}
+
+export interface ConversionPreset {
+  quality?: number; // starting JPEG quality, 1-100
+  width?: number;
+  height?: number;
+  fit?: 'stretch' | 'contain'; // contain keeps the aspect ratio within width x height and pads
+  pad_color?: string; // #rrggbb fill for contain, white by default
+  pad_image?: string; // base64 image covering the canvas behind a contained image
+  trim_borders?: boolean; // crop uniform scan margins before anything else
+  trim_tolerance?: number; // per-channel difference still counted as border, 24 by default
+  sharpen?: number;
+  max_megapixels?: number; // scale images over this many million pixels down to just under it
+  brightness?: number;
+  contrast?: number;
+  gamma?: number;
+  color_space?: 'rgb' | 'cmyk'; // cmyk is rejected with CMYK_UNSUPPORTED
+  qr?: {
+    data: string;
+    position?: 'top_left' | 'top_right' | 'bottom_left' | 'bottom_right';
+    size_px: number; // including the quiet zone
+  };
+  interlace?: boolean; // Adam7 PNG output
+  strip_metadata?: boolean; // re-encode inputs already in the target format, dropping EXIF
+  downsample_images?: boolean; // PDF to PDF over the limit: recompress only embedded images, keeping text
+  make_transparent?: boolean; // PNG/AVIF: key out a light background, e.g. for signature scans
+  transparency_threshold?: number; // luma (0-255) at and above which pixels become fully transparent; default 200
+  bit_depth?: 8 | 16; // PNG bits per channel, 8 by default
+  tiff_compression?: 'lzw' | 'deflate'; // lzw by default
+  merge?: boolean; // one multi-page TIFF for all image inputs
+  combine?: boolean; // one PDF for all image inputs, a page each in upload order
+  ensure_orientation?: 'portrait' | 'landscape'; // rotated 90° clockwise when the shape differs
+  page?: number; // 1-based PDF page rendered for JPEG/PNG targets
+  dpi?: number; // PDF render resolution; 150 for scans by default
+}
+
+export interface ValidationRules {
+  minAspectRatio?: number; // width / height
+  maxAspectRatio?: number;
+  minBackgroundBrightness?: number; // 0-255
 }
 
 export interface FileItem {
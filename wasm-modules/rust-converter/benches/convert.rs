@@ -0,0 +1,154 @@
+// Timing benches for the conversion paths, run natively through the public
+// `DocumentConverter` API:
+//
+//     cargo bench --features bench
+//     cargo bench --features bench -- jpeg    # only benches whose name contains "jpeg"
+//
+// Fixtures are generated rather than checked in: a noisy gradient stands in
+// for a camera photo and flat blocks for a scanned form or screenshot. Each
+// bench runs until it has taken TARGET_TIME and at least MIN_ITERATIONS
+// rounds, then reports the mean and fastest round.
+
+use document_converter_wasm::{ConvertRequest, DocumentConverter};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use serde_json::json;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+const TARGET_TIME: Duration = Duration::from_secs(2);
+const MIN_ITERATIONS: u32 = 5;
+const BATCH_FILES: usize = 10;
+
+fn main() {
+    // cargo passes `--bench`; anything else is a name filter
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let selected = |name: &str| match filter.as_deref() {
+        Some(filter) => name.contains(filter),
+        None => true,
+    };
+
+    let camera = jpeg_bytes(&photo(3000, 2000));
+    let scan = png_bytes(&form(1240, 1754));
+
+    if selected("resize_jpeg") {
+        // A 6 MP camera photo shrunk to a portal's 1200px width and size cap
+        let request = request(&[("photo.jpg", "image/jpeg", &camera)], "JPEG", 200_000, json!({ "width": 1200 }));
+        bench("resize_jpeg", 1, || convert(&request));
+    }
+    if selected("png_optimize") {
+        // Re-encoding an A4 scan at 150 DPI; bit_depth forces the rewrite
+        let request = request(&[("form.png", "image/png", &scan)], "PNG", 5_000_000, json!({ "bit_depth": 8 }));
+        bench("png_optimize", 1, || convert(&request));
+    }
+    if selected("pdf_from_image") {
+        let request = request(&[("photo.jpg", "image/jpeg", &camera)], "PDF", 1_000_000, json!({}));
+        bench("pdf_from_image", 1, || convert(&request));
+    }
+    if selected("convert_documents_batch") {
+        let small = jpeg_bytes(&photo(1600, 1200));
+        let names: Vec<String> = (0..BATCH_FILES).map(|index| format!("photo{}.jpg", index)).collect();
+        let files: Vec<(&str, &str, &[u8])> =
+            names.iter().map(|name| (name.as_str(), "image/jpeg", small.as_slice())).collect();
+        let request = request(&files, "JPEG", 150_000, json!({ "width": 800 }));
+        bench("convert_documents_batch", BATCH_FILES, || convert(&request));
+    }
+}
+
+fn bench(name: &str, files_per_round: usize, mut round: impl FnMut()) {
+    // One untimed round warms caches and allocator pools
+    round();
+    let mut timings = Vec::new();
+    let started = Instant::now();
+    while started.elapsed() < TARGET_TIME || (timings.len() as u32) < MIN_ITERATIONS {
+        let round_started = Instant::now();
+        round();
+        timings.push(round_started.elapsed());
+    }
+    let mean = timings.iter().sum::<Duration>() / timings.len() as u32;
+    let fastest = timings.iter().min().copied().unwrap_or_default();
+    println!(
+        "{:<26} {:>4} rounds  mean {:>9.2?}  fastest {:>9.2?}  {:>7.1} files/s",
+        name,
+        timings.len(),
+        mean,
+        fastest,
+        files_per_round as f64 / mean.as_secs_f64()
+    );
+}
+
+fn request(files: &[(&str, &str, &[u8])], format: &str, max_size: u64, options: serde_json::Value) -> ConvertRequest {
+    let files: Vec<serde_json::Value> = files
+        .iter()
+        .map(|(name, mime_type, content)| {
+            json!({ "name": name, "content": content, "mime_type": mime_type, "size": content.len() })
+        })
+        .collect();
+    serde_json::from_value(json!({
+        "files": files,
+        "exam_type": "bench",
+        "target_formats": [format],
+        "max_sizes": { format: max_size },
+        "options": options,
+        // Keeps outputs out of temporary storage so rounds do not pile up
+        "inline": true,
+    }))
+    .expect("bench request should parse")
+}
+
+/// A fresh converter per round, so no state carries over between rounds.
+fn convert(request: &ConvertRequest) {
+    let response = DocumentConverter::new()
+        .convert_documents(request)
+        .expect("bench conversion should run");
+    let response = serde_json::to_value(&response).expect("response should serialize");
+    assert_eq!(response["success"], true, "bench conversion failed: {}", response);
+}
+
+/// Smooth gradients with per-pixel noise, which JPEG finds about as hard to
+/// compress as a real photo.
+fn photo(width: u32, height: u32) -> DynamicImage {
+    let mut state: u32 = 0x9e37_79b9;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        // xorshift32: cheap, deterministic noise
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let noise = (state % 24) as i32 - 12;
+        let channel = |base: u32| (base as i32 + noise).clamp(0, 255) as u8;
+        Rgb([
+            channel(x * 255 / width),
+            channel(y * 255 / height),
+            channel((x + y) * 127 / (width + height)),
+        ])
+    }))
+}
+
+/// White page with dark rules and boxes, like a scanned form.
+fn form(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let rule = y % 40 < 2 && x > 60 && x < width - 60;
+        let boxed = (x / 200 + y / 300) % 5 == 0 && x % 200 > 20 && y % 300 > 20 && y % 300 < 60;
+        if rule || boxed {
+            Rgb([30, 30, 40])
+        } else {
+            Rgb([250, 250, 248])
+        }
+    }))
+}
+
+fn jpeg_bytes(image: &DynamicImage) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, 92)
+        .encode_image(image)
+        .expect("fixture should encode");
+    buffer
+}
+
+fn png_bytes(image: &DynamicImage) -> Vec<u8> {
+    let mut buffer = Cursor::new(Vec::new());
+    image
+        .write_to(&mut buffer, ImageOutputFormat::Png)
+        .expect("fixture should encode");
+    buffer.into_inner()
+}
@@ -0,0 +1,156 @@
+// Unpacking of ZIP uploads into individual input files, and packing of
+// outputs into a ZIP or gzipped tar for download. Entries are inflated through
+// a byte budget shared by the whole archive, so a zip bomb fails once it has
+// produced that much output rather than exhausting memory.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read, Write};
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+/// Tar works in blocks of this many bytes
+const TAR_BLOCK: usize = 512;
+
+/// Container for packed outputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BundleFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    /// A gzip-compressed tar, served as `application/gzip`
+    #[serde(rename = "targz", alias = "tar.gz")]
+    TarGz,
+}
+
+pub struct Entry {
+    /// Final path component of the entry's name
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+pub enum ExtractError {
+    Invalid(String),
+    /// The entries inflate to more than the budget allows
+    TooLarge,
+}
+
+/// Reads every file entry in archive order. Directories and the metadata
+/// macOS adds to archives (`__MACOSX/`, dotfiles) are skipped.
+pub fn extract(content: &[u8], max_total_bytes: u64) -> Result<Vec<Entry>, ExtractError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content))
+        .map_err(|e| ExtractError::Invalid(format!("Not a valid ZIP archive: {}", e)))?;
+
+    let mut entries = Vec::new();
+    let mut remaining = max_total_bytes;
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .map_err(|e| ExtractError::Invalid(format!("Could not read ZIP entry {}: {}", index, e)))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let path = entry.name().to_string();
+        let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+        if name.is_empty() || name.starts_with('.') || path.starts_with("__MACOSX/") {
+            continue;
+        }
+        // The declared size can lie, so the read itself is capped as well
+        if entry.size() > remaining {
+            return Err(ExtractError::TooLarge);
+        }
+        let mut data = Vec::new();
+        entry
+            .take(remaining + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| ExtractError::Invalid(format!("Could not inflate {}: {}", path, e)))?;
+        if data.len() as u64 > remaining {
+            return Err(ExtractError::TooLarge);
+        }
+        remaining -= data.len() as u64;
+        entries.push(Entry { name, content: data });
+    }
+    Ok(entries)
+}
+
+/// Writes `(path, content)` pairs into a new archive of `format` in the
+/// given order. Paths may contain `/` to place an entry in a folder.
+pub fn pack(format: BundleFormat, entries: &[(String, &[u8])]) -> Result<Vec<u8>, String> {
+    match format {
+        BundleFormat::Zip => pack_zip(entries),
+        BundleFormat::TarGz => pack_tar_gz(entries),
+    }
+}
+
+fn pack_zip(entries: &[(String, &[u8])]) -> Result<Vec<u8>, String> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (path, content) in entries {
+        writer
+            .start_file(path.as_str(), options)
+            .and_then(|_| writer.write_all(content).map_err(Into::into))
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+    let archive = writer.finish().map_err(|e| format!("Could not finish ZIP archive: {}", e))?;
+    Ok(archive.into_inner())
+}
+
+/// A ustar archive of regular files, gzip-compressed. Owner, times and
+/// permissions are fixed, so the same outputs always pack to the same bytes.
+fn pack_tar_gz(entries: &[(String, &[u8])]) -> Result<Vec<u8>, String> {
+    let mut writer = GzEncoder::new(Vec::new(), Compression::default());
+    for (path, content) in entries {
+        writer
+            .write_all(&tar_header(path, content.len() as u64)?)
+            .and_then(|_| writer.write_all(content))
+            .and_then(|_| writer.write_all(&[0; TAR_BLOCK][..padding(content.len())]))
+            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+    // The archive ends with two empty blocks
+    writer
+        .write_all(&[0; TAR_BLOCK * 2])
+        .and_then(|_| writer.finish())
+        .map_err(|e| format!("Could not finish tar.gz archive: {}", e))
+}
+
+fn tar_header(path: &str, size: u64) -> Result<[u8; TAR_BLOCK], String> {
+    let mut header = [0u8; TAR_BLOCK];
+    // Names over 100 bytes are split at a `/` into the 155-byte prefix field
+    let (prefix, name) = match path.len() {
+        0..=100 => ("", path),
+        _ => path
+            .char_indices()
+            .filter(|&(index, c)| c == '/' && index <= 155 && path.len() - index - 1 <= 100)
+            .map(|(index, _)| (&path[..index], &path[index + 1..]))
+            .next()
+            .ok_or_else(|| format!("{} is too long for a tar entry name", path))?,
+    };
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], 0);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // The checksum is taken with its own field read as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    write_octal(&mut header[148..155], checksum);
+    Ok(header)
+}
+
+/// `value` as zero-padded octal digits filling `field` but its last byte,
+/// which stays NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// Zero bytes that round `len` up to a whole block.
+fn padding(len: usize) -> usize {
+    (TAR_BLOCK - len % TAR_BLOCK) % TAR_BLOCK
+}
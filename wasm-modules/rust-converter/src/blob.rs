@@ -0,0 +1,106 @@
+// Bytes of a stored output, held either in memory or in a file under the
+// disk backend's directory. A disk blob owns its file: dropping the last
+// reference deletes it, so whatever removes a stored file from the map (TTL
+// cleanup, eviction, purging) also frees the disk space.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+/// Where new outputs are kept. Files stored before a switch stay where they
+/// were written until they expire.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    Memory,
+    /// One file per distinct output under `dir`, which is created if missing.
+    /// Not available in the browser, which has no filesystem.
+    Disk { dir: PathBuf },
+}
+
+pub enum Blob {
+    Memory(Vec<u8>),
+    Disk { path: PathBuf, len: u64 },
+}
+
+impl Default for Blob {
+    fn default() -> Self {
+        Blob::Memory(Vec::new())
+    }
+}
+
+impl Blob {
+    /// Keeps `content` as the backend says. Disk files are named by the
+    /// content's digest, which is unique among live blobs because
+    /// byte-identical outputs share one blob.
+    pub fn store(backend: &StorageBackend, content: Vec<u8>, sha256: &str) -> io::Result<Blob> {
+        match backend {
+            StorageBackend::Memory => Ok(Blob::Memory(content)),
+            StorageBackend::Disk { dir } => {
+                let path = dir.join(sha256);
+                std::fs::write(&path, &content)?;
+                Ok(Blob::Disk {
+                    path,
+                    len: content.len() as u64,
+                })
+            }
+        }
+    }
+
+    /// Writes and deletes a scratch file, to tell whether new outputs can be
+    /// stored at all.
+    pub fn probe(backend: &StorageBackend) -> io::Result<()> {
+        match backend {
+            StorageBackend::Memory => Ok(()),
+            StorageBackend::Disk { dir } => {
+                let path = dir.join(".health-probe");
+                std::fs::write(&path, b"probe")?;
+                std::fs::remove_file(&path)
+            }
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Blob::Memory(content) => content.len() as u64,
+            Blob::Disk { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The stored bytes; a disk blob is read from its file in chunks rather
+    /// than kept around between downloads.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Blob::Memory(content) => Ok(content.clone()),
+            Blob::Disk { path, len } => {
+                let mut content = Vec::with_capacity(*len as usize);
+                File::open(path)?.read_to_end(&mut content)?;
+                Ok(content)
+            }
+        }
+    }
+
+    /// `read`, without copying an in-memory blob.
+    pub fn into_bytes(mut self) -> io::Result<Vec<u8>> {
+        match &mut self {
+            Blob::Memory(content) => Ok(std::mem::take(content)),
+            Blob::Disk { .. } => self.read(),
+        }
+    }
+}
+
+impl Drop for Blob {
+    fn drop(&mut self) {
+        if let Blob::Disk { path, .. } = self {
+            // Already gone is fine; anything else leaves a file for the OS's
+            // own temp cleanup
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
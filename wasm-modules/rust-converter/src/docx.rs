@@ -0,0 +1,135 @@
+// Minimal DOCX support: pulls the paragraph text out of word/document.xml,
+// and writes plain paragraphs into a new package. Formatting, tables and
+// images are ignored both ways; this is only meant for previews and text
+// extraction.
+
+use std::io::{Cursor, Read, Write};
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+/// Refuse to inflate document.xml beyond this, guarding against zip bombs.
+const MAX_DOCUMENT_XML_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Returns the text of each paragraph in document order.
+pub fn extract_paragraphs(content: &[u8]) -> Result<Vec<String>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content))
+        .map_err(|e| format!("Not a valid DOCX package: {}", e))?;
+    let entry = archive
+        .by_name("word/document.xml")
+        .map_err(|_| "DOCX package has no word/document.xml".to_string())?;
+
+    let mut xml = String::new();
+    entry
+        .take(MAX_DOCUMENT_XML_BYTES)
+        .read_to_string(&mut xml)
+        .map_err(|e| format!("Could not read document.xml: {}", e))?;
+
+    Ok(paragraphs_from_xml(&xml))
+}
+
+fn paragraphs_from_xml(xml: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    let mut in_text = false;
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        if in_text {
+            current.push_str(&unescape(&rest[..start]));
+        }
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let tag = &rest[start + 1..end];
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+
+        match name {
+            "w:p" if self_closing => paragraphs.push(String::new()),
+            "w:p" => current.clear(),
+            "/w:p" => paragraphs.push(std::mem::take(&mut current)),
+            "w:t" if !self_closing => in_text = true,
+            "/w:t" => in_text = false,
+            "w:tab" => current.push(' '),
+            _ => {}
+        }
+        rest = &rest[end + 1..];
+    }
+    paragraphs
+}
+
+const CONTENT_TYPES_XML: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+    r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+    r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+    r#"<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>"#,
+    r#"</Types>"#,
+);
+
+const PACKAGE_RELS_XML: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>"#,
+    r#"</Relationships>"#,
+);
+
+/// Packs each string into its own paragraph of an otherwise unstyled
+/// document, with just the parts Word needs to open it.
+pub fn write_paragraphs(paragraphs: &[String]) -> Result<Vec<u8>, String> {
+    let mut document = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>"#,
+    ));
+    for paragraph in paragraphs {
+        document.push_str(&format!(
+            r#"<w:p><w:r><w:t xml:space="preserve">{}</w:t></w:r></w:p>"#,
+            escape(paragraph)
+        ));
+    }
+    document.push_str("</w:body></w:document>");
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in [
+        ("[Content_Types].xml", CONTENT_TYPES_XML),
+        ("_rels/.rels", PACKAGE_RELS_XML),
+        ("word/document.xml", document.as_str()),
+    ] {
+        writer
+            .start_file(name, options)
+            .and_then(|_| writer.write_all(content.as_bytes()).map_err(Into::into))
+            .map_err(|e| format!("Could not write {}: {}", name, e))?;
+    }
+    let package = writer.finish().map_err(|e| format!("Could not finish DOCX package: {}", e))?;
+    Ok(package.into_inner())
+}
+
+/// Characters XML does not allow at all are dropped rather than escaped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -1,260 +1,5775 @@
 use wasm_bindgen::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::imageops::FilterType;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, GrayImage, ImageEncoder, RgbImage, Rgba, RgbaImage};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use blob::{Blob, StorageBackend};
+
+mod archive;
+mod blob;
+mod docx;
+mod font;
+mod logging;
+mod pdf;
+mod png;
+mod render_cache;
+mod stats;
+mod svg;
+mod table;
+mod text;
+
+#[cfg(test)]
+mod tests;
 
 // Import the `console.log` function from the `console` module
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
-// Define a macro for easier console logging
+// Native builds (the benches) have no console to log to
+#[cfg(not(target_arch = "wasm32"))]
+fn log(_s: &str) {}
+
+// Define macros for easier console logging, one per level; `logging` decides
+// whether and how each line is written. The message is only formatted when
+// its level is enabled.
+macro_rules! log_at {
+    ($level:expr, $($t:tt)*) => (
+        if logging::enabled($level) {
+            if let Some(line) = logging::format_line($level, &format_args!($($t)*).to_string()) { log(&line) }
+        }
+    )
+}
+
+macro_rules! console_error {
+    ($($t:tt)*) => (log_at!(logging::LogLevel::Error, $($t)*))
+}
+
+macro_rules! console_warn {
+    ($($t:tt)*) => (log_at!(logging::LogLevel::Warn, $($t)*))
+}
+
 macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => (log_at!(logging::LogLevel::Info, $($t)*))
 }
 
-#[derive(Serialize, Deserialize)]
+macro_rules! console_debug {
+    ($($t:tt)*) => (log_at!(logging::LogLevel::Debug, $($t)*))
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FileData {
     name: String,
+    /// An array of bytes, or a base64 string in the standard or URL-safe
+    /// alphabet, with or without padding
+    #[serde(deserialize_with = "deserialize_content")]
     content: Vec<u8>,
     mime_type: String,
     size: u64,
+    /// Converted outputs of this file can only be downloaded once
+    #[serde(default)]
+    single_use: bool,
+    /// Converted outputs of this file stop being downloadable after this many reads
+    #[serde(default)]
+    max_downloads: Option<u32>,
+    /// Encoding of `text/plain` inputs; a byte-order mark takes precedence
+    #[serde(default)]
+    charset: Option<String>,
+    /// Keep the original upload so it can be converted again with
+    /// `reconvert` until the storage TTL runs out
+    #[serde(default)]
+    retain_source: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ConvertRequest {
+    /// May be empty for `reconvert`, which supplies the retained source
+    #[serde(default)]
     files: Vec<FileData>,
     exam_type: String,
     target_formats: Vec<String>,
     max_sizes: HashMap<String, u64>,
+    /// Longest edge in pixels, or a `[width, height]` box, allowed per
+    /// format; larger images are scaled down with their aspect ratio kept.
+    /// Overrides the exam config's value.
+    #[serde(default)]
+    max_dimensions: HashMap<String, DimensionLimit>,
+    /// Smallest output allowed per format, for portals that reject files
+    /// below a size; outputs under it fail with `SIZE_BELOW_MINIMUM`
+    #[serde(default)]
+    min_sizes: HashMap<String, u64>,
+    /// Grow raster outputs under their minimum, by raising JPEG quality and
+    /// then upscaling, instead of failing
+    #[serde(default)]
+    pad_to_min: Option<bool>,
+    #[serde(default)]
+    options: ConversionOptions,
+    /// Formats to try, in order, when a target cannot meet its size limit
+    #[serde(default)]
+    format_fallbacks: HashMap<String, Vec<String>>,
+    /// Name of a preset in the exam config whose options apply underneath `options`
+    #[serde(default)]
+    preset: Option<String>,
+    /// Output name pattern using `{base}`, `{ext}`, `{index}` (1-based
+    /// position in the batch) and `{format}`, e.g. `{base}_photo.{ext}`
+    #[serde(default)]
+    filename_template: Option<String>,
+    /// Client-chosen key; repeating it within the storage TTL returns the
+    /// first successful response instead of converting again
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Return the outputs' bytes in the response instead of storing them
+    #[serde(default)]
+    inline: Option<bool>,
+    /// Keep the original file name for outputs in the input's own format,
+    /// e.g. `IMG_01.JPG` stays as is rather than becoming `IMG_01.jpg`.
+    /// Takes precedence over `filename_template` for those outputs.
+    #[serde(default)]
+    preserve_name: Option<bool>,
+    /// Title, author, subject and keywords written into PDF outputs
+    #[serde(default)]
+    pdf_metadata: Option<pdf::Metadata>,
+    /// Container `convert_zip_for_exam` packs the outputs into: `zip`
+    /// (default) or `targz`
+    #[serde(default)]
+    bundle_format: archive::BundleFormat,
+    /// Resized copies, e.g. `1x` and `2x`, produced for each image target
+    /// in place of a single output; PDF and DOCX targets are unaffected
+    #[serde(default)]
+    variants: Option<Vec<Variant>>,
+}
+
+/// One copy of an image output requested through `variants`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Variant {
+    /// Appended to the output name: `{base}-{suffix}.{ext}`
+    suffix: String,
+    /// Longest edge or `[width, height]` box for this copy; tighter limits
+    /// from the request or exam config still apply
+    #[serde(default)]
+    max_dimension: Option<DimensionLimit>,
+    /// Size limit in bytes for this copy, where tighter than the format's
+    #[serde(default)]
+    max_size: Option<u64>,
+}
+
+/// Upper bound on an output's dimensions, from `max_dimensions`. Written as
+/// a number for a longest edge or as `[width, height]` for a box.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DimensionLimit {
+    LongestEdge(u32),
+    Box(u32, u32),
+}
+
+impl DimensionLimit {
+    /// The largest width and height an image may have.
+    fn bounds(self) -> (u32, u32) {
+        match self {
+            DimensionLimit::LongestEdge(edge) => (edge, edge),
+            DimensionLimit::Box(width, height) => (width, height),
+        }
+    }
+
+    fn exceeded_by(self, width: u32, height: u32) -> bool {
+        let (max_width, max_height) = self.bounds();
+        width > max_width || height > max_height
+    }
+
+    /// The area both limits allow.
+    fn intersect(self, other: DimensionLimit) -> DimensionLimit {
+        match (self, other) {
+            (DimensionLimit::LongestEdge(a), DimensionLimit::LongestEdge(b)) => DimensionLimit::LongestEdge(a.min(b)),
+            _ => {
+                let ((width_a, height_a), (width_b, height_b)) = (self.bounds(), other.bounds());
+                DimensionLimit::Box(width_a.min(width_b), height_a.min(height_b))
+            }
+        }
+    }
+}
+
+/// Binary data in a request, as a JSON array of bytes or a base64 string.
+/// A string is decoded straight from the request text, without an owned
+/// copy of it or a second decoding attempt.
+struct EncodedBytes(Vec<u8>);
+
+impl<'de> Deserialize<'de> for EncodedBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = EncodedBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a byte array or a base64 string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<EncodedBytes, E> {
+                decode_base64(text)
+                    .map(EncodedBytes)
+                    .map_err(|e| E::custom(format!("{}: {}", BASE64_ERROR_PREFIX, e)))
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<EncodedBytes, E> {
+                Ok(EncodedBytes(bytes.to_vec()))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<EncodedBytes, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(EncodedBytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn deserialize_content<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    Ok(EncodedBytes::deserialize(deserializer)?.0)
+}
+
+fn deserialize_optional_content<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+    Ok(Option::<EncodedBytes>::deserialize(deserializer)?.map(|bytes| bytes.0))
+}
+
+/// Start of the deserializer's message for bad base64 content, which
+/// `request_parse_error` reports as `DECODE_ERROR` rather than a malformed
+/// request.
+const BASE64_ERROR_PREFIX: &str = "content is not valid base64";
+
+/// Decodes standard or URL-safe base64, the latter being what JS `base64url`
+/// helpers produce. One pass over the text picks the alphabet, then it is
+/// decoded once into a buffer sized from its length. Padding is optional.
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    use base64::alphabet::{STANDARD, URL_SAFE};
+    use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+    let text = text.trim();
+    let (mut standard, mut url_safe) = (false, false);
+    for byte in text.bytes() {
+        match byte {
+            b'+' | b'/' => standard = true,
+            b'-' | b'_' => url_safe = true,
+            _ => {}
+        }
+    }
+    if standard && url_safe {
+        return Err("it mixes the standard and URL-safe alphabets".to_string());
+    }
+    let alphabet = if url_safe { &URL_SAFE } else { &STANDARD };
+    let engine = GeneralPurpose::new(
+        alphabet,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+    let mut bytes = Vec::with_capacity(base64::decoded_len_estimate(text.len()));
+    engine.decode_vec(text, &mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Lower size bound for one output, from the request's `min_sizes`.
+#[derive(Clone, Copy)]
+struct SizeFloor {
+    min_size: u64,
+    /// Grow an output under `min_size` instead of rejecting it
+    pad: bool,
+}
+
+/// Output settings for raster targets.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ConversionOptions {
+    /// Starting JPEG quality, 1-100; lowered as needed to fit `max_size`
+    quality: Option<u8>,
+    /// Target width in pixels; the height follows the source aspect if unset
+    width: Option<u32>,
+    /// Target height in pixels; the width follows the source aspect if unset
+    height: Option<u32>,
+    /// How an image given both `width` and `height` is fitted to them
+    fit: Option<Fit>,
+    /// Fill around a `contain`ed image, `#rrggbb`; white by default
+    pad_color: Option<String>,
+    /// Image scaled to cover the canvas behind a `contain`ed image, as a
+    /// byte array or base64; takes precedence over `pad_color`
+    #[serde(default, deserialize_with = "deserialize_optional_content")]
+    pad_image: Option<Vec<u8>>,
+    /// Crop away uniform margins, such as a scanner's white or black border,
+    /// before anything else is applied
+    trim_borders: Option<bool>,
+    /// Largest per-channel difference from the corner colour still counted
+    /// as border when trimming; 24 by default
+    trim_tolerance: Option<u8>,
+    /// Turn a light background, such as the paper behind a signature,
+    /// transparent so only the ink remains; needs an output with alpha
+    make_transparent: Option<bool>,
+    /// Lightness (0-255) from which a pixel counts as background for
+    /// `make_transparent`; 200 by default
+    transparency_threshold: Option<u8>,
+    /// Text band appended below the image
+    caption: Option<CaptionSpec>,
+    /// `[cols, rows]`: merge all image inputs targeting PDF into one document
+    /// with this many images per page
+    grid: Option<(u8, u8)>,
+    /// Unsharp-mask strength (blur sigma), applied after any resize
+    sharpen: Option<f32>,
+    /// Pixel budget in millions; larger images are scaled down to just
+    /// under it, aspect kept, whatever their byte size limit
+    max_megapixels: Option<f32>,
+    /// Added to every channel, -255 to 255
+    brightness: Option<i32>,
+    /// Multiplies each channel's distance from mid-grey; above 1 increases contrast
+    contrast: Option<f32>,
+    /// Gamma correction exponent; above 1 lightens midtones
+    gamma: Option<f32>,
+    /// `rgb` (default) or `cmyk`
+    color_space: Option<String>,
+    /// QR code stamped onto the image, before any caption band is added
+    qr: Option<QrSpec>,
+    /// Write PNG output Adam7-interlaced so previews render progressively
+    interlace: Option<bool>,
+    /// Re-encode an image input even when it is already in the target format
+    /// and under the size limit, dropping EXIF and other metadata
+    strip_metadata: Option<bool>,
+    /// For PDF input to PDF over the size limit, recompress and downscale
+    /// only its embedded images, keeping text selectable, instead of
+    /// passing it through unchanged
+    downsample_images: Option<bool>,
+    /// PNG bits per channel, 8 (default) or 16
+    bit_depth: Option<u8>,
+    /// Lossless compression of TIFF output, `lzw` (default) or `deflate`
+    tiff_compression: Option<TiffCompression>,
+    /// Combine all image inputs targeting TIFF into one multi-page TIFF, a
+    /// page per input in upload order
+    merge: Option<bool>,
+    /// Combine all image inputs targeting PDF into one document, a page per
+    /// input in upload order; `grid` takes precedence when set
+    combine: Option<bool>,
+    /// Field separator of CSV input, a single ASCII character; defaults to `,`
+    delimiter: Option<char>,
+    /// 0-based frame to take from an animated WebP input; the first by default
+    frame: Option<u32>,
+    /// 1-based page of a PDF input to render for JPEG and PNG targets; the
+    /// first by default
+    page: Option<u32>,
+    /// Resolution to render a PDF page at for JPEG and PNG targets; by
+    /// default 300 for PDFs with a text layer and 150 for scans
+    dpi: Option<u32>,
+    /// Rotate the image 90° clockwise when its shape does not match, before
+    /// any resize; square images are left alone
+    ensure_orientation: Option<Orientation>,
+    /// Dimension limit for the format being produced, from `max_dimensions`.
+    /// Ignored when `width` or `height` asks for exact dimensions.
+    #[serde(skip)]
+    max_dimension: Option<DimensionLimit>,
+}
+
+impl ConversionOptions {
+    /// Fills every option left unset here from `base`.
+    fn layered_over(&self, base: &ConversionOptions) -> ConversionOptions {
+        ConversionOptions {
+            quality: self.quality.or(base.quality),
+            width: self.width.or(base.width),
+            height: self.height.or(base.height),
+            fit: self.fit.or(base.fit),
+            pad_color: self.pad_color.clone().or_else(|| base.pad_color.clone()),
+            trim_borders: self.trim_borders.or(base.trim_borders),
+            trim_tolerance: self.trim_tolerance.or(base.trim_tolerance),
+            make_transparent: self.make_transparent.or(base.make_transparent),
+            transparency_threshold: self.transparency_threshold.or(base.transparency_threshold),
+            pad_image: self.pad_image.clone().or_else(|| base.pad_image.clone()),
+            caption: self.caption.clone().or_else(|| base.caption.clone()),
+            grid: self.grid.or(base.grid),
+            sharpen: self.sharpen.or(base.sharpen),
+            max_megapixels: self.max_megapixels.or(base.max_megapixels),
+            brightness: self.brightness.or(base.brightness),
+            contrast: self.contrast.or(base.contrast),
+            gamma: self.gamma.or(base.gamma),
+            color_space: self.color_space.clone().or_else(|| base.color_space.clone()),
+            qr: self.qr.clone().or_else(|| base.qr.clone()),
+            interlace: self.interlace.or(base.interlace),
+            strip_metadata: self.strip_metadata.or(base.strip_metadata),
+            downsample_images: self.downsample_images.or(base.downsample_images),
+            bit_depth: self.bit_depth.or(base.bit_depth),
+            tiff_compression: self.tiff_compression.or(base.tiff_compression),
+            merge: self.merge.or(base.merge),
+            combine: self.combine.or(base.combine),
+            delimiter: self.delimiter.or(base.delimiter),
+            frame: self.frame.or(base.frame),
+            page: self.page.or(base.page),
+            dpi: self.dpi.or(base.dpi),
+            ensure_orientation: self.ensure_orientation.or(base.ensure_orientation),
+            max_dimension: self.max_dimension.or(base.max_dimension),
+        }
+    }
+
+    /// Whether the options change pixels, so an input already in the target
+    /// format still has to be decoded and re-encoded.
+    fn requires_reencode(&self) -> bool {
+        self.width.is_some()
+            || self.height.is_some()
+            || self.trim_borders == Some(true)
+            || self.caption.is_some()
+            || self.sharpen.is_some()
+            || self.adjusts_tone()
+            || self.qr.is_some()
+            || self.make_transparent == Some(true)
+    }
+
+    fn exceeds_megapixels(&self, width: u32, height: u32) -> bool {
+        self.max_megapixels
+            .is_some_and(|megapixels| megapixel_bounds(width, height, megapixels).is_some())
+    }
+
+    fn adjusts_tone(&self) -> bool {
+        self.brightness.is_some() || self.contrast.is_some() || self.gamma.is_some()
+    }
+
+    /// Whether `max_dimension`, `max_megapixels` or `ensure_orientation`
+    /// would change an image of this size.
+    fn reshapes(&self, width: u32, height: u32) -> bool {
+        self.max_dimension.is_some_and(|limit| limit.exceeded_by(width, height))
+            || self.exceeds_megapixels(width, height)
+            || self.ensure_orientation.is_some_and(|orientation| !orientation.matches(width, height))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    /// Resize to exactly `width` x `height`, distorting the aspect ratio
+    #[default]
+    Stretch,
+    /// Scale to fit inside `width` x `height` and pad the rest
+    Contain,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Orientation {
+    fn matches(self, width: u32, height: u32) -> bool {
+        match self {
+            Orientation::Portrait => height >= width,
+            Orientation::Landscape => width >= height,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QrSpec {
+    data: String,
+    /// Corner to place the code in
+    #[serde(default)]
+    position: QrPosition,
+    /// Edge length of the code including its quiet zone
+    size_px: u32,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QrPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CaptionSpec {
+    text: String,
+    height_px: u32,
+    /// Hex color such as `#ffffff`
+    #[serde(default = "default_caption_background")]
+    background: String,
+    #[serde(default = "default_caption_text_color")]
+    text_color: String,
+}
+
+fn default_caption_background() -> String {
+    "#ffffff".to_string()
+}
+
+fn default_caption_text_color() -> String {
+    "#000000".to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SizeEstimate {
+    original_name: String,
+    format: String,
+    estimated_size: u64,
+    max_size: Option<u64>,
+    fits: bool,
+}
+
+/// How `echo_request` read a request, for checking its shape while
+/// integrating; nothing is converted or stored.
+#[cfg(feature = "debug")]
+#[derive(Serialize)]
+pub struct RequestEcho {
+    exam_type: String,
+    exam_config_found: bool,
+    /// Limit per requested format and fallback once the exam's limits are
+    /// merged in; `null` means unlimited
+    max_sizes: std::collections::BTreeMap<String, Option<u64>>,
+    /// The request's options layered over its preset
+    options: ConversionOptions,
+    files: Vec<EchoedFile>,
+    /// Everything the conversion would reject, plus fields it would ignore
+    warnings: Vec<Warning>,
+}
+
+#[cfg(feature = "debug")]
+#[derive(Serialize)]
+pub struct EchoedFile {
+    name: String,
+    declared_mime_type: String,
+    /// From the content's magic bytes; `null` when not recognized
+    detected_mime_type: Option<String>,
+    /// Length of the decoded `content`
+    size: u64,
+    /// Target formats with `AUTO` resolved for this file
+    formats: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
+pub struct DocumentInfo {
+    name: String,
+    mime_type: String,
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    page_count: Option<usize>,
+    /// Whether the PDF carries extractable text, as opposed to scanned pages
+    has_text_layer: Option<bool>,
+    /// Resolution PDF pages would be rasterized at for image targets
+    raster_dpi: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ConvertedFile {
     original_name: String,
     converted_name: String,
-    download_url: String,
+    /// Absent for inline outputs, which are never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    download_url: Option<String>,
+    /// The converted bytes, base64-encoded, when the request asked for inline output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_base64: Option<String>,
     format: String,
     size: u64,
+    /// Pixel dimensions for raster outputs
+    width: Option<u32>,
+    height: Option<u32>,
+    /// Requested format this output stands in for, when a fallback was used
+    fallback_from: Option<String>,
+    /// Hex SHA-256 of the converted bytes, for verifying downloads
+    sha256: String,
+    /// Id of the retained original, when the input asked to be retained
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_id: Option<String>,
+    /// Suffix of the requested variant this output is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+    /// False when the input was passed through byte for byte, having
+    /// already been in the target format and within its limits
+    #[serde(default)]
+    was_reencoded: bool,
+    /// Encodes tried while fitting the size limit; 0 when the output needed
+    /// no search, such as a passthrough or a lossless format
+    #[serde(default)]
+    attempts_used: u32,
 }
 
+/// What is known about a stored file, available without downloading it.
 #[derive(Serialize, Deserialize)]
+pub struct FileMetadata {
+    file_id: String,
+    format: String,
+    content_type: String,
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    expires_at: f64,
+    remaining_downloads: Option<u32>,
+    sha256: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ConvertResponse {
     success: bool,
     files: Vec<ConvertedFile>,
-    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<ConversionError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Warning>,
+    /// Files the batch gave up on while converting the rest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    file_errors: Vec<FileError>,
 }
 
-pub struct DocumentConverter {
-    temp_storage: HashMap<String, Vec<u8>>,
+/// A file dropped from an otherwise successful batch because it ran past the
+/// conversion timeout; `original_name` lists every input for a merged output.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileError {
+    original_name: String,
+    format: String,
+    error: ConversionError,
 }
 
-impl DocumentConverter {
-    pub fn new() -> Self {
+/// Non-fatal issue noticed while converting, reported alongside the results.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Warning {
+    code: String,
+    message: String,
+}
+
+impl ConvertResponse {
+    fn failure(error: ConversionError) -> Self {
         Self {
-            temp_storage: HashMap::new(),
+            success: false,
+            files: vec![],
+            error: Some(error),
+            warnings: vec![],
+            file_errors: vec![],
         }
     }
+}
 
-    pub fn convert_documents(&mut self, request: &ConvertRequest) -> Result<ConvertResponse, String> {
-        console_log!("🦀 Starting document conversion for {} files", request.files.len());
-        
-        let mut converted_files = Vec::new();
+/// Caps on a single `convert_documents` call, keeping a huge drop of files
+/// from exhausting the browser tab's memory.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchLimits {
+    max_total_bytes: u64,
+    max_file_count: usize,
+    max_file_bytes: u64,
+}
 
-        for file_data in &request.files {
-            console_log!("Processing file: {}", file_data.name);
-            
-            // Convert to each target format
-            for format in &request.target_formats {
-                let max_size = request.max_sizes.get(format).copied().unwrap_or(u64::MAX);
-                
-                match self.convert_to_format(file_data, format, max_size) {
-                    Ok(converted) => {
-                        converted_files.push(converted);
-                        console_log!("✅ Converted {} to {}", file_data.name, format);
-                    }
-                    Err(e) => {
-                        console_log!("❌ Failed to convert {} to {}: {}", file_data.name, format, e);
-                        return Ok(ConvertResponse {
-                            success: false,
-                            files: vec![],
-                            error: Some(e),
-                        });
-                    }
-                }
-            }
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: 100 * 1024 * 1024,
+            max_file_count: 50,
+            max_file_bytes: 25 * 1024 * 1024,
         }
-
-        Ok(ConvertResponse {
-            success: true,
-            files: converted_files,
-            error: None,
-        })
     }
+}
 
-    fn convert_to_format(
-        &mut self,
-        file_data: &FileData,
-        target_format: &str,
-        max_size: u64,
-    ) -> Result<ConvertedFile, String> {
-        let converted_content = match target_format.to_uppercase().as_str() {
-            "PDF" => self.convert_to_pdf(file_data)?,
-            "JPEG" | "JPG" => self.convert_to_jpeg(file_data, max_size)?,
-            "PNG" => self.convert_to_png(file_data, max_size)?,
-            "DOCX" => self.convert_to_docx(file_data)?,
-            _ => return Err(format!("Unsupported format: {}", target_format)),
-        };
-
-        // Check size constraint
-        if converted_content.len() as u64 > max_size {
-            return Err(format!(
-                "Converted file size ({} bytes) exceeds maximum allowed size ({} bytes)",
-                converted_content.len(),
-                max_size
+impl BatchLimits {
+    fn check(&self, files: &[FileData]) -> Result<(), ConversionError> {
+        if files.len() > self.max_file_count {
+            return Err(ConversionError::new(
+                "TOO_MANY_FILES",
+                format!("{} files submitted, at most {} are allowed", files.len(), self.max_file_count),
             ));
         }
+        if let Some(file) = files.iter().find(|file| file.content.len() as u64 > self.max_file_bytes) {
+            return Err(ConversionError::new(
+                "FILE_TOO_LARGE",
+                format!(
+                    "{} is {}, the limit per file is {}",
+                    file.name,
+                    format_bytes(file.content.len() as u64),
+                    format_bytes(self.max_file_bytes)
+                ),
+            )
+            .with_sizes(file.content.len() as u64, self.max_file_bytes));
+        }
+        let total: u64 = files.iter().map(|file| file.content.len() as u64).sum();
+        if total > self.max_total_bytes {
+            return Err(ConversionError::new(
+                "BATCH_TOO_LARGE",
+                format!(
+                    "Batch totals {}, the limit is {}",
+                    format_bytes(total),
+                    format_bytes(self.max_total_bytes)
+                ),
+            )
+            .with_sizes(total, self.max_total_bytes));
+        }
+        Ok(())
+    }
+}
 
-        // Generate unique filename and create blob URL
-        let file_id = uuid::Uuid::new_v4().to_string();
-        let extension = target_format.to_lowercase();
-        let base_name = file_data.name.rsplit('.').nth(1).unwrap_or(&file_data.name);
-        let converted_name = format!("{}.{}", base_name, extension);
+/// Cap on the bytes held in temporary storage and what to do when a new
+/// output would exceed it.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StorageLimit {
+    /// `None` means unlimited
+    max_bytes: Option<u64>,
+    #[serde(default)]
+    when_full: StorageFullPolicy,
+}
 
-        // Store in temporary storage (in real implementation, create blob URL)
-        self.temp_storage.insert(file_id.clone(), converted_content.clone());
-        let download_url = format!("blob:{}", file_id);
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageFullPolicy {
+    /// Fail the conversion with `STORAGE_FULL`
+    #[default]
+    Reject,
+    /// Drop the least recently used files until the new one fits
+    EvictLeastRecentlyUsed,
+    /// Return the new file's bytes in the response instead of storing it,
+    /// as for an `inline` request; files over the inline limit are rejected
+    Inline,
+}
 
-        Ok(ConvertedFile {
-            original_name: file_data.name.clone(),
-            converted_name,
-            download_url,
-            format: target_format.to_string(),
-            size: converted_content.len() as u64,
-        })
-    }
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TiffCompression {
+    #[default]
+    Lzw,
+    Deflate,
+}
 
-    fn convert_to_pdf(&self, file_data: &FileData) -> Result<Vec<u8>, String> {
-        match file_data.mime_type.as_str() {
-            "application/pdf" => Ok(file_data.content.clone()),
-            "image/jpeg" | "image/jpg" | "image/png" => {
-                self.create_pdf_with_image(&file_data.content)
-            }
-            _ => Err("Cannot convert this file type to PDF".to_string()),
-        }
-    }
+/// Which encoder writes JPEG output. Both are pure Rust; a C encoder such
+/// as mozjpeg cannot be built for the WASM target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JpegBackend {
+    /// The `image` crate's baseline encoder
+    #[default]
+    Image,
+    /// The `jpeg-encoder` crate, with optimized Huffman tables for smaller
+    /// files at the same quality
+    JpegEncoder,
+}
 
-    fn convert_to_jpeg(&self, file_data: &FileData, max_size: u64) -> Result<Vec<u8>, String> {
-        match file_data.mime_type.as_str() {
-            "image/jpeg" | "image/jpg" => {
-                self.compress_image(&file_data.content, "jpeg", max_size)
-            }
-            "image/png" => {
-                self.convert_png_to_jpeg(&file_data.content, max_size)
-            }
-            "application/pdf" => {
-                self.pdf_to_jpeg(&file_data.content, max_size)
-            }
-            _ => Err("Cannot convert this file type to JPEG".to_string()),
+impl JpegBackend {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "image" => Some(JpegBackend::Image),
+            "jpeg_encoder" | "jpeg-encoder" => Some(JpegBackend::JpegEncoder),
+            _ => None,
         }
     }
+}
 
-    fn convert_to_png(&self, file_data: &FileData, max_size: u64) -> Result<Vec<u8>, String> {
-        match file_data.mime_type.as_str() {
-            "image/png" => {
-                self.compress_image(&file_data.content, "png", max_size)
-            }
-            "image/jpeg" | "image/jpg" => {
-                self.convert_jpeg_to_png(&file_data.content, max_size)
-            }
-            _ => Err("Cannot convert this file type to PNG".to_string()),
+/// Deployment-level restriction on which input MIME types are accepted.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InputPolicy {
+    /// When set, only these MIME types are accepted
+    allowed_mime_types: Option<Vec<String>>,
+    /// Always rejected, even if also listed as allowed
+    #[serde(default)]
+    denied_mime_types: Vec<String>,
+}
+
+impl InputPolicy {
+    fn permits(&self, mime_type: &str) -> bool {
+        let matches = |list: &[String]| list.iter().any(|m| m.eq_ignore_ascii_case(mime_type));
+        if matches(&self.denied_mime_types) {
+            return false;
+        }
+        match &self.allowed_mime_types {
+            Some(allowed) => matches(allowed),
+            None => true,
         }
     }
+}
 
-    fn convert_to_docx(&self, file_data: &FileData) -> Result<Vec<u8>, String> {
-        match file_data.mime_type.as_str() {
-            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
-                Ok(file_data.content.clone())
-            }
-            _ => Err("Cannot convert this file type to DOCX".to_string()),
+/// The `error` of every failed export, nested as
+/// `{"success": false, "error": {"code": ..., "message": ...}}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConversionError {
+    code: String,
+    message: String,
+    /// Settings likely to succeed, present when the failure was a size
+    /// limit; boxed to keep `Result`s carrying this error small
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Box<SizeSuggestions>>,
+    /// For size failures, the size that failed and the limit it was held
+    /// to, in bytes; the message gives the same sizes in readable units
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    actual_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit_bytes: Option<u64>,
+}
+
+impl ConversionError {
+    fn new(code: &str, message: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            message: message.into(),
+            suggestions: None,
+            actual_bytes: None,
+            limit_bytes: None,
         }
     }
 
-    // Helper methods (mock implementations for WASM)
-    fn create_pdf_with_image(&self, _image_content: &[u8]) -> Result<Vec<u8>, String> {
-        // In a real implementation, you would use a PDF library like pdf-writer
-        console_log!("📄 Creating PDF with embedded image");
-        Ok(b"Mock PDF content with embedded image".to_vec())
+    fn with_sizes(mut self, actual_bytes: u64, limit_bytes: u64) -> Self {
+        self.actual_bytes = Some(actual_bytes);
+        self.limit_bytes = Some(limit_bytes);
+        self
     }
+}
 
-    fn compress_image(&self, content: &[u8], format: &str, max_size: u64) -> Result<Vec<u8>, String> {
-        console_log!("🖼️ Compressing {} image to max {} bytes", format, max_size);
-        
-        if content.len() as u64 <= max_size {
-            Ok(content.to_vec())
-        } else {
-            // Simulate compression by reducing size
-            let compression_ratio = max_size as f64 / content.len() as f64;
-            let compressed_size = (content.len() as f64 * compression_ratio) as usize;
-            Ok(content[..compressed_size.min(content.len())].to_vec())
-        }
-    }
+/// Computed from a failed size-limited attempt so the UI can offer a retry
+/// with settings that are likely to fit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SizeSuggestions {
+    /// Smallest output the converter managed to produce
+    smallest_size: u64,
+    max_size: u64,
+    /// Whether downscaling is expected to get under the limit
+    resize_would_help: bool,
+    current_width: Option<u32>,
+    current_height: Option<u32>,
+    suggested_width: Option<u32>,
+    suggested_height: Option<u32>,
+    suggested_quality: Option<u8>,
+}
 
-    fn convert_png_to_jpeg(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, String> {
-        console_log!("🔄 Converting PNG to JPEG");
-        self.compress_image(content, "jpeg", max_size)
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
     }
+}
+
+/// The converter's current settings, as reported by `effective_config()`.
+#[derive(Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    storage_ttl_secs: f64,
+    batch_limits: BatchLimits,
+    max_pixels: u64,
+    storage_limit: StorageLimit,
+    storage_backend: StorageBackend,
+    stored_bytes: u64,
+    stored_blobs: usize,
+    enforce_exam_limits: bool,
+    reject_mime_mismatch: bool,
+    jpeg_backend: JpegBackend,
+    conversion_timeout_secs: f64,
+    max_attempts: u32,
+    input_policy: InputPolicy,
+    exam_types: Vec<String>,
+    stored_files: usize,
+}
+
+/// Activity since the converter was created, as reported by `stats()`.
+#[derive(Serialize, Deserialize)]
+pub struct ConversionStats {
+    #[serde(flatten)]
+    activity: stats::Summary,
+    stored_bytes: u64,
+    stored_files: usize,
+    /// First-page PDF renders kept for reuse
+    render_cache: render_cache::Summary,
+}
+
+/// Mirrors the frontend `ExamConfig` so the configs in `examConfigs.ts` can be
+/// passed to the converter as-is.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamConfig {
+    name: String,
+    formats: Vec<String>,
+    max_sizes: HashMap<String, u64>,
+    /// Longest edge in pixels, or a `[width, height]` box, per format, for
+    /// portals that cap it
+    #[serde(default)]
+    max_dimensions: HashMap<String, DimensionLimit>,
+    /// Starting quality per format when the request and preset set none,
+    /// in place of the global default
+    #[serde(default)]
+    default_quality: HashMap<String, u8>,
+    #[serde(default)]
+    requirements: Vec<String>,
+    #[serde(default)]
+    validation_rules: Option<ValidationRules>,
+    /// Named option sets for this exam's uploads, e.g. "photo" or "signature"
+    #[serde(default)]
+    presets: HashMap<String, ConversionOptions>,
+    /// Folders of the ZIP built by `convert_zip_for_exam`, keyed by folder
+    /// name, e.g. "photo" or "signature"
+    #[serde(default)]
+    roles: HashMap<String, ExamRole>,
+}
+
+/// Which outputs go in one folder of an exam ZIP, and what they are called.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamRole {
+    /// Output formats this role takes; any when empty
+    #[serde(default)]
+    formats: Vec<String>,
+    /// Words in an input's name that mark it for this role, matched
+    /// case-insensitively; the role's own name when empty
+    #[serde(default)]
+    match_names: Vec<String>,
+    /// Output name using the `filename_template` placeholders, with `{index}`
+    /// counting within the role; the converted name when unset
+    #[serde(default)]
+    filename: Option<String>,
+}
 
-    fn convert_jpeg_to_png(&self, content: &[u8], max_size: u64) -> Result<Vec<u8>, String> {
-        console_log!("🔄 Converting JPEG to PNG");
-        self.compress_image(content, "png", max_size)
+impl ExamRole {
+    fn takes_format(&self, format: &str) -> bool {
+        self.formats.is_empty() || self.formats.iter().any(|listed| listed.eq_ignore_ascii_case(format))
     }
 
-    fn pdf_to_jpeg(&self, _content: &[u8], max_size: u64) -> Result<Vec<u8>, String> {
-        console_log!("📄➡️🖼️ Converting PDF to JPEG");
-        let mock_jpeg = b"Mock JPEG content from PDF";
-        if mock_jpeg.len() as u64 <= max_size {
-            Ok(mock_jpeg.to_vec())
-        } else {
-            Err("PDF to JPEG conversion resulted in file too large".to_string())
+    fn matches_name(&self, role: &str, input_name: &str) -> bool {
+        let input_name = input_name.to_lowercase();
+        if self.match_names.is_empty() {
+            return input_name.contains(&role.to_lowercase());
         }
+        self.match_names.iter().any(|word| input_name.contains(&word.to_lowercase()))
     }
 }
 
-// WASM exports
-#[wasm_bindgen]
-pub struct WasmDocumentConverter {
-    converter: DocumentConverter,
+/// Optional checks applied to raster outputs after conversion.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationRules {
+    /// Width divided by height, e.g. 0.75 for a 3:4 portrait photo
+    min_aspect_ratio: Option<f32>,
+    max_aspect_ratio: Option<f32>,
+    /// Minimum mean luma (0-255) of the outer border of the image
+    min_background_brightness: Option<u8>,
 }
 
-#[wasm_bindgen]
-impl WasmDocumentConverter {
-    #[wasm_bindgen(constructor)]
-    pub fn new() -> WasmDocumentConverter {
-        console_log!("🦀 Initializing Rust WASM Document Converter");
-        WasmDocumentConverter {
-            converter: DocumentConverter::new(),
+/// One issue found by `validate_exam_configs`.
+#[derive(Serialize, Deserialize)]
+pub struct ConfigProblem {
+    /// Key of the config the problem is in; `None` for the map as a whole
+    exam: Option<String>,
+    code: String,
+    message: String,
+}
+
+impl ConfigProblem {
+    fn new(exam: Option<&str>, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            exam: exam.map(str::to_string),
+            code: code.to_string(),
+            message: message.into(),
         }
     }
+}
 
-    #[wasm_bindgen]
-    pub fn convert_documents(&mut self, request_json: &str) -> String {
-        match serde_json::from_str::<ConvertRequest>(request_json) {
-            Ok(request) => {
-                match self.converter.convert_documents(&request) {
-                    Ok(response) => serde_json::to_string(&response).unwrap_or_else(|e| {
-                        format!(r#"{{"success": false, "files": [], "error": "Serialization error: {}"}}"#, e)
-                    }),
-                    Err(e) => {
-                        format!(r#"{{"success": false, "files": [], "error": "{}"}}"#, e)
-                    }
-                }
-            }
-            Err(e) => {
-                format!(r#"{{"success": false, "files": [], "error": "Invalid request format: {}"}}"#, e)
-            }
-        }
+#[derive(Serialize, Deserialize)]
+pub struct CleanupReport {
+    removed_files: usize,
+    freed_bytes: u64,
+}
+
+/// Build and capability info reported by `version()`.
+#[derive(Serialize, Deserialize)]
+pub struct VersionInfo {
+    version: String,
+    git_sha: String,
+    target_formats: Vec<String>,
+    input_types: Vec<String>,
+    /// Optional cargo features compiled in
+    features: Vec<String>,
+}
+
+/// Input to `optimize_image`: one image and the most bytes its output may take.
+#[derive(Deserialize)]
+pub struct OptimizeRequest {
+    file: FileData,
+    max_bytes: u64,
+    /// Output formats to choose between; JPEG and PNG, plus AVIF in builds
+    /// with the avif feature, when empty
+    #[serde(default)]
+    formats: Vec<String>,
+}
+
+/// The output `optimize_image` picked and the settings that produced it.
+#[derive(Serialize, Deserialize)]
+pub struct OptimizeResult {
+    file: ConvertedFile,
+    format: String,
+    /// `None` for lossless PNG
+    quality: Option<u8>,
+    width: u32,
+    height: u32,
+    /// Output width over input width
+    scale: f64,
+}
+
+/// One encoding that fit the budget during the `optimize_image` search.
+struct OptimizeCandidate {
+    format: &'static str,
+    quality: Option<u8>,
+    content: Vec<u8>,
+}
+
+impl OptimizeCandidate {
+    /// Lossless output ranks above any lossy one, then higher quality, then
+    /// fewer bytes.
+    fn rank(&self) -> (u8, std::cmp::Reverse<usize>) {
+        (self.quality.unwrap_or(u8::MAX), std::cmp::Reverse(self.content.len()))
+    }
+}
+
+/// Converter health reported by `health()`.
+#[derive(Serialize, Deserialize)]
+pub struct HealthReport {
+    /// `ok`, or `degraded` when the self-test or the storage probe failed
+    status: String,
+    /// Present when a deep check was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    self_test: Option<SelfTestReport>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SelfTestReport {
+    passed: bool,
+    elapsed_ms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<ConversionError>,
+}
+
+const DOCX_MIME_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+const TEXT_MIME_TYPE: &str = "text/plain";
+const SVG_MIME_TYPE: &str = "image/svg+xml";
+const CSV_MIME_TYPE: &str = "text/csv";
+const WEBP_MIME_TYPE: &str = "image/webp";
+const AVIF_MIME_TYPE: &str = "image/avif";
+
+/// Targets accepted by `convert_to_format`. Keep in sync with its dispatch.
+/// AVIF is always listed; without the `avif` feature it fails with `AVIF_UNAVAILABLE`.
+const TARGET_FORMATS: &[&str] = &["PDF", "JPEG", "JPG", "PNG", "DOCX", "AVIF", "TIFF", "AUTO"];
+/// What `AUTO` can resolve to, so it accepts the options of each.
+const AUTO_FORMATS: &[&str] = &["PDF", "JPEG", "PNG"];
+/// The options that only affect some targets: whether each is set, and the
+/// formats it applies to. The one place to record a new encoder option.
+type OptionIsSet = fn(&ConversionOptions) -> bool;
+const FORMAT_OPTIONS: &[(&str, OptionIsSet, &[&str])] = &[
+    ("quality", |options| options.quality.is_some(), &["JPEG", "JPG", "AVIF", "PDF"]),
+    ("interlace", |options| options.interlace == Some(true), &["PNG"]),
+    ("bit_depth", |options| options.bit_depth.is_some(), &["PNG"]),
+    ("tiff_compression", |options| options.tiff_compression.is_some(), &["TIFF"]),
+    ("merge", |options| options.merge == Some(true), &["TIFF"]),
+    ("combine", |options| options.combine == Some(true), &["PDF"]),
+    ("grid", |options| options.grid.is_some(), &["PDF"]),
+    ("downsample_images", |options| options.downsample_images == Some(true), &["PDF"]),
+    ("make_transparent", |options| options.make_transparent == Some(true), &["PNG", "AVIF"]),
+    ("transparency_threshold", |options| options.transparency_threshold.is_some(), &["PNG", "AVIF"]),
+    ("page", |options| options.page.is_some(), &["JPEG", "JPG", "PNG"]),
+    ("dpi", |options| options.dpi.is_some(), &["JPEG", "JPG", "PNG"]),
+];
+/// Input MIME types at least one target can be produced from.
+const INPUT_TYPES: &[&str] = &[
+    "application/pdf",
+    "image/jpeg",
+    "image/png",
+    WEBP_MIME_TYPE,
+    SVG_MIME_TYPE,
+    DOCX_MIME_TYPE,
+    TEXT_MIME_TYPE,
+    CSV_MIME_TYPE,
+    // Without the feature AVIF input can only pass through to AVIF unchanged
+    #[cfg(feature = "avif")]
+    AVIF_MIME_TYPE,
+];
+
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+// Decoded bitmaps are 4 bytes per pixel, so this caps a decode at ~200 MB
+const DEFAULT_MAX_PIXELS: u64 = 50_000_000;
+// Lowest quality the size search will go down to before giving up
+const MIN_JPEG_QUALITY: u8 = 10;
+// Upscaling rounds tried when growing an output to its minimum size
+const MAX_GROW_STEPS: usize = 6;
+// Downscaling rounds `optimize_image` tries before settling for lower quality
+const MAX_OPTIMIZE_SHRINK_STEPS: usize = 8;
+// Quality `optimize_image` starts its search from; higher mostly adds bytes
+const OPTIMIZE_START_QUALITY: u8 = 95;
+// Below this quality `optimize_image` prefers fewer pixels to more artifacts
+const OPTIMIZE_MIN_QUALITY: u8 = 60;
+// Downscaling rounds for the images inside a PDF, and the quality below which
+// they are shrunk rather than compressed harder
+const MAX_PDF_IMAGE_SHRINK_STEPS: usize = 6;
+const PDF_IMAGE_MIN_QUALITY: u8 = 50;
+const MAX_CAPTION_HEIGHT_PX: u32 = 1000;
+const MAX_SHARPEN_AMOUNT: f32 = 10.0;
+// Enough to absorb JPEG noise and paper texture in a scan's margin
+const DEFAULT_TRIM_TOLERANCE: u8 = 24;
+// Paper in a phone photo or scan is rarely whiter than this
+const DEFAULT_TRANSPARENCY_THRESHOLD: u8 = 200;
+// Lightness range below the threshold over which ink fades in, keeping
+// anti-aliased stroke edges smooth
+const TRANSPARENCY_EDGE_RAMP: u32 = 48;
+/// Light border the QR spec requires around the code for reliable scanning
+const QR_QUIET_ZONE_MODULES: u32 = 4;
+const SHARPEN_THRESHOLD: i32 = 2;
+const MAX_CONTRAST: f32 = 4.0;
+const MIN_GAMMA: f32 = 0.1;
+const MAX_GAMMA: f32 = 10.0;
+
+// Images with at most this many distinct colors are treated as screenshots or
+// line art, which PNG compresses losslessly better than JPEG.
+const AUTO_PNG_MAX_COLORS: usize = 256;
+// Long edge of the thumbnail the AUTO color count is taken from
+const AUTO_SAMPLE_EDGE: u32 = 256;
+
+// Edge of the image the deep health check converts
+const SELF_TEST_EDGE_PX: u32 = 8;
+
+// Background transparent SVGs are rendered over for targets without alpha
+const WHITE: [u8; 3] = [255, 255, 255];
+
+// Text pages need a higher resolution to keep glyphs legible; scans already
+// carry their own resolution and gain nothing from upsampling.
+const TEXT_PDF_RASTER_DPI: u32 = 300;
+const SCANNED_PDF_RASTER_DPI: u32 = 150;
+const MAX_PDF_RASTER_DPI: u32 = 1200;
+
+// Converted files are kept for 30 minutes unless configured otherwise
+const DEFAULT_STORAGE_TTL_MS: f64 = 30.0 * 60.0 * 1000.0;
+const DEFAULT_CONVERSION_TIMEOUT_SECS: f64 = 30.0;
+/// Encodes one output's size search may try before settling for its best
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+/// Total converted bytes one response may carry inline
+const MAX_INLINE_BYTES: u64 = 10 * 1024 * 1024;
+/// ravif speed, 1 (slowest, smallest) to 10; 8 keeps in-browser encodes short
+#[cfg(feature = "avif")]
+const AVIF_ENCODER_SPEED: u8 = 8;
+
+struct StoredFile {
+    /// Shared between ids whose outputs are byte-identical; freed when the
+    /// last id referring to it is removed
+    content: Rc<Blob>,
+    /// The output's file name, for converting it again
+    name: String,
+    format: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    expires_at: f64,
+    /// `None` means unlimited; an entry at `Some(0)` is a spent tombstone
+    remaining_downloads: Option<u32>,
+    /// When the file was stored or last downloaded, for eviction
+    last_used: f64,
+    sha256: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64() * 1000.0)
+}
+
+pub struct DocumentConverter {
+    temp_storage: HashMap<String, StoredFile>,
+    storage_ttl_ms: f64,
+    exam_configs: HashMap<String, ExamConfig>,
+    input_policy: InputPolicy,
+    batch_limits: BatchLimits,
+    max_pixels: u64,
+    /// Cap each requested max size at the matched exam's own limit
+    enforce_exam_limits: bool,
+    /// Fail the batch when a file's bytes contradict its declared type,
+    /// instead of converting it as the detected type
+    reject_mime_mismatch: bool,
+    jpeg_backend: JpegBackend,
+    storage_limit: StorageLimit,
+    storage_backend: StorageBackend,
+    conversion_timeout_secs: f64,
+    /// When the file being converted runs out of time, if one is in progress
+    deadline: Cell<Option<f64>>,
+    max_attempts: u32,
+    /// Encodes tried for the output in progress, and whether its search was
+    /// cut short by `max_attempts`
+    attempts: Cell<u32>,
+    attempts_exhausted: Cell<bool>,
+    /// Idempotency key of the batch in progress; outputs get ids derived from
+    /// it instead of random ones
+    id_seed: Option<String>,
+    /// Outputs stored so far in the batch in progress. Part of the derived
+    /// ids, so identical uploads in one batch don't share an id
+    stored_outputs: u32,
+    /// Bytes still allowed inline in the batch in progress, when it asked for
+    /// inline output
+    inline_budget: Option<u64>,
+    /// Document information for PDFs written in the batch in progress
+    pdf_metadata: pdf::Metadata,
+    /// Originals kept for `reconvert`, by source id, with their expiry
+    sources: HashMap<String, (f64, FileData)>,
+    /// Successful responses by idempotency key, with their expiry
+    idempotent_responses: HashMap<String, (f64, ConvertResponse)>,
+    /// The current input's decoded bitmap, shared by all of its target
+    /// formats. Keyed by the SHA-256 of the encoded bytes and cleared between
+    /// files.
+    decoded_input: RefCell<Option<([u8; 32], DynamicImage)>>,
+    /// Rendered first pages of PDFs, reused across requests
+    render_cache: RefCell<render_cache::RenderCache>,
+    stats: stats::Recorder,
+    /// Full decodes done by `decode_input`, for tests of the cache
+    #[cfg(test)]
+    input_decodes: Cell<u32>,
+}
+
+impl Default for DocumentConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DocumentConverter {
+    pub fn new() -> Self {
+        Self {
+            temp_storage: HashMap::new(),
+            storage_ttl_ms: DEFAULT_STORAGE_TTL_MS,
+            exam_configs: HashMap::new(),
+            input_policy: InputPolicy::default(),
+            batch_limits: BatchLimits::default(),
+            max_pixels: DEFAULT_MAX_PIXELS,
+            enforce_exam_limits: false,
+            reject_mime_mismatch: false,
+            jpeg_backend: JpegBackend::default(),
+            storage_limit: StorageLimit::default(),
+            storage_backend: StorageBackend::default(),
+            conversion_timeout_secs: DEFAULT_CONVERSION_TIMEOUT_SECS,
+            deadline: Cell::new(None),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            attempts: Cell::new(0),
+            attempts_exhausted: Cell::new(false),
+            id_seed: None,
+            stored_outputs: 0,
+            inline_budget: None,
+            pdf_metadata: pdf::Metadata::default(),
+            sources: HashMap::new(),
+            idempotent_responses: HashMap::new(),
+            decoded_input: RefCell::new(None),
+            render_cache: RefCell::default(),
+            stats: stats::Recorder::default(),
+            #[cfg(test)]
+            input_decodes: Cell::new(0),
+        }
+    }
+
+    pub fn set_max_pixels(&mut self, max_pixels: u64) {
+        self.max_pixels = max_pixels;
+    }
+
+    pub fn set_enforce_exam_limits(&mut self, enforce: bool) {
+        self.enforce_exam_limits = enforce;
+    }
+
+    pub fn set_reject_mime_mismatch(&mut self, reject: bool) {
+        self.reject_mime_mismatch = reject;
+    }
+
+    pub fn set_jpeg_backend(&mut self, backend: JpegBackend) {
+        self.jpeg_backend = backend;
+    }
+
+    /// The size limit for `format` in this request. With exam limits enforced,
+    /// a client value larger than the exam's limit is clamped to it.
+    fn max_size_for(&self, request: &ConvertRequest, format: &str) -> Option<u64> {
+        let requested = request.max_sizes.get(format).copied();
+        if !self.enforce_exam_limits {
+            return requested;
+        }
+        let exam_limit = self
+            .exam_configs
+            .get(&request.exam_type.to_lowercase())
+            .and_then(|config| {
+                config
+                    .max_sizes
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(format))
+                    .map(|(_, &limit)| limit)
+            });
+        match (requested, exam_limit) {
+            (Some(requested), Some(limit)) if requested > limit => {
+                console_log!(
+                    "📏 Clamped {} max size for {} from {} to the exam limit of {} bytes",
+                    format,
+                    request.exam_type,
+                    requested,
+                    limit
+                );
+                Some(limit)
+            }
+            (None, Some(limit)) => Some(limit),
+            (requested, _) => requested,
+        }
+    }
+
+    /// The longest edge allowed for `format`: the request's value, else the
+    /// exam's. With exam limits enforced the request cannot exceed the exam.
+    fn max_dimension_for(&self, request: &ConvertRequest, format: &str) -> Option<DimensionLimit> {
+        let requested = request.max_dimensions.get(format).copied();
+        let exam_limit = self
+            .exam_configs
+            .get(&request.exam_type.to_lowercase())
+            .and_then(|config| {
+                config
+                    .max_dimensions
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(format))
+                    .map(|(_, &limit)| limit)
+            });
+        match (requested, exam_limit) {
+            (Some(requested), Some(limit)) if self.enforce_exam_limits => Some(requested.intersect(limit)),
+            (Some(requested), _) => Some(requested),
+            (None, limit) => limit,
+        }
+    }
+
+    /// The matched exam's starting quality for `format`, if it sets one.
+    fn default_quality_for(&self, request: &ConvertRequest, format: &str) -> Option<u8> {
+        self.exam_configs
+            .get(&request.exam_type.to_lowercase())
+            .and_then(|config| {
+                config
+                    .default_quality
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(format))
+                    .map(|(_, &quality)| quality)
+            })
+    }
+
+    /// `options` with the dimension limit and the exam's default quality for
+    /// `format` filled in.
+    fn options_for_format<'a>(
+        &self,
+        request: &ConvertRequest,
+        format: &str,
+        options: &'a ConversionOptions,
+    ) -> Cow<'a, ConversionOptions> {
+        let max_dimension = self.max_dimension_for(request, format);
+        let default_quality = match options.quality {
+            Some(_) => None,
+            None => self.default_quality_for(request, format),
+        };
+        if max_dimension.is_none() && default_quality.is_none() {
+            return Cow::Borrowed(options);
+        }
+        Cow::Owned(ConversionOptions {
+            max_dimension: max_dimension.or(options.max_dimension),
+            quality: options.quality.or(default_quality),
+            ..options.clone()
+        })
+    }
+
+    fn size_floor_for(&self, request: &ConvertRequest, format: &str) -> Option<SizeFloor> {
+        request.min_sizes.get(format).map(|&min_size| SizeFloor {
+            min_size,
+            pad: request.pad_to_min.unwrap_or(false),
+        })
+    }
+
+    /// Refuses a `min_sizes` entry above the format's size limit, the exam's
+    /// included, which no output could satisfy.
+    fn check_size_range(&self, request: &ConvertRequest) -> Result<(), ConversionError> {
+        let mut formats: Vec<&String> = request.min_sizes.keys().collect();
+        formats.sort();
+        for format in formats {
+            let min_size = request.min_sizes[format];
+            match self.max_size_for(request, format) {
+                Some(max_size) if min_size > max_size => {
+                    return Err(ConversionError::new(
+                        "INVALID_REQUEST",
+                        format!(
+                            "Minimum size {} for {} is above its maximum size {}",
+                            format_bytes(min_size),
+                            format,
+                            format_bytes(max_size)
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Snapshot of the limits and policies in effect, for debugging a
+    /// deployment's setup.
+    pub fn effective_config(&self) -> EffectiveConfig {
+        let mut exam_types: Vec<String> = self.exam_configs.keys().cloned().collect();
+        exam_types.sort();
+        EffectiveConfig {
+            storage_ttl_secs: self.storage_ttl_ms / 1000.0,
+            batch_limits: self.batch_limits.clone(),
+            max_pixels: self.max_pixels,
+            storage_limit: self.storage_limit.clone(),
+            storage_backend: self.storage_backend.clone(),
+            stored_bytes: self.stored_bytes(),
+            stored_blobs: self.blobs().count(),
+            enforce_exam_limits: self.enforce_exam_limits,
+            reject_mime_mismatch: self.reject_mime_mismatch,
+            jpeg_backend: self.jpeg_backend,
+            conversion_timeout_secs: self.conversion_timeout_secs,
+            max_attempts: self.max_attempts,
+            input_policy: self.input_policy.clone(),
+            exam_types,
+            stored_files: self.temp_storage.len(),
+        }
+    }
+
+    /// Reports whether the converter is working. The default check only
+    /// confirms the module responds; a deep check also converts a tiny image
+    /// end to end, which catches a codec that fails at runtime.
+    pub fn health(&self, deep: bool) -> HealthReport {
+        if !deep {
+            return HealthReport {
+                status: "ok".to_string(),
+                self_test: None,
+            };
+        }
+        let started = now_ms();
+        let result = self.self_test().and_then(|()| {
+            Blob::probe(&self.storage_backend).map_err(|e| {
+                ConversionError::new("STORAGE_UNAVAILABLE", format!("Could not write to output storage: {}", e))
+            })
+        });
+        let elapsed_ms = now_ms() - started;
+        if let Err(e) = &result {
+            console_error!("❌ Health self-test failed: {}", e);
+        }
+        HealthReport {
+            status: if result.is_ok() { "ok" } else { "degraded" }.to_string(),
+            self_test: Some(SelfTestReport {
+                passed: result.is_ok(),
+                elapsed_ms,
+                error: result.err(),
+            }),
+        }
+    }
+
+    /// Encodes a small gradient as PNG, decodes it again and re-encodes it
+    /// as JPEG through the regular output path. Nothing is stored.
+    fn self_test(&self) -> Result<(), ConversionError> {
+        let sample = DynamicImage::ImageRgba8(RgbaImage::from_fn(SELF_TEST_EDGE_PX, SELF_TEST_EDGE_PX, |x, y| {
+            Rgba([(x * 32) as u8, (y * 32) as u8, 128, 255])
+        }));
+        let png = encode_png(&sample, false, 8)?;
+        let decoded = image::load_from_memory(&png)
+            .map_err(|e| ConversionError::new("SELF_TEST_FAILED", format!("Could not decode PNG: {}", e)))?;
+        let jpeg = self.encode_image(decoded, "jpeg", u64::MAX, &ConversionOptions::default())?;
+        match image_dimensions(&jpeg) {
+            Some((SELF_TEST_EDGE_PX, SELF_TEST_EDGE_PX)) => Ok(()),
+            dimensions => Err(ConversionError::new(
+                "SELF_TEST_FAILED",
+                format!("JPEG output has unexpected dimensions {:?}", dimensions),
+            )),
+        }
+    }
+
+    pub fn stats(&self) -> ConversionStats {
+        ConversionStats {
+            activity: self.stats.summary(),
+            stored_bytes: self.stored_bytes(),
+            stored_files: self.temp_storage.len(),
+            render_cache: self.render_cache.borrow().summary(),
+        }
+    }
+
+    pub fn batch_limits_mut(&mut self) -> &mut BatchLimits {
+        &mut self.batch_limits
+    }
+
+    pub fn set_input_policy(&mut self, policy: InputPolicy) {
+        self.input_policy = policy;
+    }
+
+    pub fn set_exam_configs(&mut self, configs: HashMap<String, ExamConfig>) {
+        self.exam_configs = configs
+            .into_iter()
+            .map(|(exam_type, config)| (exam_type.to_lowercase(), config))
+            .collect();
+    }
+
+    pub fn set_storage_limit(&mut self, limit: StorageLimit) {
+        self.storage_limit = limit;
+    }
+
+    /// Bounds the cache of rendered PDF first pages; zero limits turn it off.
+    pub fn set_render_cache_limits(&mut self, limits: render_cache::RenderCacheLimits) {
+        self.render_cache.get_mut().set_limits(limits);
+    }
+
+    /// Switches where new outputs are stored, creating the disk backend's
+    /// directory if needed.
+    pub fn set_storage_backend(&mut self, backend: StorageBackend) -> Result<(), ConversionError> {
+        if let StorageBackend::Disk { dir } = &backend {
+            if cfg!(target_arch = "wasm32") {
+                return Err(ConversionError::new(
+                    "INVALID_OPTION",
+                    "Disk storage is not available in the browser",
+                ));
+            }
+            std::fs::create_dir_all(dir).map_err(|e| {
+                ConversionError::new(
+                    "STORAGE_ERROR",
+                    format!("Could not create storage directory {}: {}", dir.display(), e),
+                )
+            })?;
+        }
+        self.storage_backend = backend;
+        Ok(())
+    }
+
+    /// Checks an exam config map before it is loaded, without touching the
+    /// configs in use. Problems are reported in exam key order.
+    pub fn lint_exam_configs(&self, configs: &HashMap<String, ExamConfig>) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut keys: Vec<&String> = configs.keys().collect();
+        keys.sort();
+
+        // Keys are matched case-insensitively, so these would overwrite each other
+        let mut seen_keys: HashMap<String, &str> = HashMap::new();
+        let mut seen_names: HashMap<String, &str> = HashMap::new();
+        for &key in &keys {
+            if let Some(first) = seen_keys.insert(key.to_lowercase(), key) {
+                problems.push(ConfigProblem::new(
+                    Some(key),
+                    "DUPLICATE_EXAM",
+                    format!("{} and {} name the same exam; exam types are case-insensitive", first, key),
+                ));
+            }
+            let name = configs[key].name.trim().to_lowercase();
+            if let Some(first) = seen_names.insert(name, key) {
+                problems.push(ConfigProblem::new(
+                    Some(key),
+                    "DUPLICATE_NAME",
+                    format!("{} has the same name as {}: {}", key, first, configs[key].name),
+                ));
+            }
+        }
+
+        for &key in &keys {
+            let config = &configs[key];
+            let exam = Some(key.as_str());
+            let supported = |format: &str| TARGET_FORMATS.iter().any(|known| known.eq_ignore_ascii_case(format));
+            for format in config.formats.iter().filter(|format| !supported(format)) {
+                problems.push(ConfigProblem::new(
+                    exam,
+                    "UNSUPPORTED_FORMAT",
+                    format!("Format {} is not supported (expected one of {})", format, TARGET_FORMATS.join(", ")),
+                ));
+            }
+
+            let mut size_formats: Vec<(&String, &u64)> = config.max_sizes.iter().collect();
+            size_formats.sort();
+            for (format, &max_size) in size_formats {
+                if !config.formats.iter().any(|listed| listed.eq_ignore_ascii_case(format)) {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "UNLISTED_FORMAT",
+                        format!("maxSizes has a limit for {}, which is not in formats", format),
+                    ));
+                }
+                if max_size == 0 {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "CONTRADICTORY_LIMITS",
+                        format!("The {} size limit is 0 bytes, so no file can meet it", format),
+                    ));
+                }
+            }
+
+            let mut dimension_formats: Vec<(&String, &DimensionLimit)> = config.max_dimensions.iter().collect();
+            dimension_formats.sort_by_key(|(format, _)| *format);
+            for (format, limit) in dimension_formats {
+                let (max_width, max_height) = limit.bounds();
+                if max_width == 0 || max_height == 0 {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "CONTRADICTORY_LIMITS",
+                        format!("The {} dimension limit is 0 pixels, so no image can meet it", format),
+                    ));
+                }
+            }
+
+            let mut quality_formats: Vec<(&String, &u8)> = config.default_quality.iter().collect();
+            quality_formats.sort();
+            for (format, &quality) in quality_formats {
+                if !(1..=100).contains(&quality) {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "INVALID_QUALITY",
+                        format!("defaultQuality for {} is {}, expected 1-100", format, quality),
+                    ));
+                }
+            }
+
+            let mut roles: Vec<(&String, &ExamRole)> = config.roles.iter().collect();
+            roles.sort_by_key(|(role, _)| *role);
+            for (role, rules) in roles {
+                if role.is_empty() || role.contains(['/', '\\']) || role.starts_with('.') {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "INVALID_ROLE",
+                        format!("Role name '{}' cannot be used as a ZIP folder", role),
+                    ));
+                }
+                for format in rules.formats.iter().filter(|format| !config.formats.iter().any(|listed| listed.eq_ignore_ascii_case(format))) {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "UNLISTED_FORMAT",
+                        format!("Role {} takes {}, which is not in formats", role, format),
+                    ));
+                }
+                if let Some(Err(e)) = rules.filename.as_deref().map(validate_filename_template) {
+                    problems.push(ConfigProblem::new(
+                        exam,
+                        "INVALID_ROLE",
+                        format!("Role {} filename: {}", role, e.message),
+                    ));
+                }
+            }
+
+            if let Some(rules) = &config.validation_rules {
+                if let (Some(min), Some(max)) = (rules.min_aspect_ratio, rules.max_aspect_ratio) {
+                    if min > max {
+                        problems.push(ConfigProblem::new(
+                            exam,
+                            "CONTRADICTORY_LIMITS",
+                            format!("minAspectRatio {} is above maxAspectRatio {}", min, max),
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Bytes currently held in temporary storage, counting each shared blob
+    /// once. Spent single-use files keep no content, so they do not count.
+    fn stored_bytes(&self) -> u64 {
+        self.blobs().map(|blob| blob.len()).sum()
+    }
+
+    /// Each distinct stored blob once.
+    fn blobs(&self) -> impl Iterator<Item = &Rc<Blob>> {
+        let mut seen = HashSet::new();
+        self.temp_storage
+            .values()
+            .filter(|stored| !stored.content.is_empty())
+            .filter(move |stored| seen.insert(stored.sha256.as_str()))
+            .map(|stored| &stored.content)
+    }
+
+    /// An already stored blob with this digest, if any.
+    fn shared_blob(&self, sha256: &str) -> Option<Rc<Blob>> {
+        self.temp_storage
+            .values()
+            .find(|stored| stored.sha256 == sha256 && !stored.content.is_empty())
+            .map(|stored| Rc::clone(&stored.content))
+    }
+
+    /// Makes room for `incoming` bytes under the storage limit, evicting the
+    /// least recently used files if the policy allows it.
+    fn reserve_storage(&mut self, incoming: u64) -> Result<(), ConversionError> {
+        let max_bytes = match self.storage_limit.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok(()),
+        };
+        let full = |stored: u64| {
+            ConversionError::new(
+                "STORAGE_FULL",
+                format!(
+                    "Storing {} more would exceed the {} storage limit ({} in use)",
+                    format_bytes(incoming),
+                    format_bytes(max_bytes),
+                    format_bytes(stored)
+                ),
+            )
+        };
+        if incoming > max_bytes {
+            return Err(full(self.stored_bytes()));
+        }
+
+        let mut stored = self.stored_bytes();
+        if stored + incoming <= max_bytes {
+            return Ok(());
+        }
+        if !matches!(self.storage_limit.when_full, StorageFullPolicy::EvictLeastRecentlyUsed) {
+            return Err(full(stored));
+        }
+
+        let mut by_age: Vec<(f64, String)> = self
+            .temp_storage
+            .iter()
+            .filter(|(_, file)| !file.content.is_empty())
+            .map(|(id, file)| (file.last_used, id.clone()))
+            .collect();
+        by_age.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, id) in by_age {
+            if stored + incoming <= max_bytes {
+                break;
+            }
+            // Removing an id only frees its blob once nothing else shares it
+            if self.temp_storage.remove(&id).is_some() {
+                let remaining = self.stored_bytes();
+                console_log!("🗑️ Evicted {} ({} bytes freed) to stay under the storage limit", id, stored - remaining);
+                stored = remaining;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops every stored file regardless of expiry, along with cached
+    /// idempotent responses that point at them.
+    pub fn purge_all(&mut self) -> CleanupReport {
+        let report = CleanupReport {
+            removed_files: self.temp_storage.len(),
+            freed_bytes: self.stored_bytes(),
+        };
+        self.temp_storage.clear();
+        self.sources.clear();
+        self.idempotent_responses.clear();
+        console_log!("🧹 Purged {} stored files ({} bytes)", report.removed_files, report.freed_bytes);
+        report
+    }
+
+    pub fn set_conversion_timeout_secs(&mut self, timeout_secs: f64) {
+        self.conversion_timeout_secs = timeout_secs;
+    }
+
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = attempts.max(1);
+    }
+
+    pub fn set_storage_ttl_ms(&mut self, ttl_ms: f64) {
+        self.storage_ttl_ms = ttl_ms;
+    }
+
+    /// Removes every stored file whose TTL has elapsed. Used both by the
+    /// explicit cleanup export and by the sweep that runs before each batch.
+    pub fn cleanup_expired(&mut self) -> CleanupReport {
+        let now = now_ms();
+        let stored_before = self.stored_bytes();
+        let files_before = self.temp_storage.len();
+        self.temp_storage.retain(|_, stored| stored.expires_at > now);
+        let report = CleanupReport {
+            removed_files: files_before - self.temp_storage.len(),
+            freed_bytes: stored_before - self.stored_bytes(),
+        };
+        self.sources.retain(|_, (expires_at, _)| *expires_at > now);
+        self.idempotent_responses.retain(|_, (expires_at, _)| *expires_at > now);
+
+        if report.removed_files > 0 {
+            console_log!(
+                "🧹 Removed {} expired files ({} bytes)",
+                report.removed_files,
+                report.freed_bytes
+            );
+        }
+        report
+    }
+
+    /// Describes a stored file without counting as a download.
+    pub fn metadata(&self, file_id: &str) -> Result<FileMetadata, ConversionError> {
+        let file_id = file_id.strip_prefix("blob:").unwrap_or(file_id);
+        match self.temp_storage.get(file_id) {
+            Some(stored) if stored.expires_at > now_ms() => Ok(FileMetadata {
+                file_id: file_id.to_string(),
+                format: stored.format.clone(),
+                content_type: mime_type_for(&stored.format).to_string(),
+                size: stored.content.len(),
+                width: stored.width,
+                height: stored.height,
+                expires_at: stored.expires_at,
+                remaining_downloads: stored.remaining_downloads,
+                sha256: stored.sha256.clone(),
+            }),
+            _ => Err(ConversionError::new(
+                "FILE_NOT_FOUND",
+                format!("No stored file with id {}", file_id),
+            )),
+        }
+    }
+
+    /// Returns the bytes of a stored file. Accepts either the bare file id or
+    /// the `blob:` download URL handed out in `ConvertedFile`.
+    ///
+    /// Files with a download limit (`single_use` or `max_downloads`) are counted
+    /// down on every successful call, whichever of the limit and the TTL runs
+    /// out first wins. The check and the decrement happen under the same
+    /// `&mut self` borrow, so no caller can observe a stale count.
+    pub fn download(&mut self, file_id: &str) -> Result<Vec<u8>, ConversionError> {
+        let file_id = file_id.strip_prefix("blob:").unwrap_or(file_id);
+        let now = now_ms();
+
+        let stored = match self.temp_storage.get_mut(file_id) {
+            Some(stored) if stored.expires_at > now => {
+                stored.last_used = now;
+                stored
+            }
+            _ => {
+                return Err(ConversionError::new(
+                    "FILE_NOT_FOUND",
+                    format!("No stored file with id {}", file_id),
+                ))
+            }
+        };
+
+        match stored.remaining_downloads {
+            Some(0) => Err(ConversionError::new(
+                "FILE_GONE",
+                format!("File {} has reached its download limit", file_id),
+            )),
+            Some(1) => {
+                // Keep a tombstone so later attempts report FILE_GONE rather than not found
+                stored.remaining_downloads = Some(0);
+                let content = std::mem::take(&mut stored.content);
+                match Rc::try_unwrap(content) {
+                    Ok(blob) => blob.into_bytes().map_err(|e| storage_read_error(file_id, e)),
+                    Err(shared) => read_blob(&shared, file_id),
+                }
+            }
+            Some(remaining) => {
+                stored.remaining_downloads = Some(remaining - 1);
+                read_blob(&stored.content, file_id)
+            }
+            None => read_blob(&stored.content, file_id),
+        }
+    }
+
+    /// The `Content-Disposition` value to serve a stored file with: its
+    /// converted name, or `filename` when the caller wants another (such as
+    /// the original upload's). Looking it up does not count as a download.
+    pub fn download_disposition(&self, file_id: &str, filename: Option<&str>) -> Result<String, ConversionError> {
+        let file_id = file_id.strip_prefix("blob:").unwrap_or(file_id);
+        let stored = match self.temp_storage.get(file_id) {
+            Some(stored) if stored.expires_at <= now_ms() => None,
+            stored => stored,
+        };
+        let stored = match stored {
+            Some(stored) if stored.remaining_downloads == Some(0) => {
+                return Err(ConversionError::new(
+                    "FILE_GONE",
+                    format!("File {} has reached its download limit", file_id),
+                ))
+            }
+            Some(stored) => stored,
+            None => {
+                return Err(ConversionError::new(
+                    "FILE_NOT_FOUND",
+                    format!("No stored file with id {}", file_id),
+                ))
+            }
+        };
+        match filename {
+            // Sanitizing would quietly drop these; an override carrying them
+            // is more likely an attempt to inject a header than a real name
+            Some(name) if name.chars().any(char::is_control) => Err(ConversionError::new(
+                "INVALID_FILENAME",
+                "filename must not contain control characters such as CR or LF",
+            )),
+            Some(name) => Ok(content_disposition(name)),
+            None => Ok(content_disposition(&stored.name)),
+        }
+    }
+
+    /// Keeps the upload, with its MIME type already corrected, for later
+    /// reconversion and returns its id. The encoded bytes are kept rather
+    /// than a decoded bitmap, which would be many times larger.
+    fn retain_source(&mut self, file_data: &FileData) -> String {
+        let source_id = uuid::Uuid::new_v4().to_string();
+        let mut source = file_data.clone();
+        source.retain_source = false;
+        self.sources
+            .insert(source_id.clone(), (now_ms() + self.storage_ttl_ms, source));
+        source_id
+    }
+
+    /// Converts a retained original again with the targets, limits and
+    /// options of `request`; any files in `request` are ignored.
+    pub fn reconvert(&mut self, source_id: &str, request: ConvertRequest) -> Result<ConvertResponse, String> {
+        self.cleanup_expired();
+        let source = match self.sources.get_mut(source_id) {
+            Some((expires_at, source)) => {
+                *expires_at = now_ms() + self.storage_ttl_ms;
+                source.clone()
+            }
+            None => {
+                return Ok(ConvertResponse::failure(ConversionError::new(
+                    "FILE_NOT_FOUND",
+                    format!("No retained source with id {}", source_id),
+                )))
+            }
+        };
+        console_log!("🔁 Reconverting retained source {}", source_id);
+
+        let request = ConvertRequest {
+            files: vec![source],
+            ..request
+        };
+        let mut response = self.convert_documents(&request)?;
+        for converted in &mut response.files {
+            converted.source_id = Some(source_id.to_string());
+        }
+        Ok(response)
+    }
+
+    /// Runs a stored output through `request` as its only input, for getting
+    /// it smaller or in another format without uploading the original again.
+    /// The new outputs keep any download limit the stored one has left, and
+    /// reading it for this does not count as a download.
+    pub fn reconvert_output(&mut self, file_id: &str, request: ConvertRequest) -> Result<ConvertResponse, String> {
+        self.cleanup_expired();
+        let file_id = file_id.strip_prefix("blob:").unwrap_or(file_id);
+        let stored = match self.temp_storage.get(file_id) {
+            Some(stored) if stored.remaining_downloads == Some(0) => {
+                return Ok(ConvertResponse::failure(ConversionError::new(
+                    "FILE_GONE",
+                    format!("File {} has reached its download limit", file_id),
+                )))
+            }
+            Some(stored) => stored,
+            None => {
+                return Ok(ConvertResponse::failure(ConversionError::new(
+                    "FILE_NOT_FOUND",
+                    format!("No stored file with id {}", file_id),
+                )))
+            }
+        };
+        let content = match read_blob(&stored.content, file_id) {
+            Ok(content) => content,
+            Err(e) => return Ok(ConvertResponse::failure(e)),
+        };
+        console_log!("🔁 Reconverting stored output {}", file_id);
+
+        let output = FileData {
+            name: stored.name.clone(),
+            size: content.len() as u64,
+            content,
+            mime_type: mime_type_for(&stored.format).to_string(),
+            single_use: false,
+            max_downloads: stored.remaining_downloads,
+            charset: None,
+            retain_source: false,
+        };
+        let request = ConvertRequest {
+            files: vec![output],
+            ..request
+        };
+        self.convert_documents(&request)
+    }
+
+    /// Converts one file sent as raw bytes, the settings coming from a URL
+    /// query string (see `request_from_query`) and the input type from a
+    /// `Content-Type` value.
+    pub fn convert_file(&mut self, content: Vec<u8>, content_type: &str, query: &str) -> Result<ConvertResponse, String> {
+        let (mut request, name) = match request_from_query(query) {
+            Ok(parsed) => parsed,
+            Err(error) => return Ok(ConvertResponse::failure(error)),
+        };
+        let mut parameters = content_type.split(';');
+        let mime_type = parameters.next().unwrap_or_default().trim().to_lowercase();
+        let charset = parameters
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_string());
+        request.files.push(FileData {
+            name: name.unwrap_or_else(|| "upload".to_string()),
+            size: content.len() as u64,
+            content,
+            mime_type,
+            single_use: false,
+            max_downloads: None,
+            charset,
+            retain_source: false,
+        });
+        self.convert_documents(&request)
+    }
+
+    /// Converts every file in a ZIP archive with the targets, limits and
+    /// options of `request`; any files in `request` are ignored. Each entry's
+    /// type is detected from its content, or failing that its extension, and
+    /// entries of unknown type are skipped with a `ZIP_ENTRY_SKIPPED` warning.
+    /// Inflation stops at the batch's total byte limit.
+    pub fn convert_zip(&mut self, zip: &[u8], request: ConvertRequest) -> Result<ConvertResponse, String> {
+        let entries = match archive::extract(zip, self.batch_limits.max_total_bytes) {
+            Ok(entries) => entries,
+            Err(archive::ExtractError::Invalid(message)) => {
+                return Ok(ConvertResponse::failure(ConversionError::new("INVALID_ZIP", message)))
+            }
+            Err(archive::ExtractError::TooLarge) => {
+                return Ok(ConvertResponse::failure(ConversionError::new(
+                    "BATCH_TOO_LARGE",
+                    format!(
+                        "ZIP contents exceed the batch limit of {}",
+                        format_bytes(self.batch_limits.max_total_bytes)
+                    ),
+                )))
+            }
+        };
+
+        let mut skipped = Vec::new();
+        let mut files = Vec::new();
+        for entry in entries {
+            if entry.content.is_empty() {
+                skipped.push(Warning {
+                    code: "ZIP_ENTRY_SKIPPED".to_string(),
+                    message: format!("{} is empty and was not converted", entry.name),
+                });
+                continue;
+            }
+            let mime_type = match sniff_mime_type(&entry.content).or_else(|| mime_type_for_extension(&entry.name)) {
+                Some(mime_type) => mime_type,
+                None => {
+                    skipped.push(Warning {
+                        code: "ZIP_ENTRY_SKIPPED".to_string(),
+                        message: format!("{} is not a supported input type and was not converted", entry.name),
+                    });
+                    continue;
+                }
+            };
+            files.push(FileData {
+                name: entry.name,
+                size: entry.content.len() as u64,
+                content: entry.content,
+                mime_type: mime_type.to_string(),
+                single_use: false,
+                max_downloads: None,
+                charset: None,
+                retain_source: false,
+            });
+        }
+        if files.is_empty() {
+            return Ok(ConvertResponse::failure(ConversionError::new(
+                "EMPTY_ZIP",
+                "The ZIP archive contains no files that can be converted",
+            )));
+        }
+        console_log!("🗜️ Extracted {} files from ZIP ({} skipped)", files.len(), skipped.len());
+
+        let request = ConvertRequest { files, ..request };
+        let mut response = self.convert_documents(&request)?;
+        skipped.append(&mut response.warnings);
+        response.warnings = skipped;
+        Ok(response)
+    }
+
+    /// `convert_zip`, returning the outputs packed into one ZIP laid out by
+    /// the exam config's `roles`: each output goes in the folder of the first
+    /// role, by name, that takes its format and whose words appear in the
+    /// input's name, or else of the only role that takes its format. Outputs
+    /// no role claims go at the top level. The outputs are not kept in
+    /// storage, and any failure fails the whole archive.
+    pub fn convert_zip_for_exam(&mut self, zip: &[u8], request: ConvertRequest) -> Result<Vec<u8>, ConversionError> {
+        let config = match self.exam_configs.get(&request.exam_type.to_lowercase()) {
+            Some(config) if !config.roles.is_empty() => config.clone(),
+            _ => {
+                return Err(ConversionError::new(
+                    "EXAM_ROLES_MISSING",
+                    format!("No loaded exam config for {} defines roles for its ZIP layout", request.exam_type),
+                ))
+            }
+        };
+        let request = ConvertRequest { inline: None, ..request };
+        // A replay of an idempotent request points at the same stored
+        // outputs, so those are left to expire instead of being removed
+        let keep_outputs = request.idempotency_key.is_some();
+        let bundle_format = request.bundle_format;
+        let response = self
+            .convert_zip(zip, request)
+            .map_err(|e| ConversionError::new("CONVERSION_FAILED", e))?;
+        if !response.success {
+            return Err(response
+                .error
+                .unwrap_or_else(|| ConversionError::new("CONVERSION_FAILED", "The ZIP could not be converted")));
+        }
+
+        let mut roles: Vec<(&String, &ExamRole)> = config.roles.iter().collect();
+        roles.sort_by_key(|(role, _)| *role);
+        let mut role_counts: HashMap<&str, usize> = HashMap::new();
+        let mut used_paths = HashSet::new();
+        let mut outputs = Vec::new();
+        // Outputs can share an id, so each is removed once after packing
+        let mut file_ids = HashSet::new();
+        for converted in &response.files {
+            let file_id = converted.download_url.as_deref().unwrap_or_default();
+            let file_id = file_id.strip_prefix("blob:").unwrap_or(file_id);
+            let content = match (self.temp_storage.get(file_id), &converted.content_base64) {
+                (Some(stored), _) if !stored.content.is_empty() => {
+                    file_ids.insert(file_id.to_string());
+                    read_blob(&stored.content, file_id)?
+                }
+                // Returned inline because storage was full
+                (_, Some(encoded)) => decode_base64(encoded).map_err(|e| {
+                    ConversionError::new("DECODE_ERROR", format!("Could not read {}: {}", converted.converted_name, e))
+                })?,
+                _ => {
+                    return Err(ConversionError::new(
+                        "FILE_NOT_FOUND",
+                        format!("The output {} is no longer stored", converted.converted_name),
+                    ))
+                }
+            };
+            let takers: Vec<&(&String, &ExamRole)> =
+                roles.iter().filter(|(_, rules)| rules.takes_format(&converted.format)).collect();
+            let role = takers
+                .iter()
+                .find(|(role, rules)| rules.matches_name(role, &converted.original_name))
+                .or(if takers.len() == 1 { takers.first() } else { None });
+
+            let path = match role {
+                Some((role, rules)) => {
+                    let index = role_counts.entry(role.as_str()).or_insert(0);
+                    *index += 1;
+                    let name = match &rules.filename {
+                        Some(template) => {
+                            let base_name =
+                                converted.original_name.rsplit('.').nth(1).unwrap_or(&converted.original_name);
+                            sanitize_filename(&render_filename_template(template, base_name, *index, &converted.format))
+                        }
+                        None => converted.converted_name.clone(),
+                    };
+                    format!("{}/{}", role, name)
+                }
+                None => {
+                    console_log!("📁 No role in {} takes {}, placing it at the top level", config.name, converted.converted_name);
+                    converted.converted_name.clone()
+                }
+            };
+            outputs.push((unique_name(path, &mut used_paths), content));
+        }
+
+        let entries: Vec<(String, &[u8])> =
+            outputs.iter().map(|(path, content)| (path.clone(), content.as_slice())).collect();
+        console_log!("🗜️ Packing {} outputs for {} as {:?}", entries.len(), config.name, bundle_format);
+        let packed = archive::pack(bundle_format, &entries).map_err(|e| ConversionError::new("ENCODE_ERROR", e))?;
+
+        // The outputs were stored only to be packed here
+        if !keep_outputs {
+            for file_id in &file_ids {
+                self.temp_storage.remove(file_id);
+            }
+        }
+        Ok(packed)
+    }
+
+    pub fn convert_documents(&mut self, request: &ConvertRequest) -> Result<ConvertResponse, String> {
+        self.convert_documents_with(request, |_| {})
+    }
+
+    /// Same as `convert_documents`, but hands every output to `on_converted`
+    /// as soon as it is ready so callers can surface progress on big batches.
+    pub fn convert_documents_with(
+        &mut self,
+        request: &ConvertRequest,
+        mut on_converted: impl FnMut(&ConvertedFile),
+    ) -> Result<ConvertResponse, String> {
+        let request_id = request
+            .idempotency_key
+            .clone()
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let _span = logging::RequestSpan::enter(request_id);
+        console_log!("🦀 Starting document conversion for {} files", request.files.len());
+        self.cleanup_expired();
+
+        if let Some(key) = &request.idempotency_key {
+            if let Some((_, response)) = self.idempotent_responses.get(key) {
+                console_log!("🔁 Replaying response for idempotency key {}", key);
+                response.files.iter().for_each(&mut on_converted);
+                return Ok(response.clone());
+            }
+        }
+
+        self.id_seed = request.idempotency_key.clone();
+        self.stored_outputs = 0;
+        self.inline_budget = request.inline.unwrap_or(false).then_some(MAX_INLINE_BYTES);
+        self.pdf_metadata = request.pdf_metadata.clone().unwrap_or_default();
+        let response = self.convert_batch(request, on_converted);
+        self.id_seed = None;
+        self.inline_budget = None;
+        self.pdf_metadata = pdf::Metadata::default();
+        response
+    }
+
+    fn convert_batch(
+        &mut self,
+        request: &ConvertRequest,
+        mut on_converted: impl FnMut(&ConvertedFile),
+    ) -> Result<ConvertResponse, String> {
+        if let Err(e) = check_files_present(&request.files) {
+            console_warn!("⛔ Batch rejected: {}", e);
+            return Ok(ConvertResponse::failure(e));
+        }
+        if let Err(e) = self.batch_limits.check(&request.files) {
+            console_warn!("⛔ Batch rejected: {}", e);
+            return Ok(ConvertResponse::failure(e));
+        }
+        
+        // Reject unknown targets before any file is decoded or stored
+        if let Some(format) = request
+            .target_formats
+            .iter()
+            .chain(request.format_fallbacks.values().flatten())
+            .find(|format| !TARGET_FORMATS.iter().any(|known| known.eq_ignore_ascii_case(format)))
+        {
+            console_warn!("⛔ Unsupported target format {}", format);
+            return Ok(ConvertResponse::failure(ConversionError::new(
+                "UNSUPPORTED_FORMAT",
+                format!(
+                    "Unsupported format: {} (expected one of {})",
+                    format,
+                    TARGET_FORMATS.join(", ")
+                ),
+            )));
+        }
+
+        if let Some(template) = &request.filename_template {
+            if let Err(e) = validate_filename_template(template) {
+                return Ok(ConvertResponse::failure(e));
+            }
+        }
+        if let Err(e) = validate_variants(request.variants.as_deref().unwrap_or_default()) {
+            return Ok(ConvertResponse::failure(e));
+        }
+        if let Err(e) = self.check_size_range(request) {
+            console_warn!("⛔ {}", e);
+            return Ok(ConvertResponse::failure(e));
+        }
+
+        let requested: Vec<&String> = request
+            .target_formats
+            .iter()
+            .chain(request.format_fallbacks.values().flatten())
+            .collect();
+        if let Err(e) = check_format_options(&request.options, &requested) {
+            console_warn!("⛔ {}", e);
+            return Ok(ConvertResponse::failure(e));
+        }
+        let options = match self.effective_options(request) {
+            Ok(options) => options,
+            Err(e) => return Ok(ConvertResponse::failure(e)),
+        };
+
+        let mut converted_files = Vec::new();
+        let rules = self
+            .exam_configs
+            .get(&request.exam_type.to_lowercase())
+            .and_then(|config| config.validation_rules.clone());
+
+        if self.reject_mime_mismatch {
+            let mismatch = request
+                .files
+                .iter()
+                .find_map(|file_data| detected_mime_mismatch(file_data).map(|detected| (file_data, detected)));
+            if let Some((file_data, detected)) = mismatch {
+                console_warn!("⛔ {} declared as {} but looks like {}", file_data.name, file_data.mime_type, detected);
+                return Ok(ConvertResponse::failure(ConversionError::new(
+                    "MIME_MISMATCH",
+                    format!(
+                        "{} was declared as {} but its content is {}",
+                        file_data.name, file_data.mime_type, detected
+                    ),
+                )));
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let files: Vec<Cow<FileData>> = request
+            .files
+            .iter()
+            .map(|file_data| correct_mime_type(file_data, &mut warnings))
+            .collect();
+
+        if let Some(blocked) = files
+            .iter()
+            .find(|file_data| !self.input_policy.permits(&file_data.mime_type))
+        {
+            console_warn!("⛔ Rejected {} ({})", blocked.name, blocked.mime_type);
+            return Ok(ConvertResponse::failure(ConversionError::new(
+                "INPUT_TYPE_BLOCKED",
+                format!(
+                    "Input type {} of {} is not accepted by this deployment",
+                    blocked.mime_type, blocked.name
+                ),
+            )));
+        }
+
+        // With a grid, image inputs share one PDF instead of getting one each;
+        // combine is the same with one image per page
+        let merge_pdf_grid = options
+            .grid
+            .or((options.combine == Some(true)).then_some((1, 1)))
+            .filter(|_| request.target_formats.iter().any(|format| format.eq_ignore_ascii_case("PDF")));
+        let mut merged_images: Vec<&FileData> = Vec::new();
+        // Likewise with merge, image inputs share one multi-page TIFF
+        let merge_tiff = options.merge == Some(true)
+            && request.target_formats.iter().any(|format| format.eq_ignore_ascii_case("TIFF"));
+        let mut tiff_pages: Vec<&FileData> = Vec::new();
+        let mut used_names = HashSet::new();
+        let mut file_errors = Vec::new();
+
+        'files: for (index, file_data) in files.iter().enumerate() {
+            let file_data = file_data.as_ref();
+            console_debug!("Processing file: {}", file_data.name);
+            self.start_file();
+
+            let source_id = if file_data.retain_source {
+                Some(self.retain_source(file_data))
+            } else {
+                None
+            };
+
+            let is_image = matches!(
+                file_data.mime_type.as_str(),
+                "image/jpeg" | "image/jpg" | "image/png" | WEBP_MIME_TYPE
+            );
+            if merge_pdf_grid.is_some() && is_image {
+                merged_images.push(file_data);
+            }
+            if merge_tiff && is_image {
+                tiff_pages.push(file_data);
+            }
+
+            // Convert to each target format
+            for format in &request.target_formats {
+                if merge_pdf_grid.is_some() && is_image && format.eq_ignore_ascii_case("PDF") {
+                    continue;
+                }
+                if merge_tiff && is_image && format.eq_ignore_ascii_case("TIFF") {
+                    continue;
+                }
+                let variants: Vec<Option<&Variant>> = match &request.variants {
+                    Some(variants) if !matches!(format.to_uppercase().as_str(), "PDF" | "DOCX") => {
+                        variants.iter().map(Some).collect()
+                    }
+                    _ => vec![None],
+                };
+                for variant in variants {
+                    let converted = catch_conversion_panic(&file_data.name, || {
+                        self.convert_with_fallbacks(file_data, format, request, &options, rules.as_ref(), variant)
+                    });
+                    match converted {
+                        Ok(mut converted) => {
+                            converted.source_id = source_id.clone();
+                            if request.preserve_name == Some(true) && named_as_format(file_data, &converted.format) {
+                                converted.converted_name = sanitize_filename(&file_data.name);
+                            } else if let Some(template) = &request.filename_template {
+                                let base_name = file_data.name.rsplit('.').nth(1).unwrap_or(&file_data.name);
+                                let name = render_filename_template(template, base_name, index + 1, &converted.format);
+                                converted.converted_name = unique_name(sanitize_filename(&name), &mut used_names);
+                            }
+                            if let Some(variant) = variant {
+                                let name = with_suffix(&converted.converted_name, &variant.suffix);
+                                converted.converted_name = unique_name(sanitize_filename(&name), &mut used_names);
+                                converted.variant = Some(variant.suffix.clone());
+                            }
+                            on_converted(&converted);
+                            converted_files.push(converted);
+                            console_log!("✅ Converted {} to {}", file_data.name, format);
+                        }
+                        Err(e) if e.code == "CONVERSION_TIMEOUT" => {
+                            console_warn!("⏱️ Gave up on {} after the timeout: {}", file_data.name, e);
+                            file_errors.push(FileError {
+                                original_name: file_data.name.clone(),
+                                format: format.clone(),
+                                error: e,
+                            });
+                            continue 'files;
+                        }
+                        Err(e) if e.code == "CONVERSION_PANIC" => {
+                            console_error!("💥 {}", e);
+                            file_errors.push(FileError {
+                                original_name: file_data.name.clone(),
+                                format: format.clone(),
+                                error: e,
+                            });
+                            continue 'files;
+                        }
+                        Err(e) => {
+                            console_warn!("❌ Failed to convert {} to {}: {}", file_data.name, format, e);
+                            self.finish_file();
+                            return Ok(ConvertResponse::failure(e));
+                        }
+                    }
+                }
+            }
+        }
+        self.finish_file();
+
+        if let (Some(grid), false) = (merge_pdf_grid, merged_images.is_empty()) {
+            let pdf_format = request
+                .target_formats
+                .iter()
+                .find(|format| format.eq_ignore_ascii_case("PDF"))
+                .map_or("PDF", String::as_str);
+            let max_size = self.max_size_for(request, pdf_format).unwrap_or(u64::MAX);
+            self.start_file();
+            let pdf_options = self.options_for_format(request, pdf_format, &options);
+            let result = self.convert_to_merged_pdf(&merged_images, grid, max_size, &pdf_options);
+            self.finish_file();
+            match result {
+                Ok(converted) => {
+                    on_converted(&converted);
+                    converted_files.push(converted);
+                }
+                Err(e) if e.code == "CONVERSION_TIMEOUT" => file_errors.push(FileError {
+                    original_name: merged_names(&merged_images),
+                    format: pdf_format.to_string(),
+                    error: e,
+                }),
+                Err(e) => {
+                    console_warn!("❌ Failed to merge images into PDF: {}", e);
+                    return Ok(ConvertResponse::failure(e));
+                }
+            }
+        }
+
+        if merge_tiff && !tiff_pages.is_empty() {
+            let max_size = self.max_size_for(request, "TIFF").unwrap_or(u64::MAX);
+            self.start_file();
+            let tiff_options = self.options_for_format(request, "TIFF", &options);
+            let result = self.convert_to_merged_tiff(&tiff_pages, max_size, &tiff_options);
+            self.finish_file();
+            match result {
+                Ok(converted) => {
+                    on_converted(&converted);
+                    converted_files.push(converted);
+                }
+                Err(e) if e.code == "CONVERSION_TIMEOUT" => file_errors.push(FileError {
+                    original_name: merged_names(&tiff_pages),
+                    format: "TIFF".to_string(),
+                    error: e,
+                }),
+                Err(e) => {
+                    console_warn!("❌ Failed to merge images into TIFF: {}", e);
+                    return Ok(ConvertResponse::failure(e));
+                }
+            }
+        }
+
+        let response = ConvertResponse {
+            success: true,
+            files: converted_files,
+            error: None,
+            warnings,
+            file_errors,
+        };
+        // A retry should get another go at files that timed out
+        if let (Some(key), true) = (&request.idempotency_key, response.file_errors.is_empty()) {
+            // Kept as long as the outputs it points at
+            self.idempotent_responses
+                .insert(key.clone(), (now_ms() + self.storage_ttl_ms, response.clone()));
+        }
+        Ok(response)
+    }
+
+    /// Resets per-file state and starts the file's conversion deadline.
+    fn start_file(&self) {
+        self.decoded_input.borrow_mut().take();
+        self.deadline.set(Some(now_ms() + self.conversion_timeout_secs * 1000.0));
+    }
+
+    fn finish_file(&self) {
+        self.decoded_input.borrow_mut().take();
+        self.deadline.set(None);
+    }
+
+    /// WASM cannot interrupt a running decoder or encoder, so the deadline is
+    /// checked between steps: before each target or fallback and between
+    /// JPEG quality attempts.
+    fn check_deadline(&self) -> Result<(), ConversionError> {
+        match self.deadline.get() {
+            Some(deadline) if now_ms() > deadline => Err(ConversionError::new(
+                "CONVERSION_TIMEOUT",
+                format!("Conversion took longer than {} seconds", self.conversion_timeout_secs),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Resets the attempt count for a new output.
+    fn start_attempts(&self) {
+        self.attempts.set(0);
+        self.attempts_exhausted.set(false);
+    }
+
+    /// Counts one encode of a size search against `max_attempts`, after
+    /// checking the deadline. Once the budget is spent this fails with
+    /// `CANNOT_MEET_SIZE`, on which the searches stop and keep their best
+    /// result so far.
+    fn attempt(&self) -> Result<(), ConversionError> {
+        self.check_deadline()?;
+        if self.attempts.get() >= self.max_attempts {
+            self.attempts_exhausted.set(true);
+            return Err(ConversionError::new(
+                "CANNOT_MEET_SIZE",
+                format!("Gave up after {} encoding attempts", self.max_attempts),
+            ));
+        }
+        self.attempts.set(self.attempts.get() + 1);
+        Ok(())
+    }
+
+    /// Whether another search round would be refused, noting that the
+    /// search was cut short if so.
+    fn attempts_spent(&self) -> bool {
+        let spent = self.attempts.get() >= self.max_attempts;
+        if spent {
+            self.attempts_exhausted.set(true);
+        }
+        spent
+    }
+
+    /// `check_size`, reporting a miss as `CANNOT_MEET_SIZE` when the search
+    /// ran out of attempts rather than of ways to shrink the output.
+    fn check_output_size(&self, content: &[u8], format: &str, max_size: u64) -> Result<(), ConversionError> {
+        check_size(content, format, max_size).map_err(|e| {
+            if !self.attempts_exhausted.get() {
+                return e;
+            }
+            ConversionError {
+                code: "CANNOT_MEET_SIZE".to_string(),
+                message: format!(
+                    "Could not fit the {} output in {} within {} encoding attempts; the smallest was {}",
+                    format,
+                    format_bytes(max_size),
+                    self.max_attempts,
+                    format_bytes(content.len() as u64)
+                ),
+                ..e
+            }
+        })
+    }
+
+    /// Predicts the output size of converting `file_data` to `target_format`
+    /// from the pixel count and a per-format bits-per-pixel model, without
+    /// encoding anything. Expect results within roughly 20% for photos.
+    pub fn estimate_size(&self, file_data: &FileData, target_format: &str, options: &ConversionOptions) -> u64 {
+        let input_size = file_data.content.len() as u64;
+        let source_format = match file_data.mime_type.as_str() {
+            "image/jpeg" | "image/jpg" => "JPEG",
+            "image/png" => "PNG",
+            // Non-image inputs are passed through or wrapped as-is
+            _ => return input_size,
+        };
+
+        let (src_width, src_height) = match image_dimensions(&file_data.content) {
+            Some(dimensions) => dimensions,
+            None => return input_size,
+        };
+        let (width, height) = target_dimensions(src_width, src_height, options);
+        let (width, height) = options
+            .max_megapixels
+            .and_then(|megapixels| megapixel_bounds(width, height, megapixels))
+            .unwrap_or((width, height));
+
+        // Calibrate against the source: a busy photo costs more bits per pixel
+        // than the model predicts, a flat scan fewer.
+        let src_pixels = (src_width as f64 * src_height as f64).max(1.0);
+        let expected_src_bits = src_pixels * bits_per_pixel(source_format, DEFAULT_JPEG_QUALITY);
+        let complexity = (input_size as f64 * 8.0 / expected_src_bits).clamp(0.25, 4.0);
+
+        let quality = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+        let pixels = width as f64 * height as f64;
+        match target_format.to_uppercase().as_str() {
+            "JPEG" | "JPG" => (pixels * bits_per_pixel("JPEG", quality) * complexity / 8.0) as u64 + 600,
+            "PNG" => (pixels * bits_per_pixel("PNG", quality) * complexity / 8.0) as u64 + 100,
+            // Images are embedded into PDFs as JPEG plus a small document wrapper
+            "PDF" => (pixels * bits_per_pixel("JPEG", quality) * complexity / 8.0) as u64 + 1_200,
+            _ => input_size,
+        }
+    }
+
+    pub fn inspect(&self, file_data: &FileData) -> DocumentInfo {
+        let mut info = DocumentInfo {
+            name: file_data.name.clone(),
+            mime_type: file_data.mime_type.clone(),
+            size: file_data.content.len() as u64,
+            width: None,
+            height: None,
+            page_count: None,
+            has_text_layer: None,
+            raster_dpi: None,
+        };
+
+        match file_data.mime_type.as_str() {
+            "application/pdf" => {
+                let has_text_layer = pdf::has_text_layer(&file_data.content);
+                info.page_count = Some(pdf::page_count(&file_data.content));
+                info.has_text_layer = Some(has_text_layer);
+                info.raster_dpi = Some(pdf_raster_dpi(has_text_layer));
+            }
+            "image/jpeg" | "image/jpg" | "image/png" | WEBP_MIME_TYPE | AVIF_MIME_TYPE => {
+                if let Some((width, height)) = image_dimensions(&file_data.content) {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            SVG_MIME_TYPE => {
+                let size = svg::Document::parse(&file_data.content).ok().and_then(|document| document.intrinsic_size());
+                if let Some((width, height)) = size {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            _ => {}
+        }
+        info
+    }
+
+    /// The request's options layered over the exam preset it names, if any.
+    fn effective_options<'a>(&self, request: &'a ConvertRequest) -> Result<Cow<'a, ConversionOptions>, ConversionError> {
+        let preset_name = match &request.preset {
+            Some(name) => name,
+            None => return Ok(Cow::Borrowed(&request.options)),
+        };
+        let preset = self
+            .exam_configs
+            .get(&request.exam_type.to_lowercase())
+            .and_then(|config| config.presets.get(preset_name))
+            .ok_or_else(|| {
+                ConversionError::new(
+                    "UNKNOWN_PRESET",
+                    format!("Exam {} has no preset named {}", request.exam_type, preset_name),
+                )
+            })?;
+        Ok(Cow::Owned(request.options.layered_over(preset)))
+    }
+
+    pub fn validate_conversion(&self, request: &ConvertRequest) -> Vec<SizeEstimate> {
+        // An unknown preset is reported by the conversion itself
+        let options = self
+            .effective_options(request)
+            .unwrap_or(Cow::Borrowed(&request.options));
+        let mut estimates = Vec::new();
+        for file_data in &request.files {
+            for format in &request.target_formats {
+                let estimated_size = self.estimate_size(file_data, format, &options);
+                let max_size = self.max_size_for(request, format);
+                estimates.push(SizeEstimate {
+                    original_name: file_data.name.clone(),
+                    format: format.clone(),
+                    estimated_size,
+                    max_size,
+                    fits: !matches!(max_size, Some(max) if estimated_size > max),
+                });
+            }
+        }
+        estimates
+    }
+
+    /// Reports how `request` would be interpreted without converting it.
+    /// `fields` is the request as plain JSON, checked for keys the typed
+    /// request silently drops, such as a misspelt `maxSizes`.
+    #[cfg(feature = "debug")]
+    pub fn echo_request(&self, request: &ConvertRequest, fields: &serde_json::Value) -> RequestEcho {
+        let mut warnings = Vec::new();
+        let mut report = |result: Result<(), ConversionError>| {
+            if let Err(e) = result {
+                warnings.push(Warning {
+                    code: e.code,
+                    message: e.message,
+                });
+            }
+        };
+
+        report(check_files_present(&request.files));
+        report(self.batch_limits.check(&request.files));
+        let requested: Vec<&String> = request
+            .target_formats
+            .iter()
+            .chain(request.format_fallbacks.values().flatten())
+            .collect();
+        for format in &requested {
+            if !TARGET_FORMATS.iter().any(|known| known.eq_ignore_ascii_case(format)) {
+                report(Err(ConversionError::new(
+                    "UNSUPPORTED_FORMAT",
+                    format!("Unsupported format: {} (expected one of {})", format, TARGET_FORMATS.join(", ")),
+                )));
+            }
+        }
+        if let Some(template) = &request.filename_template {
+            report(validate_filename_template(template));
+        }
+        report(validate_variants(request.variants.as_deref().unwrap_or_default()));
+        report(check_format_options(&request.options, &requested));
+        let options = match self.effective_options(request) {
+            Ok(options) => options,
+            Err(e) => {
+                report(Err(e));
+                Cow::Borrowed(&request.options)
+            }
+        };
+
+        let exam_config_found = self.exam_configs.contains_key(&request.exam_type.to_lowercase());
+        if !exam_config_found {
+            report(Err(ConversionError::new(
+                "UNKNOWN_EXAM_TYPE",
+                format!("No exam config is loaded for {}; only the request's own limits apply", request.exam_type),
+            )));
+        }
+        // Limits are looked up by the exact format name, so `jpeg` does not
+        // limit a `JPEG` target
+        for format in request.max_sizes.keys() {
+            if !requested.contains(&format) {
+                report(Err(ConversionError::new(
+                    "UNUSED_MAX_SIZE",
+                    format!("max_sizes has {} but no target or fallback is named exactly that", format),
+                )));
+            }
+        }
+        let max_sizes = requested
+            .iter()
+            .map(|format| (format.to_string(), self.max_size_for(request, format)))
+            .collect();
+
+        let mut files = Vec::new();
+        for file_data in &request.files {
+            let mut mime_warnings = Vec::new();
+            let corrected = correct_mime_type(file_data, &mut mime_warnings);
+            warnings.append(&mut mime_warnings);
+            if !self.input_policy.permits(&corrected.mime_type) {
+                warnings.push(Warning {
+                    code: "INPUT_TYPE_BLOCKED".to_string(),
+                    message: format!(
+                        "Input type {} of {} is not accepted by this deployment",
+                        corrected.mime_type, file_data.name
+                    ),
+                });
+            }
+            if file_data.size != file_data.content.len() as u64 {
+                warnings.push(Warning {
+                    code: "SIZE_MISMATCH".to_string(),
+                    message: format!(
+                        "{} gives size {} but its content is {} bytes",
+                        file_data.name,
+                        file_data.size,
+                        file_data.content.len()
+                    ),
+                });
+            }
+            let formats = request
+                .target_formats
+                .iter()
+                .map(|format| match format.eq_ignore_ascii_case("AUTO") {
+                    true => select_auto_format(&corrected, self.max_pixels).to_string(),
+                    false => format.to_uppercase(),
+                })
+                .collect();
+            files.push(EchoedFile {
+                name: file_data.name.clone(),
+                declared_mime_type: file_data.mime_type.clone(),
+                detected_mime_type: sniff_mime_type(&file_data.content).map(str::to_string),
+                size: file_data.content.len() as u64,
+                formats,
+            });
+        }
+
+        warnings.extend(unknown_request_fields(fields));
+        RequestEcho {
+            exam_type: request.exam_type.clone(),
+            exam_config_found,
+            max_sizes,
+            options: options.into_owned(),
+            files,
+            warnings,
+        }
+    }
+
+    /// Converts and stores one output, recording the attempt in `stats()`.
+    fn convert_to_format(
+        &mut self,
+        file_data: &FileData,
+        target_format: &str,
+        max_size: u64,
+        floor: Option<SizeFloor>,
+        rules: Option<&ValidationRules>,
+        options: &ConversionOptions,
+    ) -> Result<ConvertedFile, ConversionError> {
+        let started = now_ms();
+        let result = self.produce_output(file_data, target_format, max_size, floor, rules, options);
+        let latency_ms = now_ms() - started;
+        match &result {
+            Ok(converted) => self.stats.record_success(
+                &converted.format,
+                file_data.content.len() as u64,
+                converted.size,
+                latency_ms,
+            ),
+            Err(e) => self.stats.record_failure(&e.code, latency_ms),
+        }
+        result
+    }
+
+    fn produce_output(
+        &mut self,
+        file_data: &FileData,
+        target_format: &str,
+        max_size: u64,
+        floor: Option<SizeFloor>,
+        rules: Option<&ValidationRules>,
+        options: &ConversionOptions,
+    ) -> Result<ConvertedFile, ConversionError> {
+        self.check_deadline()?;
+        self.start_attempts();
+        let target_format = if target_format.eq_ignore_ascii_case("AUTO") {
+            let chosen = select_auto_format(file_data, self.max_pixels);
+            console_debug!("🎯 AUTO target resolved to {} for {}", chosen, file_data.name);
+            chosen
+        } else {
+            target_format
+        };
+
+        check_color_space(options)?;
+
+        let converted_content = match target_format.to_uppercase().as_str() {
+            "PDF" => self.convert_to_pdf(file_data, max_size, options)?,
+            "JPEG" | "JPG" => self.convert_to_jpeg(file_data, max_size, options)?,
+            "PNG" => self.convert_to_png(file_data, max_size, options)?,
+            "DOCX" => self.convert_to_docx(file_data)?,
+            "AVIF" => self.convert_to_avif(file_data, max_size, options)?,
+            "TIFF" => self.convert_to_tiff(file_data, max_size, options)?,
+            _ => {
+                return Err(ConversionError::new(
+                    "UNSUPPORTED_FORMAT",
+                    format!(
+                        "Unsupported format: {} (expected one of {})",
+                        target_format,
+                        TARGET_FORMATS.join(", ")
+                    ),
+                ))
+            }
+        };
+
+        let converted_content = match floor {
+            Some(floor) if (converted_content.len() as u64) < floor.min_size => {
+                if !floor.pad {
+                    return Err(below_minimum(converted_content.len(), floor.min_size));
+                }
+                self.grow_to_min(file_data, target_format, floor.min_size, max_size, options)?
+            }
+            _ => converted_content,
+        };
+        self.check_output_size(&converted_content, target_format, max_size)?;
+
+        if let Some(rules) = rules {
+            if matches!(target_format.to_uppercase().as_str(), "JPEG" | "JPG" | "PNG") {
+                self.check_validation_rules(&converted_content, rules)?;
+            }
+        }
+
+        let extension = extension_for(target_format);
+        let base_name = file_data.name.rsplit('.').nth(1).unwrap_or(&file_data.name);
+        let converted_name = sanitize_filename(&format!("{}.{}", base_name, extension));
+
+        let was_reencoded = converted_content != file_data.content;
+        let mut converted = self.store_output(
+            &file_data.name,
+            converted_name,
+            target_format,
+            converted_content,
+            download_limit(file_data),
+        )?;
+        converted.was_reencoded = was_reencoded;
+        Ok(converted)
+    }
+
+    /// Puts a finished output into temporary storage and describes it.
+    fn store_output(
+        &mut self,
+        original_name: &str,
+        converted_name: String,
+        format: &str,
+        mut content: Vec<u8>,
+        remaining_downloads: Option<u32>,
+    ) -> Result<ConvertedFile, ConversionError> {
+        let (width, height) = match format.to_uppercase().as_str() {
+            "JPEG" | "JPG" | "PNG" | "AVIF" => match image_dimensions(&content) {
+                Some((width, height)) => (Some(width), Some(height)),
+                None => (None, None),
+            },
+            _ => (None, None),
+        };
+
+        let size = content.len() as u64;
+        let sha256 = sha256_hex(&content);
+        let mut converted = ConvertedFile {
+            original_name: original_name.to_string(),
+            converted_name,
+            download_url: None,
+            content_base64: None,
+            format: format.to_string(),
+            size,
+            width,
+            height,
+            fallback_from: None,
+            sha256,
+            source_id: None,
+            variant: None,
+            was_reencoded: true,
+            attempts_used: self.attempts.get(),
+        };
+
+        if let Some(budget) = self.inline_budget {
+            if size > budget {
+                return Err(ConversionError::new(
+                    "INLINE_TOO_LARGE",
+                    format!(
+                        "Inline outputs are limited to {} per response; request download URLs instead",
+                        format_bytes(MAX_INLINE_BYTES)
+                    ),
+                ));
+            }
+            self.inline_budget = Some(budget - size);
+            converted.content_base64 = Some(base64::engine::general_purpose::STANDARD.encode(&content));
+            return Ok(converted);
+        }
+
+        // Idempotent requests get ids derived from the output, so a retry
+        // after a partial failure lands on the same ids and URLs
+        let file_id = match &self.id_seed {
+            Some(seed) => stable_file_id(seed, self.stored_outputs, format, &converted.sha256),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        self.stored_outputs += 1;
+        if let Some(previous) = self.temp_storage.remove(&file_id) {
+            console_debug!("♻️ Replacing stored file {} ({} bytes)", file_id, previous.content.len());
+        }
+        // Full or unwritable storage both fall back to inline output under
+        // the inline policy
+        let can_inline = matches!(self.storage_limit.when_full, StorageFullPolicy::Inline) && size <= MAX_INLINE_BYTES;
+        // Byte-identical outputs share one stored copy
+        let stored = match self.shared_blob(&converted.sha256) {
+            Some(shared) => Ok(shared),
+            None => match self.reserve_storage(size) {
+                Ok(()) => {
+                    // A disk write can fail, so keep the bytes to fall back on
+                    let fallback = (can_inline && matches!(self.storage_backend, StorageBackend::Disk { .. }))
+                        .then(|| content.clone());
+                    match Blob::store(&self.storage_backend, std::mem::take(&mut content), &converted.sha256) {
+                        Ok(blob) => Ok(Rc::new(blob)),
+                        Err(e) => {
+                            content = fallback.unwrap_or_default();
+                            Err(ConversionError::new(
+                                "STORAGE_UNAVAILABLE",
+                                format!("Could not store {}: {}", converted.converted_name, e),
+                            ))
+                        }
+                    }
+                }
+                Err(e) => Err(e),
+            },
+        };
+        let content = match stored {
+            Ok(content) => content,
+            Err(e) if can_inline => {
+                console_warn!("📦 {}, returning {} ({} bytes) inline", e, converted.converted_name, size);
+                converted.content_base64 = Some(base64::engine::general_purpose::STANDARD.encode(&content));
+                return Ok(converted);
+            }
+            Err(e) => return Err(e),
+        };
+        let now = now_ms();
+
+        // Store in temporary storage (in real implementation, create blob URL)
+        self.temp_storage.insert(
+            file_id.clone(),
+            StoredFile {
+                content,
+                name: converted.converted_name.clone(),
+                format: format.to_string(),
+                width,
+                height,
+                expires_at: now + self.storage_ttl_ms,
+                remaining_downloads,
+                last_used: now,
+                sha256: converted.sha256.clone(),
+            },
+        );
+        converted.download_url = Some(format!("blob:{}", file_id));
+        Ok(converted)
+    }
+
+    /// Lays all image inputs out on shared PDF pages, `cols` x `rows` per page,
+    /// in upload order.
+    fn convert_to_merged_pdf(
+        &mut self,
+        files: &[&FileData],
+        (cols, rows): (u8, u8),
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<ConvertedFile, ConversionError> {
+        if cols == 0 || rows == 0 {
+            return Err(ConversionError::new("INVALID_OPTION", "grid must have at least one column and one row"));
+        }
+        check_color_space(options)?;
+        self.start_attempts();
+        console_log!("📄 Merging {} images into a {}x{} grid PDF", files.len(), cols, rows);
+
+        let images = files
+            .iter()
+            .map(|file_data| self.decode_input(&file_data.content))
+            .collect::<Result<Vec<_>, _>>()?;
+        let content = self.images_to_pdf(&images, (cols as u32, rows as u32), max_size, options)?;
+        self.check_output_size(&content, "PDF", max_size)?;
+
+        let first = files[0];
+        let base_name = first.name.rsplit('.').nth(1).unwrap_or(&first.name);
+        let converted_name = sanitize_filename(&format!("{}_merged.pdf", base_name));
+        let original_name = files.iter().map(|file_data| file_data.name.as_str()).collect::<Vec<_>>().join(", ");
+        // The merged file is as restricted as the most restricted input
+        let remaining_downloads = files.iter().filter_map(|file_data| download_limit(file_data)).min();
+
+        self.store_output(&original_name, converted_name, "PDF", content, remaining_downloads)
+    }
+
+    /// Writes every image input as one page of a single TIFF, in upload order.
+    fn convert_to_merged_tiff(
+        &mut self,
+        files: &[&FileData],
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<ConvertedFile, ConversionError> {
+        console_log!("🗂️ Merging {} images into a multi-page TIFF", files.len());
+        self.start_attempts();
+        let pages = files
+            .iter()
+            .map(|file_data| {
+                self.check_deadline()?;
+                let image = self.decode_input(&file_data.content)?;
+                self.apply_pixel_options(image, options)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let content = encode_tiff(&pages, options.tiff_compression.unwrap_or_default())?;
+        check_size(&content, "TIFF", max_size)?;
+
+        let first = files[0];
+        let base_name = first.name.rsplit('.').nth(1).unwrap_or(&first.name);
+        let converted_name = sanitize_filename(&format!("{}_merged.tiff", base_name));
+        let original_name = files.iter().map(|file_data| file_data.name.as_str()).collect::<Vec<_>>().join(", ");
+        let remaining_downloads = files.iter().filter_map(|file_data| download_limit(file_data)).min();
+
+        self.store_output(&original_name, converted_name, "TIFF", content, remaining_downloads)
+    }
+
+    /// Converts to `format`, and if that misses its size limit, walks the
+    /// request's fallback chain for it. Other failures are returned as-is.
+    fn convert_with_fallbacks(
+        &mut self,
+        file_data: &FileData,
+        format: &str,
+        request: &ConvertRequest,
+        options: &ConversionOptions,
+        rules: Option<&ValidationRules>,
+        variant: Option<&Variant>,
+    ) -> Result<ConvertedFile, ConversionError> {
+        let variant_max = variant.and_then(|variant| variant.max_size).unwrap_or(u64::MAX);
+        let max_size = self.max_size_for(request, format).unwrap_or(u64::MAX).min(variant_max);
+        let floor = self.size_floor_for(request, format);
+        let format_options = with_variant(self.options_for_format(request, format, options), variant);
+        let error = match self.convert_to_format(file_data, format, max_size, floor, rules, &format_options) {
+            Err(e) if matches!(e.code.as_str(), "SIZE_LIMIT_EXCEEDED" | "CANNOT_MEET_SIZE") => e,
+            result => return result,
+        };
+
+        for fallback in request.format_fallbacks.get(format).into_iter().flatten() {
+            // Keep the original budget unless the fallback has a tighter one
+            let fallback_max = self.max_size_for(request, fallback).map_or(max_size, |limit| limit.min(max_size));
+            let fallback_floor = self.size_floor_for(request, fallback);
+            let fallback_options = with_variant(self.options_for_format(request, fallback, options), variant);
+            match self.convert_to_format(file_data, fallback, fallback_max, fallback_floor, rules, &fallback_options) {
+                Ok(mut converted) => {
+                    console_log!("↪️ {} did not fit as {}, used {} instead", file_data.name, format, fallback);
+                    converted.fallback_from = Some(format.to_string());
+                    return Ok(converted);
+                }
+                Err(e) => console_warn!("❌ Fallback {} for {} failed: {}", fallback, file_data.name, e),
+            }
+        }
+        Err(error)
+    }
+
+    fn convert_to_pdf(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            "application/pdf" if options.downsample_images == Some(true) => {
+                self.downsample_pdf_images(&file_data.content, max_size, options)
+            }
+            "application/pdf" => Ok(file_data.content.clone()),
+            "image/jpeg" | "image/jpg" | "image/png" => {
+                self.create_pdf_with_image(&file_data.content, max_size, options)
+            }
+            AVIF_MIME_TYPE => {
+                let image = self.decode_input(&file_data.content)?;
+                self.images_to_pdf(&[image], (1, 1), max_size, options)
+            }
+            WEBP_MIME_TYPE => {
+                let image = self.decode_webp(&file_data.content, options)?;
+                self.images_to_pdf(&[image], (1, 1), max_size, options)
+            }
+            SVG_MIME_TYPE => {
+                let (image, options) = self.rasterize_svg(&file_data.content, options, Some(WHITE))?;
+                self.images_to_pdf(&[image], (1, 1), max_size, &options)
+            }
+            TEXT_MIME_TYPE => self.text_to_pdf(file_data, max_size, options),
+            CSV_MIME_TYPE => self.csv_to_pdf(file_data, max_size, options),
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to PDF",
+            )),
+        }
+    }
+
+    fn convert_to_jpeg(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            "image/jpeg" | "image/jpg" => {
+                self.compress_image(&file_data.content, "jpeg", max_size, options)
+            }
+            "image/png" => {
+                self.convert_png_to_jpeg(&file_data.content, max_size, options)
+            }
+            "application/pdf" => {
+                self.pdf_to_image(&file_data.content, "jpeg", max_size, options)
+            }
+            DOCX_MIME_TYPE => {
+                self.docx_to_image(&file_data.content, "jpeg", max_size, options)
+            }
+            SVG_MIME_TYPE => {
+                self.svg_to_image(&file_data.content, "jpeg", max_size, options)
+            }
+            WEBP_MIME_TYPE => {
+                let image = self.decode_webp(&file_data.content, options)?;
+                self.encode_image(image, "jpeg", max_size, options)
+            }
+            AVIF_MIME_TYPE => {
+                let image = self.decode_input(&file_data.content)?;
+                self.encode_image(image, "jpeg", max_size, options)
+            }
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to JPEG",
+            )),
+        }
+    }
+
+    fn convert_to_png(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            "image/png" => {
+                self.compress_image(&file_data.content, "png", max_size, options)
+            }
+            "image/jpeg" | "image/jpg" => {
+                self.convert_jpeg_to_png(&file_data.content, max_size, options)
+            }
+            "application/pdf" => {
+                self.pdf_to_image(&file_data.content, "png", max_size, options)
+            }
+            DOCX_MIME_TYPE => {
+                self.docx_to_image(&file_data.content, "png", max_size, options)
+            }
+            SVG_MIME_TYPE => {
+                self.svg_to_image(&file_data.content, "png", max_size, options)
+            }
+            WEBP_MIME_TYPE => {
+                let image = self.decode_webp(&file_data.content, options)?;
+                self.encode_image(image, "png", max_size, options)
+            }
+            AVIF_MIME_TYPE => {
+                let image = self.decode_input(&file_data.content)?;
+                self.encode_image(image, "png", max_size, options)
+            }
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to PNG",
+            )),
+        }
+    }
+
+    fn convert_to_tiff(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            "image/jpeg" | "image/jpg" | "image/png" | AVIF_MIME_TYPE => {
+                let image = self.decode_input(&file_data.content)?;
+                self.encode_image(image, "tiff", max_size, options)
+            }
+            WEBP_MIME_TYPE => {
+                let image = self.decode_webp(&file_data.content, options)?;
+                self.encode_image(image, "tiff", max_size, options)
+            }
+            DOCX_MIME_TYPE => self.docx_to_image(&file_data.content, "tiff", max_size, options),
+            SVG_MIME_TYPE => self.svg_to_image(&file_data.content, "tiff", max_size, options),
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to TIFF",
+            )),
+        }
+    }
+
+    fn convert_to_avif(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            AVIF_MIME_TYPE => {
+                self.compress_image(&file_data.content, "avif", max_size, options)
+            }
+            "image/jpeg" | "image/jpg" | "image/png" => {
+                if !cfg!(feature = "avif") {
+                    return Err(avif_unavailable());
+                }
+                console_debug!("🔄 Converting {} to AVIF", file_data.mime_type);
+                let image = self.decode_input(&file_data.content)?;
+                self.encode_image(image, "avif", max_size, options)
+            }
+            DOCX_MIME_TYPE => {
+                self.docx_to_image(&file_data.content, "avif", max_size, options)
+            }
+            SVG_MIME_TYPE => {
+                self.svg_to_image(&file_data.content, "avif", max_size, options)
+            }
+            WEBP_MIME_TYPE => {
+                let image = self.decode_webp(&file_data.content, options)?;
+                self.encode_image(image, "avif", max_size, options)
+            }
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to AVIF",
+            )),
+        }
+    }
+
+    fn convert_to_docx(&self, file_data: &FileData) -> Result<Vec<u8>, ConversionError> {
+        match file_data.mime_type.as_str() {
+            DOCX_MIME_TYPE => {
+                Ok(file_data.content.clone())
+            }
+            "application/pdf" => self.pdf_to_docx(&file_data.content),
+            _ => Err(ConversionError::new(
+                "CONVERSION_NOT_SUPPORTED",
+                "Cannot convert this file type to DOCX",
+            )),
+        }
+    }
+
+    /// Writes each line of the PDF's text layer as a paragraph. Scans have
+    /// no text to extract and fail with `NO_TEXT_LAYER`.
+    fn pdf_to_docx(&self, content: &[u8]) -> Result<Vec<u8>, ConversionError> {
+        let lines = if pdf::has_text_layer(content) { pdf::extract_lines(content) } else { Vec::new() };
+        if lines.is_empty() {
+            return Err(ConversionError::new(
+                "NO_TEXT_LAYER",
+                "The PDF has no extractable text; it may be a scan",
+            ));
+        }
+        console_debug!("📄➡️📝 Extracted {} lines of PDF text for DOCX", lines.len());
+        docx::write_paragraphs(&lines)
+            .map_err(|e| ConversionError::new("ENCODE_ERROR", format!("DOCX writing failed: {}", e)))
+    }
+
+    fn check_validation_rules(&self, content: &[u8], rules: &ValidationRules) -> Result<(), ConversionError> {
+        let image = image::load_from_memory(content).map_err(|e| {
+            ConversionError::new(
+                "RULE_VIOLATION",
+                format!("Converted image could not be decoded for validation: {}", e),
+            )
+        })?;
+        let luma = image.to_luma8();
+        let (width, height) = luma.dimensions();
+
+        let aspect_ratio = width as f32 / height.max(1) as f32;
+        if let Some(min) = rules.min_aspect_ratio {
+            if aspect_ratio < min {
+                return Err(ConversionError::new(
+                    "RULE_VIOLATION",
+                    format!("Aspect ratio {:.2} is below the minimum of {:.2}", aspect_ratio, min),
+                ));
+            }
+        }
+        if let Some(max) = rules.max_aspect_ratio {
+            if aspect_ratio > max {
+                return Err(ConversionError::new(
+                    "RULE_VIOLATION",
+                    format!("Aspect ratio {:.2} is above the maximum of {:.2}", aspect_ratio, max),
+                ));
+            }
+        }
+
+        if let Some(min_brightness) = rules.min_background_brightness {
+            // Sample a border band 10% of the shorter edge wide as the background
+            let band = (width.min(height) / 10).max(1);
+            let mut total: u64 = 0;
+            let mut count: u64 = 0;
+            for (x, y, pixel) in luma.enumerate_pixels() {
+                if x < band || y < band || x >= width.saturating_sub(band) || y >= height.saturating_sub(band) {
+                    total += pixel.0[0] as u64;
+                    count += 1;
+                }
+            }
+            let brightness = total.checked_div(count).unwrap_or(0);
+            if brightness < min_brightness as u64 {
+                return Err(ConversionError::new(
+                    "RULE_VIOLATION",
+                    format!(
+                        "Background brightness {} is below the required minimum of {}",
+                        brightness, min_brightness
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Helper methods
+    fn create_pdf_with_image(
+        &self,
+        image_content: &[u8],
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("📄 Creating PDF with embedded image");
+        let image = self.decode_input(image_content)?;
+        self.images_to_pdf(&[image], (1, 1), max_size, options)
+    }
+
+    /// Embeds the images as JPEGs on A4 pages laid out in a `cols` x `rows`
+    /// grid, lowering the JPEG quality until the document fits `max_size`.
+    fn images_to_pdf(
+        &self,
+        images: &[DynamicImage],
+        (cols, rows): (u32, u32),
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let images = images
+            .iter()
+            .map(|image| self.apply_pixel_options(image.clone(), options))
+            .collect::<Result<Vec<_>, _>>()?;
+        let dimensions: Vec<(u32, u32)> = images.iter().map(|image| (image.width(), image.height())).collect();
+        let placements = pdf::layout_grid(&dimensions, cols, rows);
+
+        let start = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+        fit_jpeg_quality(start, max_size, |quality| {
+            self.attempt()?;
+            let mut pages: Vec<pdf::Page> = Vec::new();
+            for (image, placement) in images.iter().zip(&placements) {
+                if placement.page == pages.len() {
+                    pages.push(pdf::Page { images: Vec::new() });
+                }
+                pages[placement.page].images.push(pdf::PlacedImage {
+                    jpeg: encode_jpeg(image, quality, self.jpeg_backend)?,
+                    pixel_width: image.width(),
+                    pixel_height: image.height(),
+                    x: placement.x,
+                    y: placement.y,
+                    width: placement.width,
+                    height: placement.height,
+                });
+            }
+            Ok(pdf::write_document(&pages, &self.pdf_metadata))
+        })
+    }
+
+    /// Recompresses the images embedded in a PDF as JPEGs, lowering quality
+    /// and then resolution until the document fits `max_size`. Text, vector
+    /// drawing and images that cannot be decoded are kept as they were, so
+    /// a document that is large for other reasons can still miss the limit.
+    fn downsample_pdf_images(
+        &self,
+        content: &[u8],
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        if content.len() as u64 <= max_size {
+            console_debug!("⏩ PDF is already under {} bytes, passing it through unchanged", max_size);
+            return Ok(content.to_vec());
+        }
+        let (rewriter, images) =
+            pdf::ImageRewriter::load(content).map_err(|e| ConversionError::new("DECODE_ERROR", e))?;
+        // Images the converter cannot decode are left for `write` to keep
+        let images: Vec<Option<DynamicImage>> = images.into_iter().map(|data| self.decode_pdf_image(data).ok()).collect();
+        if images.iter().all(Option::is_none) {
+            console_debug!("⏩ PDF has no images that can be recompressed, passing it through unchanged");
+            return Ok(content.to_vec());
+        }
+        console_log!("📄🖼️ Recompressing {} images embedded in PDF", images.iter().flatten().count());
+
+        let start = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+        let mut scale = 1.0f64;
+        // Highest quality that fits, though below the floor; used if no
+        // scale does better
+        let mut fallback: Option<(u8, Vec<u8>)> = None;
+        let mut smallest: Option<Vec<u8>> = None;
+        for _ in 0..MAX_PDF_IMAGE_SHRINK_STEPS {
+            if self.attempts_spent() {
+                break;
+            }
+            let scaled: Vec<Option<DynamicImage>> = images
+                .iter()
+                .map(|image| {
+                    image.as_ref().map(|image| {
+                        if scale < 1.0 {
+                            let width = ((image.width() as f64 * scale).round() as u32).max(1);
+                            let height = ((image.height() as f64 * scale).round() as u32).max(1);
+                            image.resize_exact(width, height, FilterType::Lanczos3)
+                        } else {
+                            image.clone()
+                        }
+                    })
+                })
+                .collect();
+            let write = |quality: u8| {
+                self.attempt()?;
+                let replacements = scaled
+                    .iter()
+                    .map(|image| {
+                        image
+                            .as_ref()
+                            .map(|image| {
+                                Ok(pdf::ReplacementImage {
+                                    jpeg: encode_jpeg(image, quality, self.jpeg_backend)?,
+                                    pixel_width: image.width(),
+                                    pixel_height: image.height(),
+                                })
+                            })
+                            .transpose()
+                    })
+                    .collect::<Result<Vec<_>, ConversionError>>()?;
+                rewriter.write(&replacements).map_err(|e| ConversionError::new("ENCODE_ERROR", e))
+            };
+            let floor = PDF_IMAGE_MIN_QUALITY.min(start);
+            let (quality, output) = fit_quality(start, max_size, write)?;
+            let output_size = output.len() as u64;
+            if output_size <= max_size && quality >= floor {
+                return Ok(output);
+            }
+            // Size at the quality floor, for estimating how far to shrink
+            let floor_size = match quality {
+                quality if quality == floor => output_size,
+                _ => match out_of_attempts(write(floor))? {
+                    Some(encoded) => encoded.len() as u64,
+                    None => output_size,
+                },
+            };
+            if output_size <= max_size {
+                if !matches!(&fallback, Some((best, _)) if *best >= quality) {
+                    fallback = Some((quality, output));
+                }
+            } else if !matches!(&smallest, Some(smallest) if smallest.len() as u64 <= output_size) {
+                smallest = Some(output);
+            }
+            // Image bytes grow roughly with the pixel count
+            let shrink = (max_size as f64 / floor_size.max(1) as f64).sqrt() * 0.95;
+            scale *= shrink.min(0.9);
+        }
+        // The caller's size check rejects an output still over the limit
+        Ok(fallback.map(|(_, output)| output).or(smallest).unwrap_or_else(|| content.to_vec()))
+    }
+
+    fn decode_pdf_image(&self, data: pdf::ImageData) -> Result<DynamicImage, ConversionError> {
+        match data {
+            pdf::ImageData::Jpeg(jpeg) => decode_image(&jpeg, self.max_pixels),
+            pdf::ImageData::Raw {
+                width,
+                height,
+                channels,
+                samples,
+            } => {
+                check_pixel_count(width, height, self.max_pixels)?;
+                let image = match channels {
+                    1 => GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+                    _ => RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8),
+                };
+                image.ok_or_else(|| ConversionError::new("DECODE_ERROR", "Embedded image data is truncated"))
+            }
+        }
+    }
+
+    fn compress_image(
+        &self,
+        content: &[u8],
+        format: &str,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("🖼️ Compressing {} image to max {} bytes", format, max_size);
+
+        match reencode_reason(content, format, max_size, options) {
+            Some(reason) => console_debug!("🔁 Re-encoding {} input: {}", format, reason),
+            None => {
+                console_debug!("⏩ Input is already {} and under {} bytes, passing it through unchanged", format, max_size);
+                return Ok(content.to_vec());
+            }
+        }
+        let image = self.decode_input(content)?;
+        self.encode_image(image, format, max_size, options)
+    }
+
+    fn convert_png_to_jpeg(
+        &self,
+        content: &[u8],
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("🔄 Converting PNG to JPEG");
+        let image = self.decode_input(content)?;
+        self.encode_image(image, "jpeg", max_size, options)
+    }
+
+    fn convert_jpeg_to_png(
+        &self,
+        content: &[u8],
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("🔄 Converting JPEG to PNG");
+        let image = self.decode_input(content)?;
+        self.encode_image(image, "png", max_size, options)
+    }
+
+    /// Renders a preview of the document's first page from its paragraph text.
+    fn docx_to_image(
+        &self,
+        content: &[u8],
+        format: &str,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("📝➡️🖼️ Rendering DOCX first page as {}", format);
+        let paragraphs = docx::extract_paragraphs(content)
+            .map_err(|e| ConversionError::new("CONVERSION_NOT_SUPPORTED", e))?;
+        let page = render_text_page(&paragraphs);
+        self.encode_image(page, format, max_size, options)
+    }
+
+    fn svg_to_image(
+        &self,
+        content: &[u8],
+        format: &str,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("✏️➡️🖼️ Rasterizing SVG as {}", format);
+        // JPEG has no alpha channel, so render it over white rather than black
+        let background = if format == "jpeg" { Some(WHITE) } else { None };
+        let (image, options) = self.rasterize_svg(content, options, background)?;
+        self.encode_image(image, format, max_size, &options)
+    }
+
+    /// Renders the SVG straight at the requested width and height, either one
+    /// following the intrinsic aspect ratio. The options are returned without
+    /// the dimensions so the bitmap is not resampled again afterwards.
+    fn rasterize_svg(
+        &self,
+        content: &[u8],
+        options: &ConversionOptions,
+        background: Option<[u8; 3]>,
+    ) -> Result<(DynamicImage, ConversionOptions), ConversionError> {
+        let document = svg::Document::parse(content).map_err(|e| ConversionError::new("DECODE_ERROR", e))?;
+        let (width, height) = match (document.intrinsic_size(), options.width, options.height) {
+            (Some((width, height)), _, _) => target_dimensions(width, height, options),
+            (None, Some(width), Some(height)) => (width, height),
+            (None, _, _) => {
+                return Err(ConversionError::new(
+                    "SVG_SIZE_REQUIRED",
+                    "The SVG has no width, height or viewBox; set both width and height to rasterize it",
+                ))
+            }
+        };
+        check_pixel_count(width, height, self.max_pixels)?;
+        self.check_deadline()?;
+        let image = document
+            .rasterize(width, height, background)
+            .map_err(|e| ConversionError::new("DECODE_ERROR", e))?;
+
+        let mut remaining = options.clone();
+        remaining.width = None;
+        remaining.height = None;
+        Ok((DynamicImage::ImageRgba8(image), remaining))
+    }
+
+    /// Lays plain text out on an A4 page and embeds it in a PDF.
+    fn text_to_pdf(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("📝➡️📄 Converting text to PDF");
+        let text = text::decode(&file_data.content, file_data.charset.as_deref())
+            .map_err(|e| ConversionError::new("TEXT_DECODE_ERROR", e))?;
+        let page = render_text_page(&text::paragraphs(&text));
+        self.images_to_pdf(&[page], (1, 1), max_size, options)
+    }
+
+    /// Renders CSV as a paginated table, one image per page.
+    fn csv_to_pdf(
+        &self,
+        file_data: &FileData,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        console_debug!("📊➡️📄 Converting CSV to PDF");
+        let delimiter = match options.delimiter {
+            None => b',',
+            Some(delimiter) if delimiter.is_ascii() => delimiter as u8,
+            Some(_) => {
+                return Err(ConversionError::new(
+                    "INVALID_OPTION",
+                    "delimiter must be a single ASCII character",
+                ))
+            }
+        };
+        let text = text::decode(&file_data.content, file_data.charset.as_deref())
+            .map_err(|e| ConversionError::new("TEXT_DECODE_ERROR", e))?;
+        let rows = table::parse(&text, delimiter).map_err(|e| ConversionError::new("CSV_PARSE_ERROR", e))?;
+        let pages: Vec<DynamicImage> = table::render_pages(&rows).into_iter().map(DynamicImage::ImageRgba8).collect();
+        self.images_to_pdf(&pages, (1, 1), max_size, options)
+    }
+
+    /// Decodes an input image once per file; further target formats for the
+    /// same bytes get a copy of the cached bitmap instead of decoding again.
+    fn decode_input(&self, content: &[u8]) -> Result<DynamicImage, ConversionError> {
+        use sha2::{Digest, Sha256};
+        let key: [u8; 32] = Sha256::digest(content).into();
+        if let Some((cached_key, image)) = self.decoded_input.borrow().as_ref() {
+            if *cached_key == key {
+                return Ok(image.clone());
+            }
+        }
+        let image = decode_image(content, self.max_pixels)?;
+        #[cfg(test)]
+        self.input_decodes.set(self.input_decodes.get() + 1);
+        *self.decoded_input.borrow_mut() = Some((key, image.clone()));
+        Ok(image)
+    }
+
+    /// Decodes WebP input. For an animated file the frame picked by
+    /// `options.frame` is composed onto the canvas and used as a still image.
+    fn decode_webp(&self, content: &[u8], options: &ConversionOptions) -> Result<DynamicImage, ConversionError> {
+        let index = options.frame.unwrap_or(0);
+        if let Some((width, height)) = image_dimensions(content) {
+            check_pixel_count(width, height, self.max_pixels)?;
+        }
+        let decode_error = |e: image::ImageError| ConversionError::new("DECODE_ERROR", format!("Could not decode image: {}", e));
+        let decoder = WebPDecoder::new(Cursor::new(content)).map_err(decode_error)?;
+        if !decoder.has_animation() {
+            if index > 0 {
+                return Err(ConversionError::new(
+                    "INVALID_OPTION",
+                    format!("frame {} was requested but the WebP is not animated", index),
+                ));
+            }
+            return DynamicImage::from_decoder(decoder).map_err(decode_error);
+        }
+        console_debug!("🎞️ Extracting frame {} of animated WebP", index);
+        let mut frame_count = 0;
+        for frame in decoder.into_frames() {
+            self.check_deadline()?;
+            let frame = frame.map_err(decode_error)?;
+            if frame_count == index {
+                return Ok(DynamicImage::ImageRgba8(frame.into_buffer()));
+            }
+            frame_count += 1;
+        }
+        Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("frame {} is out of range; the animation has {} frames", index, frame_count),
+        ))
+    }
+
+    /// Scales the image to fit inside `width` x `height`, keeping its aspect
+    /// ratio, and centres it on a canvas of that size filled with
+    /// `pad_image` or `pad_color`.
+    fn contain(
+        &self,
+        image: &DynamicImage,
+        width: u32,
+        height: u32,
+        options: &ConversionOptions,
+    ) -> Result<DynamicImage, ConversionError> {
+        check_pixel_count(width, height, self.max_pixels)?;
+        let (mut canvas, pad_has_alpha) = match &options.pad_image {
+            Some(content) => {
+                if let Some((pad_width, pad_height)) = image_dimensions(content) {
+                    check_pixel_count(pad_width, pad_height, self.max_pixels)?;
+                }
+                let background = image::load_from_memory(content).map_err(|e| {
+                    ConversionError::new("INVALID_OPTION", format!("pad_image could not be decoded: {}", e))
+                })?;
+                let has_alpha = background.color().has_alpha();
+                (background.resize_to_fill(width, height, FilterType::Lanczos3).to_rgba8(), has_alpha)
+            }
+            None => {
+                let color = parse_hex_color(options.pad_color.as_deref().unwrap_or("#ffffff"))?;
+                (RgbaImage::from_pixel(width, height, color), false)
+            }
+        };
+        let fitted = image.resize(width, height, FilterType::Lanczos3);
+        let (x, y) = ((width - fitted.width()) / 2, (height - fitted.height()) / 2);
+        image::imageops::overlay(&mut canvas, &fitted.to_rgba8(), x as i64, y as i64);
+
+        let canvas = DynamicImage::ImageRgba8(canvas);
+        if image.color().has_alpha() || pad_has_alpha {
+            Ok(canvas)
+        } else {
+            Ok(DynamicImage::ImageRgb8(canvas.to_rgb8()))
+        }
+    }
+
+    /// Resizes and captions the image as requested by the options.
+    fn apply_pixel_options(&self, image: DynamicImage, options: &ConversionOptions) -> Result<DynamicImage, ConversionError> {
+        let mut image = image;
+        if options.trim_borders == Some(true) {
+            image = trim_borders(image, options.trim_tolerance.unwrap_or(DEFAULT_TRIM_TOLERANCE));
+        }
+        if options.ensure_orientation.is_some_and(|orientation| !orientation.matches(image.width(), image.height())) {
+            image = image.rotate90();
+        }
+        if let (Some(width), Some(height), Some(Fit::Contain)) = (options.width, options.height, options.fit) {
+            image = self.contain(&image, width, height, options)?;
+        } else if options.width.is_some() || options.height.is_some() {
+            let (width, height) = target_dimensions(image.width(), image.height(), options);
+            image = image.resize_exact(width, height, FilterType::Lanczos3);
+        } else if let Some(max_dimension) = options.max_dimension {
+            if max_dimension.exceeded_by(image.width(), image.height()) {
+                let (max_width, max_height) = max_dimension.bounds();
+                image = image.resize(max_width, max_height, FilterType::Lanczos3);
+            }
+        }
+        if let Some(megapixels) = options.max_megapixels {
+            image = clamp_to_megapixels(image, megapixels)?;
+        }
+        if options.adjusts_tone() {
+            image = adjust_tone(&image, options)?;
+        }
+        if let Some(amount) = options.sharpen {
+            image = sharpen(&image, amount)?;
+        }
+        if options.make_transparent == Some(true) {
+            let threshold = options.transparency_threshold.unwrap_or(DEFAULT_TRANSPARENCY_THRESHOLD);
+            image = make_transparent(&image, threshold)?;
+        }
+        if let Some(qr) = &options.qr {
+            image = stamp_qr(&image, qr)?;
+        }
+        if let Some(caption) = &options.caption {
+            image = add_caption(&image, caption)?;
+        }
+        Ok(image)
+    }
+
+    /// Applies the pixel options, then encodes. JPEG quality is searched
+    /// downwards for the best setting that fits `max_size`; if none does, the
+    /// smallest attempt is returned and the caller's size check rejects it.
+    fn encode_image(
+        &self,
+        image: DynamicImage,
+        format: &str,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let image = self.apply_pixel_options(image, options)?;
+
+        match format {
+            "jpeg" => {
+                console_debug!("🖼️ Encoding JPEG with the {:?} backend", self.jpeg_backend);
+                let start = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+                fit_jpeg_quality(start, max_size, |quality| {
+                    self.attempt()?;
+                    encode_jpeg(&image, quality, self.jpeg_backend)
+                })
+            }
+            "png" => encode_png(&image, options.interlace == Some(true), png_bit_depth(options)?),
+            #[cfg(feature = "avif")]
+            "avif" => {
+                let start = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+                fit_jpeg_quality(start, max_size, |quality| {
+                    self.attempt()?;
+                    encode_avif(&image, quality)
+                })
+            }
+            #[cfg(not(feature = "avif"))]
+            "avif" => Err(avif_unavailable()),
+            "tiff" => encode_tiff(std::slice::from_ref(&image), options.tiff_compression.unwrap_or_default()),
+            _ => Err(ConversionError::new(
+                "UNSUPPORTED_FORMAT",
+                format!("Unsupported raster format: {}", format),
+            )),
+        }
+    }
+
+    /// Picks the format, quality and dimensions that give the best-looking
+    /// output under `max_bytes`. At each scale every format is fitted to the
+    /// budget; the first scale where PNG fits or a lossy format keeps at
+    /// least `OPTIMIZE_MIN_QUALITY` wins. Otherwise the image is shrunk by
+    /// the estimated excess and tried again.
+    pub fn optimize(&mut self, request: &OptimizeRequest) -> Result<OptimizeResult, ConversionError> {
+        let file_data = &request.file;
+        check_files_present(std::slice::from_ref(file_data))?;
+        self.batch_limits.check(std::slice::from_ref(file_data))?;
+        if !self.input_policy.permits(&file_data.mime_type) {
+            return Err(ConversionError::new(
+                "INPUT_TYPE_BLOCKED",
+                format!("Input type {} of {} is not accepted by this deployment", file_data.mime_type, file_data.name),
+            ));
+        }
+        let formats = optimize_formats(&request.formats)?;
+        let max_bytes = request.max_bytes;
+        console_log!("🎯 Optimizing {} for {} bytes across {}", file_data.name, max_bytes, formats.join(", "));
+
+        self.start_file();
+        // The search has its own bounds; `max_attempts` is for conversions
+        self.start_attempts();
+        let result = self.search_under_budget(file_data, &formats, max_bytes);
+        self.finish_file();
+        let (candidate, width, height, scale) = result?;
+
+        let base_name = file_data.name.rsplit('.').nth(1).unwrap_or(&file_data.name);
+        let converted_name = sanitize_filename(&format!("{}.{}", base_name, extension_for(candidate.format)));
+        let file = self.store_output(
+            &file_data.name,
+            converted_name,
+            &candidate.format.to_uppercase(),
+            candidate.content,
+            download_limit(file_data),
+        )?;
+        Ok(OptimizeResult {
+            file,
+            format: candidate.format.to_uppercase(),
+            quality: candidate.quality,
+            width,
+            height,
+            scale,
+        })
+    }
+
+    fn search_under_budget(
+        &self,
+        file_data: &FileData,
+        formats: &[&'static str],
+        max_bytes: u64,
+    ) -> Result<(OptimizeCandidate, u32, u32, f64), ConversionError> {
+        let image = self.decode_input(&file_data.content)?;
+        let mut scale = 1.0f64;
+        // Best fit below the quality floor, used if no scale does better
+        let mut fallback: Option<(OptimizeCandidate, u32, u32, f64)> = None;
+        let mut smallest_seen = u64::MAX;
+
+        for _ in 0..MAX_OPTIMIZE_SHRINK_STEPS {
+            let width = ((image.width() as f64 * scale).round() as u32).max(1);
+            let height = ((image.height() as f64 * scale).round() as u32).max(1);
+            let scaled = if scale < 1.0 {
+                image.resize_exact(width, height, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+
+            let mut best: Option<OptimizeCandidate> = None;
+            // Size at the quality floor, for estimating how far to shrink
+            let mut floor_size = u64::MAX;
+            for &format in formats {
+                self.check_deadline()?;
+                let (quality, content) = match format {
+                    "png" => (None, encode_png(&scaled, false, 8)?),
+                    _ => {
+                        let encode = |quality: u8| match format {
+                            #[cfg(feature = "avif")]
+                            "avif" => encode_avif(&scaled, quality),
+                            _ => encode_jpeg(&scaled, quality, self.jpeg_backend),
+                        };
+                        let (quality, content) = fit_quality(OPTIMIZE_START_QUALITY, max_bytes, |quality| {
+                            self.check_deadline()?;
+                            encode(quality)
+                        })?;
+                        if content.len() as u64 > max_bytes || quality < OPTIMIZE_MIN_QUALITY {
+                            floor_size = floor_size.min(encode(OPTIMIZE_MIN_QUALITY)?.len() as u64);
+                        }
+                        (Some(quality), content)
+                    }
+                };
+                smallest_seen = smallest_seen.min(content.len() as u64);
+                if content.len() as u64 > max_bytes {
+                    floor_size = floor_size.min(content.len() as u64);
+                    continue;
+                }
+                let candidate = OptimizeCandidate { format, quality, content };
+                if !matches!(&best, Some(best) if best.rank() >= candidate.rank()) {
+                    best = Some(candidate);
+                }
+            }
+
+            match best {
+                Some(best) if !matches!(best.quality, Some(quality) if quality < OPTIMIZE_MIN_QUALITY) => {
+                    return Ok((best, width, height, scale));
+                }
+                Some(best) if fallback.is_none() => fallback = Some((best, width, height, scale)),
+                _ => {}
+            }
+            // Encoded size grows roughly with the pixel count
+            let shrink = (max_bytes as f64 / floor_size.max(1) as f64).sqrt() * 0.95;
+            scale *= shrink.min(0.9);
+        }
+
+        fallback.ok_or_else(|| {
+            ConversionError::new(
+                "SIZE_LIMIT_EXCEEDED",
+                format!(
+                    "No format fits {}; the smallest output was {}",
+                    format_bytes(max_bytes),
+                    format_bytes(smallest_seen)
+                ),
+            )
+            .with_sizes(smallest_seen, max_bytes)
+        })
+    }
+
+    /// Re-encodes an image output that came out under `min_size`. JPEG quality
+    /// is raised first; if even quality 100 is too small, the image is
+    /// upscaled by the estimated shortfall and encoded again at quality 100,
+    /// so each growth step costs a single attempt.
+    fn grow_to_min(
+        &self,
+        file_data: &FileData,
+        target_format: &str,
+        min_size: u64,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let format = match target_format.to_uppercase().as_str() {
+            "JPEG" | "JPG" => "jpeg",
+            "PNG" => "png",
+            _ => return Err(cannot_grow(target_format)),
+        };
+        if !matches!(file_data.mime_type.as_str(), "image/jpeg" | "image/jpg" | "image/png" | WEBP_MIME_TYPE) {
+            return Err(cannot_grow(target_format));
+        }
+        console_debug!("📈 Growing {} as {} to at least {} bytes", file_data.name, format, min_size);
+
+        let image = match file_data.mime_type.as_str() {
+            WEBP_MIME_TYPE => self.decode_webp(&file_data.content, options)?,
+            _ => self.decode_input(&file_data.content)?,
+        };
+        let image = self.apply_pixel_options(image, options)?;
+        let mut start = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+        let mut scale = 1.0f64;
+        let mut last_size = 0;
+        for _ in 0..MAX_GROW_STEPS {
+            self.check_deadline()?;
+            if self.attempts_spent() {
+                break;
+            }
+            let scaled = if scale > 1.0 {
+                let width = (image.width() as f64 * scale).round() as u32;
+                let height = (image.height() as f64 * scale).round() as u32;
+                check_pixel_count(width, height, self.max_pixels)?;
+                if options.max_dimension.is_some_and(|max_dimension| max_dimension.exceeded_by(width, height))
+                    || options.exceeds_megapixels(width, height)
+                {
+                    break;
+                }
+                image.resize_exact(width, height, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+            let encoded = match format {
+                "jpeg" => lowest_jpeg_quality_reaching(start, min_size, |quality| {
+                    self.attempt()?;
+                    encode_jpeg(&scaled, quality, self.jpeg_backend)
+                })?,
+                _ => encode_png(&scaled, options.interlace == Some(true), png_bit_depth(options)?)?,
+            };
+            if encoded.len() as u64 >= min_size || encoded.len() as u64 > max_size {
+                return Ok(encoded);
+            }
+            last_size = encoded.len();
+            // Encoded size grows roughly with the pixel count
+            scale *= (min_size as f64 / encoded.len().max(1) as f64).sqrt() * 1.05;
+            // Even quality 100 fell short, so searching lower ones again is wasted
+            start = 100;
+        }
+        Err(below_minimum(last_size, min_size))
+    }
+
+    /// Renders `options.page` as `format` ("jpeg" or "png") at `options.dpi`,
+    /// or the DPI `pdf_raster_dpi` picks. There is no full PDF rasterizer in
+    /// the module, so only pages made of images, such as scans, can be drawn;
+    /// anything else fails with `PDF_RENDER_UNSUPPORTED`. First pages come
+    /// from the render cache when it is on.
+    fn pdf_to_image(
+        &self,
+        content: &[u8],
+        format: &str,
+        max_size: u64,
+        options: &ConversionOptions,
+    ) -> Result<Vec<u8>, ConversionError> {
+        let number = options.page.unwrap_or(1);
+        if number == 0 {
+            return Err(ConversionError::new("INVALID_OPTION", "page is 1-based and must be at least 1"));
+        }
+        let dpi = options.dpi.unwrap_or_else(|| pdf_raster_dpi(pdf::has_text_layer(content)));
+        if !(1..=MAX_PDF_RASTER_DPI).contains(&dpi) {
+            return Err(ConversionError::new(
+                "INVALID_OPTION",
+                format!("dpi must be between 1 and {}", MAX_PDF_RASTER_DPI),
+            ));
+        }
+        console_log!("📄➡️🖼️ Rendering page {} of PDF to {} at {} DPI", number, format.to_uppercase(), dpi);
+
+        let cache_key = (number == 1 && self.render_cache.borrow().is_enabled()).then(|| {
+            use sha2::{Digest, Sha256};
+            let digest: [u8; 32] = Sha256::digest(content).into();
+            (digest, dpi)
+        });
+        let cached = cache_key.and_then(|key| self.render_cache.borrow_mut().get(key));
+        let canvas = match cached {
+            Some(canvas) => {
+                console_debug!("♻️ Reusing the cached render of the first page");
+                canvas
+            }
+            None => {
+                let canvas = self.render_pdf_page(content, number, dpi)?;
+                if let Some(key) = cache_key {
+                    self.render_cache.borrow_mut().insert(key, canvas.clone());
+                }
+                canvas
+            }
+        };
+        self.encode_image(DynamicImage::ImageRgb8(canvas), format, max_size, options)
+    }
+
+    /// Draws page `number` (1-based) onto a white canvas at `dpi`.
+    fn render_pdf_page(&self, content: &[u8], number: u32, dpi: u32) -> Result<RgbImage, ConversionError> {
+        let page = pdf::image_page(content, number).map_err(|e| match e {
+            pdf::RenderError::NoSuchPage(count) => ConversionError::new(
+                "INVALID_OPTION",
+                format!("page {} was requested but the PDF has {} pages", number, count),
+            ),
+            pdf::RenderError::Unsupported(message) => ConversionError::new("PDF_RENDER_UNSUPPORTED", message),
+        })?;
+        let scale = dpi as f32 / 72.0;
+        let to_pixels = |points: f32| (points * scale).round().max(0.0) as u32;
+        let (width, height) = (to_pixels(page.width).max(1), to_pixels(page.height).max(1));
+        check_pixel_count(width, height, self.max_pixels)?;
+
+        let mut canvas = RgbImage::from_pixel(width, height, image::Rgb(WHITE));
+        for drawn in page.images {
+            self.check_deadline()?;
+            let (drawn_width, drawn_height) = (to_pixels(drawn.width).max(1), to_pixels(drawn.height).max(1));
+            check_pixel_count(drawn_width, drawn_height, self.max_pixels)?;
+            let image = self
+                .decode_pdf_image(drawn.data)?
+                .resize_exact(drawn_width, drawn_height, FilterType::Triangle)
+                .to_rgb8();
+            // PDF measures up from the bottom, images down from the top
+            let top = (page.height - drawn.y - drawn.height) * scale;
+            image::imageops::overlay(&mut canvas, &image, (drawn.x * scale).round() as i64, top.round() as i64);
+        }
+        Ok(canvas)
+    }
+}
+
+/// Trusts the bytes over the declared type when they disagree, recording a
+/// `MIME_MISMATCH` warning for the caller.
+fn correct_mime_type<'a>(file_data: &'a FileData, warnings: &mut Vec<Warning>) -> Cow<'a, FileData> {
+    match detected_mime_mismatch(file_data) {
+        Some(detected) => {
+            console_warn!(
+                "⚠️ {} declared as {} but looks like {}",
+                file_data.name,
+                file_data.mime_type,
+                detected
+            );
+            warnings.push(Warning {
+                code: "MIME_MISMATCH".to_string(),
+                message: format!(
+                    "{} was declared as {} but its content is {}; converted as {}",
+                    file_data.name, file_data.mime_type, detected, detected
+                ),
+            });
+            let mut corrected = file_data.clone();
+            corrected.mime_type = detected.to_string();
+            Cow::Owned(corrected)
+        }
+        None => Cow::Borrowed(file_data),
+    }
+}
+
+/// The type the content was detected as, if it differs from the declared one.
+/// Content of no recognized type is taken at its word.
+fn detected_mime_mismatch(file_data: &FileData) -> Option<&'static str> {
+    sniff_mime_type(&file_data.content).filter(|detected| !same_mime_type(detected, &file_data.mime_type))
+}
+
+/// Detects the actual file type from its leading magic bytes.
+fn sniff_mime_type(content: &[u8]) -> Option<&'static str> {
+    if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+        Some(WEBP_MIME_TYPE)
+    } else if content.get(4..12) == Some(b"ftypavif") {
+        Some(AVIF_MIME_TYPE)
+    } else if content.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else if content.starts_with(b"PK\x03\x04")
+        && content.windows(b"word/".len()).any(|window| window == b"word/")
+    {
+        Some(DOCX_MIME_TYPE)
+    } else if looks_like_svg(content) {
+        Some(SVG_MIME_TYPE)
+    } else {
+        None
+    }
+}
+
+/// Markup starting with a tag and containing an `<svg` element early on.
+fn looks_like_svg(content: &[u8]) -> bool {
+    let head = &content[..content.len().min(1024)];
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let first = head.iter().find(|byte| !byte.is_ascii_whitespace());
+    first == Some(&b'<') && head.windows(4).any(|window| window == b"<svg")
+}
+
+fn same_mime_type(a: &str, b: &str) -> bool {
+    let normalize = |mime: &str| match mime.to_ascii_lowercase().as_str() {
+        "image/jpg" => "image/jpeg".to_string(),
+        other => other.to_string(),
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Picks the output format for an `AUTO` target: PDF for documents, PNG for
+/// flat-color images and JPEG for photographs.
+fn select_auto_format(file_data: &FileData, max_pixels: u64) -> &'static str {
+    if !file_data.mime_type.starts_with("image/") {
+        return "PDF";
+    }
+    // Vector art is flat color and may rely on transparency
+    if file_data.mime_type == SVG_MIME_TYPE {
+        return "PNG";
+    }
+    let image = match decode_image(&file_data.content, max_pixels) {
+        Ok(image) => image,
+        Err(_) => return "JPEG",
+    };
+
+    // Nearest-neighbour sampling keeps the original palette intact
+    let sample = image
+        .resize(AUTO_SAMPLE_EDGE, AUTO_SAMPLE_EDGE, FilterType::Nearest)
+        .to_rgb8();
+    let mut colors = std::collections::HashSet::new();
+    for pixel in sample.pixels() {
+        colors.insert(pixel.0);
+        if colors.len() > AUTO_PNG_MAX_COLORS {
+            return "JPEG";
+        }
+    }
+    "PNG"
+}
+
+fn pdf_raster_dpi(has_text_layer: bool) -> u32 {
+    if has_text_layer {
+        TEXT_PDF_RASTER_DPI
+    } else {
+        SCANNED_PDF_RASTER_DPI
+    }
+}
+
+/// Encoded size scales roughly with pixel count, so the dimensions that would
+/// fit are the current ones shrunk by the square root of the size overshoot,
+/// with a 10% margin.
+fn size_suggestions(content: &[u8], target_format: &str, max_size: u64) -> SizeSuggestions {
+    let smallest_size = content.len() as u64;
+    let is_jpeg = matches!(target_format.to_uppercase().as_str(), "JPEG" | "JPG");
+    let is_raster = is_jpeg || target_format.eq_ignore_ascii_case("PNG");
+    let dimensions = if is_raster { image_dimensions(content) } else { None };
+
+    let mut suggestions = SizeSuggestions {
+        smallest_size,
+        max_size,
+        resize_would_help: false,
+        current_width: dimensions.map(|(width, _)| width),
+        current_height: dimensions.map(|(_, height)| height),
+        suggested_width: None,
+        suggested_height: None,
+        suggested_quality: if is_jpeg { Some(MIN_JPEG_QUALITY) } else { None },
+    };
+
+    if let Some((width, height)) = dimensions {
+        let scale = (max_size as f64 / smallest_size.max(1) as f64).sqrt() * 0.9;
+        let suggested_width = (width as f64 * scale).floor() as u32;
+        let suggested_height = (height as f64 * scale).floor() as u32;
+        if suggested_width > 0 && suggested_height > 0 {
+            suggestions.resize_would_help = true;
+            suggestions.suggested_width = Some(suggested_width);
+            suggestions.suggested_height = Some(suggested_height);
+        }
+    }
+    suggestions
+}
+
+fn check_size(content: &[u8], format: &str, max_size: u64) -> Result<(), ConversionError> {
+    if content.len() as u64 <= max_size {
+        return Ok(());
+    }
+    let mut error = ConversionError::new(
+        "SIZE_LIMIT_EXCEEDED",
+        format!(
+            "Converted file size ({}) exceeds maximum allowed size ({})",
+            format_bytes(content.len() as u64),
+            format_bytes(max_size)
+        ),
+    )
+    .with_sizes(content.len() as u64, max_size);
+    error.suggestions = Some(Box::new(size_suggestions(content, format, max_size)));
+    Err(error)
+}
+
+/// Byte count for messages: exact below 1 KiB, otherwise in binary units
+/// to one decimal, e.g. `120.6 KiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} {}", bytes, if bytes == 1 { "byte" } else { "bytes" });
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn below_minimum(size: usize, min_size: u64) -> ConversionError {
+    ConversionError::new(
+        "SIZE_BELOW_MINIMUM",
+        format!(
+            "Converted file size ({}) is below the minimum allowed size ({})",
+            format_bytes(size as u64),
+            format_bytes(min_size)
+        ),
+    )
+    .with_sizes(size as u64, min_size)
+}
+
+fn cannot_grow(format: &str) -> ConversionError {
+    ConversionError::new(
+        "SIZE_BELOW_MINIMUM",
+        format!(
+            "Converted {} file is below the minimum size and only JPEG or PNG outputs of images can be grown",
+            format
+        ),
+    )
+}
+
+/// The mirror of `fit_jpeg_quality`: binary-searches upwards from `start`
+/// for the lowest quality whose output reaches `min_size`. If even quality
+/// 100 falls short, that attempt is returned.
+fn lowest_jpeg_quality_reaching(
+    start: u8,
+    min_size: u64,
+    encode: impl Fn(u8) -> Result<Vec<u8>, ConversionError>,
+) -> Result<Vec<u8>, ConversionError> {
+    let first = encode(start)?;
+    if first.len() as u64 >= min_size || start >= 100 {
+        return Ok(first);
+    }
+    let mut reaching = match out_of_attempts(encode(100))? {
+        Some(encoded) => encoded,
+        None => return Ok(first),
+    };
+    if (reaching.len() as u64) < min_size {
+        return Ok(reaching);
+    }
+
+    // `high` always reaches the minimum and `reaching` is its output
+    let (mut low, mut high) = (start + 1, 100);
+    while low < high {
+        let quality = low + (high - low) / 2;
+        let Some(encoded) = out_of_attempts(encode(quality))? else {
+            break;
+        };
+        if encoded.len() as u64 >= min_size {
+            reaching = encoded;
+            high = quality;
+        } else {
+            low = quality + 1;
+        }
+    }
+    Ok(reaching)
+}
+
+/// Runs `encode` at `start` quality and, if the result is over `max_size`,
+/// binary-searches down to `MIN_JPEG_QUALITY` for the highest quality that
+/// fits. If nothing fits, or `encode` runs out of attempts first, the best
+/// attempt so far is returned and the caller's size check judges it.
+fn fit_jpeg_quality(
+    start: u8,
+    max_size: u64,
+    encode: impl Fn(u8) -> Result<Vec<u8>, ConversionError>,
+) -> Result<Vec<u8>, ConversionError> {
+    fit_quality(start, max_size, encode).map(|(_, encoded)| encoded)
+}
+
+/// `fit_jpeg_quality`, also returning the quality the result was encoded at.
+fn fit_quality(
+    start: u8,
+    max_size: u64,
+    encode: impl Fn(u8) -> Result<Vec<u8>, ConversionError>,
+) -> Result<(u8, Vec<u8>), ConversionError> {
+    let best = encode(start)?;
+    if best.len() as u64 <= max_size || start <= MIN_JPEG_QUALITY {
+        return Ok((start, best));
+    }
+
+    let (mut low, mut high) = (MIN_JPEG_QUALITY, start - 1);
+    let mut fitting: Option<(u8, Vec<u8>)> = None;
+    let mut smallest = (start, best);
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let Some(encoded) = out_of_attempts(encode(quality))? else {
+            break;
+        };
+        if encoded.len() as u64 <= max_size {
+            fitting = Some((quality, encoded));
+            low = quality + 1;
+        } else {
+            if encoded.len() < smallest.1.len() {
+                smallest = (quality, encoded);
+            }
+            if quality == MIN_JPEG_QUALITY {
+                break;
+            }
+            high = quality - 1;
+        }
+    }
+    Ok(fitting.unwrap_or(smallest))
+}
+
+/// `None` for an encode refused because the output's `max_attempts` are
+/// spent, which ends a search with its best result so far.
+fn out_of_attempts(encoded: Result<Vec<u8>, ConversionError>) -> Result<Option<Vec<u8>>, ConversionError> {
+    match encoded {
+        Ok(encoded) => Ok(Some(encoded)),
+        Err(e) if e.code == "CANNOT_MEET_SIZE" => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decodes an image after checking the dimensions declared in its header
+/// against `max_pixels`, so decompression bombs are refused before the
+/// full bitmap is allocated.
+fn decode_image(content: &[u8], max_pixels: u64) -> Result<DynamicImage, ConversionError> {
+    if !cfg!(feature = "avif") && sniff_mime_type(content) == Some(AVIF_MIME_TYPE) {
+        return Err(ConversionError::new(
+            "AVIF_UNAVAILABLE",
+            "Decoding AVIF input requires a build with the avif feature; without it AVIF can only be passed through unchanged",
+        ));
+    }
+    if let Some((width, height)) = image_dimensions(content) {
+        check_pixel_count(width, height, max_pixels)?;
+    }
+    image::load_from_memory(content)
+        .map_err(|e| ConversionError::new("DECODE_ERROR", format!("Could not decode image: {}", e)))
+}
+
+/// Runs one file's conversion, turning a decoder or encoder panic into a
+/// `CONVERSION_PANIC` error for that file. Native hosts unwind, so the rest
+/// of the batch and the converter survive a malformed file; the WASM build
+/// aborts on panic, so there it can only run `convert`.
+fn catch_conversion_panic<T>(
+    file_name: &str,
+    convert: impl FnOnce() -> Result<T, ConversionError>,
+) -> Result<T, ConversionError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(convert)).unwrap_or_else(|panic| {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            Err(ConversionError::new(
+                "CONVERSION_PANIC",
+                format!("Converting {} crashed: {}", file_name, reason),
+            ))
+        })
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = file_name;
+        convert()
+    }
+}
+
+fn read_blob(blob: &Blob, file_id: &str) -> Result<Vec<u8>, ConversionError> {
+    blob.read().map_err(|e| storage_read_error(file_id, e))
+}
+
+fn storage_read_error(file_id: &str, e: std::io::Error) -> ConversionError {
+    ConversionError::new("STORAGE_ERROR", format!("Could not read stored file {}: {}", file_id, e))
+}
+
+/// Encoder names for `optimize_image`'s candidate formats.
+fn optimize_formats(requested: &[String]) -> Result<Vec<&'static str>, ConversionError> {
+    if requested.is_empty() {
+        let mut formats = vec!["jpeg", "png"];
+        if cfg!(feature = "avif") {
+            formats.push("avif");
+        }
+        return Ok(formats);
+    }
+    let mut formats = Vec::new();
+    for format in requested {
+        let encoder = match format.to_uppercase().as_str() {
+            "JPEG" | "JPG" => "jpeg",
+            "PNG" => "png",
+            #[cfg(feature = "avif")]
+            "AVIF" => "avif",
+            #[cfg(not(feature = "avif"))]
+            "AVIF" => return Err(avif_unavailable()),
+            _ => {
+                return Err(ConversionError::new(
+                    "UNSUPPORTED_FORMAT",
+                    format!("optimize_image chooses between JPEG, PNG and AVIF, not {}", format),
+                ))
+            }
+        };
+        if !formats.contains(&encoder) {
+            formats.push(encoder);
+        }
+    }
+    Ok(formats)
+}
+
+/// A batch needs at least one file, and every file some content; an empty
+/// upload would otherwise only fail later in a decoder.
+fn check_files_present(files: &[FileData]) -> Result<(), ConversionError> {
+    if files.is_empty() {
+        return Err(ConversionError::new("NO_FILES", "The request contains no files to convert"));
+    }
+    if let Some(file) = files.iter().find(|file| file.content.is_empty()) {
+        return Err(ConversionError::new("EMPTY_FILE", format!("{} is empty (0 bytes)", file.name)));
+    }
+    Ok(())
+}
+
+fn check_pixel_count(width: u32, height: u32, max_pixels: u64) -> Result<(), ConversionError> {
+    let pixels = width as u64 * height as u64;
+    if pixels > max_pixels {
+        return Err(ConversionError::new(
+            "IMAGE_TOO_LARGE",
+            format!(
+                "Image is {}x{} ({} pixels), the limit is {} pixels",
+                width, height, pixels, max_pixels
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn image_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    if sniff_mime_type(content) == Some(AVIF_MIME_TYPE) {
+        return avif_dimensions(content);
+    }
+    image::io::Reader::new(Cursor::new(content))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
+
+/// Width and height from the `ispe` (image spatial extents) property of an
+/// AVIF file, which every AVIF image carries. Read without decoding, so it
+/// works in builds without the `avif` feature too.
+fn avif_dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    // Box size and type, then a version and flags word before the extents
+    let start = content.windows(4).position(|window| window == b"ispe")? + 8;
+    let field = |offset: usize| -> Option<u32> {
+        let bytes = content.get(start + offset..start + offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    };
+    match (field(0)?, field(4)?) {
+        (0, _) | (_, 0) => None,
+        dimensions => Some(dimensions),
+    }
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8, backend: JpegBackend) -> Result<Vec<u8>, ConversionError> {
+    let rgb = image.to_rgb8();
+    let mut buffer = Vec::new();
+    let encode_error =
+        |e: &dyn fmt::Display| ConversionError::new("ENCODE_ERROR", format!("JPEG encoding failed: {}", e));
+    match backend {
+        JpegBackend::Image => JpegEncoder::new_with_quality(&mut buffer, quality)
+            .encode_image(&rgb)
+            .map_err(|e| encode_error(&e))?,
+        JpegBackend::JpegEncoder => {
+            // JPEG frame headers store each dimension in 16 bits
+            let (width, height) = match (u16::try_from(rgb.width()), u16::try_from(rgb.height())) {
+                (Ok(width), Ok(height)) => (width, height),
+                _ => {
+                    let size = format!("{}x{} exceeds the JPEG size limit", rgb.width(), rgb.height());
+                    return Err(encode_error(&size));
+                }
+            };
+            let mut encoder = jpeg_encoder::Encoder::new(&mut buffer, quality);
+            encoder.set_optimized_huffman_tables(true);
+            encoder
+                .encode(rgb.as_raw(), width, height, jpeg_encoder::ColorType::Rgb)
+                .map_err(|e| encode_error(&e))?;
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, ConversionError> {
+    let rgba = image.to_rgba8();
+    let mut buffer = Vec::new();
+    image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_ENCODER_SPEED, quality)
+        .write_image(&rgba, rgba.width(), rgba.height(), image::ColorType::Rgba8)
+        .map_err(|e| ConversionError::new("ENCODE_ERROR", format!("AVIF encoding failed: {}", e)))?;
+    Ok(buffer)
+}
+
+fn avif_unavailable() -> ConversionError {
+    ConversionError::new(
+        "AVIF_UNAVAILABLE",
+        "AVIF output requires a build with the avif feature",
+    )
+}
+
+fn png_bit_depth(options: &ConversionOptions) -> Result<u8, ConversionError> {
+    match options.bit_depth {
+        None => Ok(8),
+        Some(depth @ (8 | 16)) => Ok(depth),
+        Some(depth) => Err(ConversionError::new(
+            "INVALID_BIT_DEPTH",
+            format!("PNG bit depth must be 8 or 16, got {}", depth),
+        )),
+    }
+}
+
+/// One TIFF directory per page, as 8-bit RGB, or RGBA where a page has alpha.
+fn encode_tiff(pages: &[DynamicImage], compression: TiffCompression) -> Result<Vec<u8>, ConversionError> {
+    use tiff::encoder::compression::{Deflate, Lzw};
+    let mut buffer = Cursor::new(Vec::new());
+    let mut encoder = tiff::encoder::TiffEncoder::new(&mut buffer).map_err(tiff_error)?;
+    for page in pages {
+        match compression {
+            TiffCompression::Lzw => write_tiff_page(&mut encoder, page, Lzw),
+            TiffCompression::Deflate => write_tiff_page(&mut encoder, page, Deflate::default()),
+        }
+        .map_err(tiff_error)?;
+    }
+    Ok(buffer.into_inner())
+}
+
+fn write_tiff_page<W: std::io::Write + std::io::Seek, D: tiff::encoder::compression::Compression>(
+    encoder: &mut tiff::encoder::TiffEncoder<W>,
+    page: &DynamicImage,
+    compression: D,
+) -> tiff::TiffResult<()> {
+    use tiff::encoder::colortype::{RGB8, RGBA8};
+    if page.color().has_alpha() {
+        encoder.write_image_with_compression::<RGBA8, D>(page.width(), page.height(), compression, &page.to_rgba8())
+    } else {
+        encoder.write_image_with_compression::<RGB8, D>(page.width(), page.height(), compression, &page.to_rgb8())
+    }
+}
+
+fn tiff_error(e: tiff::TiffError) -> ConversionError {
+    ConversionError::new("ENCODE_ERROR", format!("TIFF encoding failed: {}", e))
+}
+
+/// `bit_depth` is 8 or 16. Widening to 16 bits scales each sample by 257,
+/// so 8-bit sources keep their exact values.
+fn encode_png(image: &DynamicImage, interlace: bool, bit_depth: u8) -> Result<Vec<u8>, ConversionError> {
+    let has_alpha = image.color().has_alpha();
+    let (width, height) = (image.width(), image.height());
+    let channels: u8 = if has_alpha { 4 } else { 3 };
+    // Samples in the byte order each encoder expects: big-endian as stored
+    // in the file for the interlaced writer, native for the png crate
+    let samples = |to_bytes: fn(u16) -> [u8; 2]| -> Vec<u8> {
+        match (bit_depth, has_alpha) {
+            (16, true) => image.to_rgba16().into_raw().into_iter().flat_map(to_bytes).collect(),
+            (16, false) => image.to_rgb16().into_raw().into_iter().flat_map(to_bytes).collect(),
+            (_, true) => image.to_rgba8().into_raw(),
+            (_, false) => image.to_rgb8().into_raw(),
+        }
+    };
+
+    if interlace {
+        return png::encode_interlaced(&samples(u16::to_be_bytes), width, height, channels, bit_depth)
+            .map_err(|e| ConversionError::new("ENCODE_ERROR", format!("PNG encoding failed: {}", e)));
+    }
+
+    let color_type = match (bit_depth, has_alpha) {
+        (16, true) => image::ColorType::Rgba16,
+        (16, false) => image::ColorType::Rgb16,
+        (_, true) => image::ColorType::Rgba8,
+        (_, false) => image::ColorType::Rgb8,
+    };
+    let mut buffer = Vec::new();
+    PngEncoder::new_with_quality(&mut buffer, CompressionType::Best, PngFilterType::Adaptive)
+        .write_image(&samples(u16::to_ne_bytes), width, height, color_type)
+        .map_err(|e| ConversionError::new("ENCODE_ERROR", format!("PNG encoding failed: {}", e)))?;
+    Ok(buffer)
+}
+
+/// Only RGB output can be produced: CMYK needs an ICC profile and a colour
+/// management engine, neither of which is bundled with the WASM module.
+fn check_color_space(options: &ConversionOptions) -> Result<(), ConversionError> {
+    match options.color_space.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("rgb") => Ok(()),
+        Some("cmyk") => Err(ConversionError::new(
+            "CMYK_UNSUPPORTED",
+            "CMYK output needs an ICC profile, which is not available in this build",
+        )),
+        Some(other) => Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("Unknown color_space '{}', expected rgb or cmyk", other),
+        )),
+    }
+}
+
+/// Applies gamma, then contrast around mid-grey, then brightness, through a
+/// per-channel lookup table. Alpha is left untouched.
+fn adjust_tone(image: &DynamicImage, options: &ConversionOptions) -> Result<DynamicImage, ConversionError> {
+    let brightness = options.brightness.unwrap_or(0);
+    let contrast = options.contrast.unwrap_or(1.0);
+    let gamma = options.gamma.unwrap_or(1.0);
+    if !(-255..=255).contains(&brightness) {
+        return Err(ConversionError::new("INVALID_OPTION", "brightness must be between -255 and 255"));
+    }
+    if !(contrast > 0.0 && contrast <= MAX_CONTRAST) {
+        return Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("contrast must be greater than 0 and at most {}", MAX_CONTRAST),
+        ));
+    }
+    if !(MIN_GAMMA..=MAX_GAMMA).contains(&gamma) {
+        return Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("gamma must be between {} and {}", MIN_GAMMA, MAX_GAMMA),
+        ));
+    }
+
+    let mut table = [0u8; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        let corrected = 255.0 * (value as f32 / 255.0).powf(1.0 / gamma);
+        let contrasted = (corrected - 127.5) * contrast + 127.5;
+        *entry = (contrasted + brightness as f32).round().clamp(0.0, 255.0) as u8;
+    }
+
+    let mut pixels = image.to_rgba8();
+    for pixel in pixels.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = table[*channel as usize];
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(pixels))
+}
+
+/// Crops to the smallest rectangle holding every pixel that differs from the
+/// top-left corner's colour by more than `tolerance` in some channel. An
+/// image with no such pixel is all border and is returned unchanged.
+fn trim_borders(image: DynamicImage, tolerance: u8) -> DynamicImage {
+    let pixels = image.to_rgba8();
+    let border = *pixels.get_pixel(0, 0);
+    let mut content: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        let differs = pixel.0.iter().zip(border.0).any(|(&channel, edge)| channel.abs_diff(edge) > tolerance);
+        if differs {
+            content = Some(match content {
+                Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    match content {
+        Some((left, top, right, bottom)) => {
+            console_debug!("✂️ Trimmed borders to {}x{} at ({}, {})", right - left + 1, bottom - top + 1, left, top);
+            image.crop_imm(left, top, right - left + 1, bottom - top + 1)
+        }
+        None => {
+            console_debug!("✂️ Image is one uniform colour, leaving it untrimmed");
+            image
+        }
+    }
+}
+
+/// Fades out pixels by lightness: at or above `threshold` they become fully
+/// transparent, and darker ones reach full opacity over
+/// `TRANSPARENCY_EDGE_RAMP` levels. Colours are kept, so formats without
+/// alpha still show the original background.
+fn make_transparent(image: &DynamicImage, threshold: u8) -> Result<DynamicImage, ConversionError> {
+    let lightness = image.to_luma8();
+    let mut pixels = image.to_rgba8();
+    let (width, height) = lightness.dimensions();
+    // A signature scan's edges are paper (or already transparent); if most
+    // are darker, keying would eat into the picture itself
+    let edge: Vec<(u32, u32)> = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]))
+        .collect();
+    let background = edge
+        .iter()
+        .filter(|&&(x, y)| lightness.get_pixel(x, y).0[0] >= threshold || pixels.get_pixel(x, y).0[3] == 0)
+        .count();
+    if background * 2 < edge.len() {
+        return Err(ConversionError::new(
+            "BACKGROUND_NOT_LIGHT",
+            format!(
+                "make_transparent needs a light background, but most of the image's edge is darker than {}",
+                threshold
+            ),
+        ));
+    }
+
+    for (pixel, value) in pixels.pixels_mut().zip(lightness.pixels()) {
+        let opacity = (threshold.saturating_sub(value.0[0]) as u32 * 255 / TRANSPARENCY_EDGE_RAMP).min(255);
+        pixel.0[3] = (pixel.0[3] as u32 * opacity / 255) as u8;
+    }
+    Ok(DynamicImage::ImageRgba8(pixels))
+}
+
+/// Unsharp mask with `amount` as the blur sigma, so larger values sharpen
+/// coarser detail. Differences below `SHARPEN_THRESHOLD` are left alone to
+/// avoid amplifying sensor noise.
+fn sharpen(image: &DynamicImage, amount: f32) -> Result<DynamicImage, ConversionError> {
+    if !(amount > 0.0 && amount <= MAX_SHARPEN_AMOUNT) {
+        return Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("sharpen must be greater than 0 and at most {}", MAX_SHARPEN_AMOUNT),
+        ));
+    }
+    Ok(image.unsharpen(amount, SHARPEN_THRESHOLD))
+}
+
+/// Draws the QR code for `qr.data`, with its quiet zone, into a corner of the
+/// image. Modules are scaled by a whole number of pixels so they stay sharp;
+/// any remainder of `size_px` becomes extra white border.
+fn stamp_qr(image: &DynamicImage, qr: &QrSpec) -> Result<DynamicImage, ConversionError> {
+    let code = qrcode::QrCode::new(qr.data.as_bytes())
+        .map_err(|e| ConversionError::new("INVALID_OPTION", format!("Cannot encode qr.data: {}", e)))?;
+    let modules = code.width() as u32;
+    let span = modules + 2 * QR_QUIET_ZONE_MODULES;
+    let (width, height) = (image.width(), image.height());
+    if qr.size_px < span || qr.size_px > width.min(height) {
+        return Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!(
+                "qr.size_px must be between {} and {} for this data and image",
+                span,
+                width.min(height)
+            ),
+        ));
+    }
+
+    let scale = qr.size_px / span;
+    let margin = (qr.size_px - span * scale) / 2 + QR_QUIET_ZONE_MODULES * scale;
+    let (left, top) = match qr.position {
+        QrPosition::TopLeft => (0, 0),
+        QrPosition::TopRight => (width - qr.size_px, 0),
+        QrPosition::BottomLeft => (0, height - qr.size_px),
+        QrPosition::BottomRight => (width - qr.size_px, height - qr.size_px),
+    };
+
+    let mut canvas = image.to_rgba8();
+    let colors = code.to_colors();
+    for y in 0..qr.size_px {
+        for x in 0..qr.size_px {
+            let dark = x >= margin && y >= margin && {
+                let (col, row) = ((x - margin) / scale, (y - margin) / scale);
+                col < modules && row < modules && colors[(row * modules + col) as usize] == qrcode::Color::Dark
+            };
+            let value = if dark { 0 } else { 255 };
+            canvas.put_pixel(left + x, top + y, Rgba([value, value, value, 255]));
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+/// Extends the canvas by `height_px`, fills the new band with the background
+/// color and draws the caption text centred vertically within it.
+fn add_caption(image: &DynamicImage, caption: &CaptionSpec) -> Result<DynamicImage, ConversionError> {
+    if caption.height_px == 0 || caption.height_px > MAX_CAPTION_HEIGHT_PX {
+        return Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("caption.height_px must be between 1 and {}", MAX_CAPTION_HEIGHT_PX),
+        ));
+    }
+    let background = parse_hex_color(&caption.background)?;
+    let text_color = parse_hex_color(&caption.text_color)?;
+
+    let (width, height) = (image.width(), image.height());
+    let mut canvas = RgbaImage::from_pixel(width, height + caption.height_px, background);
+    image::imageops::overlay(&mut canvas, &image.to_rgba8(), 0, 0);
+
+    // Glyphs take up to 70% of the band height, shrunk further if the text is too wide
+    let padding = (caption.height_px / 10).max(1);
+    let mut scale = ((caption.height_px * 7 / 10) / font::GLYPH_HEIGHT).max(1);
+    while scale > 1 && font::text_width(&caption.text, scale) + 2 * padding > width {
+        scale -= 1;
+    }
+    let text_y = height + (caption.height_px.saturating_sub(font::GLYPH_HEIGHT * scale)) / 2;
+    font::draw_text(&mut canvas, padding, text_y, scale, &caption.text, text_color);
+
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+/// Lays paragraphs out on a white A4 page (96 DPI) with the built-in font,
+/// wrapping at word boundaries. Text that does not fit on the page is dropped.
+fn render_text_page(paragraphs: &[String]) -> DynamicImage {
+    const PAGE_WIDTH: u32 = 794;
+    const PAGE_HEIGHT: u32 = 1123;
+    const MARGIN: u32 = 72;
+    const SCALE: u32 = 2;
+    let line_height = (font::GLYPH_HEIGHT + 3) * SCALE;
+    let max_chars = ((PAGE_WIDTH - 2 * MARGIN) / ((font::GLYPH_WIDTH + 1) * SCALE)) as usize;
+
+    let mut page = RgbaImage::from_pixel(PAGE_WIDTH, PAGE_HEIGHT, Rgba([255, 255, 255, 255]));
+    let mut y = MARGIN;
+    'paragraphs: for paragraph in paragraphs {
+        for line in wrap_text(paragraph, max_chars) {
+            if y + line_height > PAGE_HEIGHT - MARGIN {
+                break 'paragraphs;
+            }
+            font::draw_text(&mut page, MARGIN, y, SCALE, &line, Rgba([0, 0, 0, 255]));
+            y += line_height;
+        }
+        // Blank line between paragraphs
+        y += line_height / 2;
+    }
+    DynamicImage::ImageRgba8(page)
+}
+
+fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word;
+        // Hard-break words longer than a full line
+        while word.chars().count() > max_chars {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            let split = word.char_indices().nth(max_chars).map_or(word.len(), |(i, _)| i);
+            lines.push(word[..split].to_string());
+            word = &word[split..];
+        }
+        if word.is_empty() {
+            continue;
+        }
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn parse_hex_color(value: &str) -> Result<Rgba<u8>, ConversionError> {
+    let hex = value.trim_start_matches('#');
+    let channel = |range: std::ops::Range<usize>| {
+        hex.get(range).and_then(|part| u8::from_str_radix(part, 16).ok())
+    };
+    match (hex.len(), channel(0..2), channel(2..4), channel(4..6)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(Rgba([r, g, b, 255])),
+        _ => Err(ConversionError::new(
+            "INVALID_OPTION",
+            format!("Invalid color '{}', expected #rrggbb", value),
+        )),
+    }
+}
+
+/// Whether the input is already of type `format` and its name's extension
+/// says so, in any spelling: `.JPG` and `.jpeg` both name a JPEG.
+fn named_as_format(file_data: &FileData, format: &str) -> bool {
+    let output_type = mime_type_for(format);
+    same_mime_type(&file_data.mime_type, output_type)
+        && mime_type_for_extension(&file_data.name).is_some_and(|named_type| named_type == output_type)
+}
+
+/// The inputs of a merged output, for reporting them as one.
+fn merged_names(files: &[&FileData]) -> String {
+    files.iter().map(|file| file.name.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+/// Makes a user-supplied name safe to use as a download filename: control
+/// characters and path separators are dropped, and leading dots are trimmed so
+/// the result can't be `..` or a hidden file.
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '/' | '\\'))
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim();
+    if cleaned.is_empty() {
+        "file".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+const FILENAME_PLACEHOLDERS: &[&str] = &["base", "ext", "index", "format"];
+
+/// Checks that every `{...}` in a filename template is a known placeholder.
+fn validate_filename_template(template: &str) -> Result<(), ConversionError> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| {
+            ConversionError::new("INVALID_OPTION", format!("Unclosed '{{' in filename_template '{}'", template))
+        })?;
+        let placeholder = &rest[start + 1..start + end];
+        if !FILENAME_PLACEHOLDERS.contains(&placeholder) {
+            return Err(ConversionError::new(
+                "INVALID_OPTION",
+                format!(
+                    "Unknown placeholder {{{}}} in filename_template, expected one of {}",
+                    placeholder,
+                    FILENAME_PLACEHOLDERS.iter().map(|name| format!("{{{}}}", name)).collect::<Vec<_>>().join(", ")
+                ),
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+fn render_filename_template(template: &str, base_name: &str, index: usize, format: &str) -> String {
+    template
+        .replace("{base}", base_name)
+        .replace("{ext}", extension_for(format))
+        .replace("{index}", &index.to_string())
+        .replace("{format}", format)
+}
+
+/// Rejects options that none of the requested formats (targets and their
+/// fallbacks) would use, such as `interlace` when only JPEG is asked for,
+/// listing every conflict. An option used by at least one of them is fine.
+/// Only the request's own options are checked; a preset's are shared across
+/// requests and apply wherever they fit.
+fn check_format_options(options: &ConversionOptions, formats: &[&String]) -> Result<(), ConversionError> {
+    let applies = |targets: &[&str]| {
+        formats.iter().any(|format| {
+            let auto = format.eq_ignore_ascii_case("AUTO");
+            targets
+                .iter()
+                .any(|target| target.eq_ignore_ascii_case(format) || (auto && AUTO_FORMATS.contains(target)))
+        })
+    };
+    let conflicts: Vec<String> = FORMAT_OPTIONS
+        .iter()
+        .filter(|(_, is_set, targets)| is_set(options) && !applies(targets))
+        .map(|(name, _, targets)| format!("{} only applies to {}", name, targets.join(", ")))
+        .collect();
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let formats: Vec<&str> = formats.iter().map(|format| format.as_str()).collect();
+    Err(ConversionError::new(
+        "INVALID_OPTION_FOR_FORMAT",
+        format!("{}; requested {}", conflicts.join("; "), formats.join(", ")),
+    ))
+}
+
+/// Variant suffixes must be present, distinct and usable in a file name.
+fn validate_variants(variants: &[Variant]) -> Result<(), ConversionError> {
+    let mut seen = HashSet::new();
+    for variant in variants {
+        if variant.suffix.is_empty() || sanitize_filename(&variant.suffix) != variant.suffix {
+            return Err(ConversionError::new(
+                "INVALID_OPTION",
+                format!("Variant suffix '{}' cannot be used in a file name", variant.suffix),
+            ));
+        }
+        if !seen.insert(variant.suffix.as_str()) {
+            return Err(ConversionError::new(
+                "INVALID_OPTION",
+                format!("Variant suffix '{}' is used more than once", variant.suffix),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Narrows the options' dimension limit to the variant's.
+fn with_variant<'a>(options: Cow<'a, ConversionOptions>, variant: Option<&Variant>) -> Cow<'a, ConversionOptions> {
+    let Some(limit) = variant.and_then(|variant| variant.max_dimension) else {
+        return options;
+    };
+    let max_dimension = match options.max_dimension {
+        Some(current) => current.intersect(limit),
+        None => limit,
+    };
+    Cow::Owned(ConversionOptions {
+        max_dimension: Some(max_dimension),
+        ..options.into_owned()
+    })
+}
+
+/// `photo.jpg` with suffix `2x` becomes `photo-2x.jpg`.
+fn with_suffix(name: &str, suffix: &str) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..dot], suffix, &name[dot..]),
+        _ => format!("{}-{}", name, suffix),
+    }
+}
+
+/// Appends `_2`, `_3`, ... before the extension until `name` is not in `used`.
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    if used.insert(name.clone()) {
+        return name;
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name.as_str(), ""),
+    };
+    let mut counter = 2;
+    loop {
+        let candidate = format!("{}_{}{}", stem, counter, extension);
+        if used.insert(candidate.clone()) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Builds a `Content-Disposition` header value for `name`, for callers that
+/// serve converted files over HTTP (e.g. from a service worker). Quotes are
+/// escaped in the ASCII fallback and the exact name is carried percent-encoded
+/// in `filename*`.
+#[wasm_bindgen]
+pub fn content_disposition(name: &str) -> String {
+    let name = sanitize_filename(name);
+    let fallback: String = name
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect::<String>()
+        .replace('"', "\\\"");
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// Sets how much the module logs to the console and in what shape, e.g.
+/// `{"level": "warn"}`, `{"level": "info,document_converter_wasm=debug"}` or
+/// `{"format": "json"}`; unset fields take their defaults, `info` and
+/// `pretty`. Returns `{"success": true}`, or the standard error body for
+/// invalid settings, which leave the current ones in place.
+#[wasm_bindgen]
+pub fn configure_logging(settings_json: &str) -> String {
+    match serde_json::from_str::<logging::LogSettings>(settings_json) {
+        Ok(settings) => {
+            logging::configure(settings);
+            r#"{"success":true}"#.to_string()
+        }
+        Err(e) => {
+            console_warn!("❌ Invalid log settings: {}", e);
+            let code = if e.is_syntax() || e.is_eof() { "INVALID_JSON" } else { "INVALID_OPTION" };
+            error_json(&ConversionError::new(code, format!("Invalid log settings: {}", e)))
+        }
+    }
+}
+
+/// Reports the module version, the commit it was built from and the formats
+/// it can convert between, so clients can detect what a deployed build supports.
+#[wasm_bindgen]
+pub fn version() -> String {
+    let info = VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("GIT_SHA").to_string(),
+        target_formats: TARGET_FORMATS.iter().map(|format| format.to_string()).collect(),
+        input_types: INPUT_TYPES.iter().map(|mime_type| mime_type.to_string()).collect(),
+        features: [("avif", cfg!(feature = "avif")), ("debug", cfg!(feature = "debug"))]
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    };
+    to_json(&info)
+}
+
+/// Input type implied by a file name's extension, for content that has no
+/// magic bytes to sniff.
+fn mime_type_for_extension(name: &str) -> Option<&'static str> {
+    let (_, extension) = name.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "txt" => Some(TEXT_MIME_TYPE),
+        "csv" => Some(CSV_MIME_TYPE),
+        "svg" => Some(SVG_MIME_TYPE),
+        "webp" => Some(WEBP_MIME_TYPE),
+        other => match mime_type_for(other) {
+            "application/octet-stream" => None,
+            mime_type => Some(mime_type),
+        },
+    }
+}
+
+fn mime_type_for(format: &str) -> &'static str {
+    match format.to_uppercase().as_str() {
+        "PDF" => "application/pdf",
+        "JPEG" | "JPG" => "image/jpeg",
+        "PNG" => "image/png",
+        "DOCX" => DOCX_MIME_TYPE,
+        "AVIF" => "image/avif",
+        "TIFF" | "TIF" => "image/tiff",
+        _ => "application/octet-stream",
+    }
+}
+
+/// UUID-shaped id from the idempotency key, the output's position in the
+/// batch, its format and digest. The key keeps different clients' identical
+/// outputs apart, the position identical uploads within one batch.
+fn stable_file_id(seed: &str, ordinal: u32, format: &str, sha256: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::new()
+        .chain_update(seed.as_bytes())
+        .chain_update([0])
+        .chain_update(ordinal.to_le_bytes())
+        .chain_update(format.to_uppercase().as_bytes())
+        .chain_update([0])
+        .chain_update(sha256.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Uuid::from_bytes(bytes).to_string()
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(content).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Conventional file extension for a target format.
+fn extension_for(format: &str) -> &'static str {
+    match format.to_uppercase().as_str() {
+        "PDF" => "pdf",
+        "JPEG" | "JPG" => "jpg",
+        "PNG" => "png",
+        "DOCX" => "docx",
+        "AVIF" => "avif",
+        "TIFF" | "TIF" => "tiff",
+        _ => "bin",
+    }
+}
+
+fn download_limit(file_data: &FileData) -> Option<u32> {
+    match (file_data.single_use, file_data.max_downloads) {
+        (true, _) => Some(1),
+        (false, limit) => limit,
+    }
+}
+
+/// Why an image input already in the target `format` cannot be passed
+/// through as is, or `None` when it can.
+fn reencode_reason(content: &[u8], format: &str, max_size: u64, options: &ConversionOptions) -> Option<&'static str> {
+    if content.len() as u64 > max_size {
+        Some("it is over the size limit")
+    } else if options.strip_metadata == Some(true) {
+        Some("strip_metadata is set")
+    } else if options.requires_reencode() {
+        Some("the options change its pixels")
+    } else if format == "png" && (options.interlace == Some(true) || options.bit_depth.is_some()) {
+        Some("PNG interlacing or bit depth was requested")
+    } else if (options.max_dimension.is_some() || options.max_megapixels.is_some() || options.ensure_orientation.is_some())
+        && image_dimensions(content).is_some_and(|(width, height)| options.reshapes(width, height))
+    {
+        Some("its dimensions or orientation have to change")
+    } else {
+        None
+    }
+}
+
+fn target_dimensions(src_width: u32, src_height: u32, options: &ConversionOptions) -> (u32, u32) {
+    let aspect = src_width as f64 / src_height.max(1) as f64;
+    match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, ((width as f64 / aspect).round() as u32).max(1)),
+        (None, Some(height)) => (((height as f64 * aspect).round() as u32).max(1), height),
+        (None, None) => (src_width, src_height),
+    }
+}
+
+/// The largest size with the aspect of `width` x `height` that stays within
+/// `megapixels` million pixels, or `None` when the image already does.
+/// Rounding down keeps the result under the budget rather than a pixel over.
+fn megapixel_bounds(width: u32, height: u32, megapixels: f32) -> Option<(u32, u32)> {
+    let budget = megapixels as f64 * 1_000_000.0;
+    let pixels = width as f64 * height as f64;
+    if pixels <= budget {
+        return None;
+    }
+    let scale = (budget / pixels).sqrt();
+    let scaled_width = ((width as f64 * scale).floor() as u32).max(1);
+    let scaled_height = ((height as f64 * scale).floor() as u32).max(1);
+    Some((scaled_width, scaled_height))
+}
+
+fn clamp_to_megapixels(image: DynamicImage, megapixels: f32) -> Result<DynamicImage, ConversionError> {
+    if !(megapixels.is_finite() && megapixels > 0.0) {
+        return Err(ConversionError::new("INVALID_OPTION", "max_megapixels must be greater than 0"));
+    }
+    Ok(match megapixel_bounds(image.width(), image.height(), megapixels) {
+        Some((width, height)) => {
+            console_log!(
+                "📐 Clamping {}x{} to {}x{} for a {} MP budget",
+                image.width(),
+                image.height(),
+                width,
+                height,
+                megapixels
+            );
+            image.resize_exact(width, height, FilterType::Lanczos3)
+        }
+        None => image,
+    })
+}
+
+/// Rough bits-per-pixel model for a typical photograph.
+fn bits_per_pixel(format: &str, quality: u8) -> f64 {
+    match format {
+        "JPEG" => {
+            let q = quality.clamp(1, 100) as f64 / 100.0;
+            0.3 + 2.7 * q.powi(3)
+        }
+        // 24-bit RGB after deflate typically lands around half its raw size
+        "PNG" => 12.0,
+        _ => 24.0,
+    }
+}
+
+/// Keys of the request, its files and its options that deserializing would
+/// drop without complaint. The known keys are those of a serialized empty
+/// request, so the list follows the types.
+#[cfg(feature = "debug")]
+fn unknown_request_fields(fields: &serde_json::Value) -> Vec<Warning> {
+    let known_request = serde_json::from_str::<ConvertRequest>(r#"{"exam_type":"","target_formats":[],"max_sizes":{}}"#)
+        .map(|request| serde_json::to_value(request).unwrap_or_default())
+        .unwrap_or_default();
+    let known_file = serde_json::from_str::<FileData>(r#"{"name":"","content":[],"mime_type":"","size":0}"#)
+        .map(|file| serde_json::to_value(file).unwrap_or_default())
+        .unwrap_or_default();
+    let known_options = serde_json::to_value(ConversionOptions::default()).unwrap_or_default();
+
+    let mut warnings = Vec::new();
+    let mut check = |given: &serde_json::Value, known: &serde_json::Value, path: &str| {
+        let Some(given) = given.as_object() else { return };
+        for key in given.keys().filter(|key| known.get(key.as_str()).is_none()) {
+            warnings.push(Warning {
+                code: "UNKNOWN_FIELD".to_string(),
+                message: format!("{}{} is not a request field and is ignored", path, key),
+            });
+        }
+    };
+    check(fields, &known_request, "");
+    check(&fields["options"], &known_options, "options.");
+    for (index, file) in fields["files"].as_array().into_iter().flatten().enumerate() {
+        check(file, &known_file, &format!("files[{}].", index));
+    }
+    warnings
+}
+
+/// Classifies a request that failed to deserialize: `INVALID_JSON` when the
+/// text is not JSON at all, `DECODE_ERROR` for file content that is not valid
+/// base64, `INVALID_REQUEST` when it is JSON of the wrong shape.
+fn request_parse_error(e: &serde_json::Error) -> ConversionError {
+    if e.is_data() && e.to_string().starts_with(BASE64_ERROR_PREFIX) {
+        ConversionError::new("DECODE_ERROR", format!("Could not decode file content: {}", e))
+    } else if e.is_syntax() || e.is_eof() {
+        ConversionError::new("INVALID_JSON", format!("Request is not valid JSON: {}", e))
+    } else {
+        ConversionError::new("INVALID_REQUEST", format!("Invalid request format: {}", e))
+    }
+}
+
+/// Builds a request without files from a URL query string such as
+/// `exam_type=neet&formats=JPEG,PDF`, also returning the file name given as
+/// `name`. `preset`, `quality` and `inline` are read too; size limits come
+/// from the exam config. Unknown parameters are rejected rather than
+/// ignored, so a typo cannot silently drop a setting.
+fn request_from_query(query: &str) -> Result<(ConvertRequest, Option<String>), ConversionError> {
+    let invalid = |message: String| ConversionError::new("INVALID_REQUEST", message);
+    let mut fields = serde_json::Map::new();
+    let mut options = serde_json::Map::new();
+    let mut name = None;
+    for pair in query.trim_start_matches('?').split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let (key, value) = (percent_decode(key)?, percent_decode(value)?);
+        match key.as_str() {
+            "exam_type" | "preset" => {
+                fields.insert(key, serde_json::Value::String(value));
+            }
+            "formats" => {
+                let formats: Vec<&str> = value.split(',').map(str::trim).filter(|format| !format.is_empty()).collect();
+                fields.insert("target_formats".to_string(), formats.into());
+            }
+            "inline" => {
+                let inline = value
+                    .parse::<bool>()
+                    .map_err(|_| invalid(format!("inline must be true or false, got {:?}", value)))?;
+                fields.insert(key, inline.into());
+            }
+            "quality" => {
+                let quality = value
+                    .parse::<u8>()
+                    .map_err(|_| invalid(format!("quality must be a number from 1 to 100, got {:?}", value)))?;
+                options.insert(key, quality.into());
+            }
+            "name" => name = Some(value),
+            _ => return Err(invalid(format!("Unknown query parameter {:?}", key))),
+        }
+    }
+    for required in ["exam_type", "target_formats"] {
+        if !fields.contains_key(required) {
+            let parameter = if required == "target_formats" { "formats" } else { required };
+            return Err(invalid(format!("Query is missing {}", parameter)));
+        }
+    }
+    fields.insert("max_sizes".to_string(), serde_json::Value::Object(serde_json::Map::new()));
+    fields.insert("options".to_string(), serde_json::Value::Object(options));
+    let request = serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| request_parse_error(&e))?;
+    Ok((request, name))
+}
+
+/// Decodes `%XX` escapes and `+` as a space, as in form-encoded query strings.
+fn percent_decode(value: &str) -> Result<String, ConversionError> {
+    let invalid = || ConversionError::new("INVALID_REQUEST", format!("Malformed query string near {:?}", value));
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes.get(index + 1..index + 3).ok_or_else(invalid)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(invalid());
+                }
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                index += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// Body of every failed export that returns JSON.
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    success: bool,
+    error: &'a ConversionError,
+}
+
+fn error_json(error: &ConversionError) -> String {
+    serde_json::to_string(&ErrorResponse { success: false, error }).unwrap_or_else(|_| {
+        r#"{"success": false, "error": {"code": "SERIALIZATION_ERROR", "message": "Could not serialize the error"}}"#
+            .to_string()
+    })
+}
+
+/// `value` as JSON, or the standard error body if it cannot be serialized.
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_else(|e| error_json(&ConversionError::new("SERIALIZATION_ERROR", format!("Serialization error: {}", e))))
+}
+
+fn response_json(response: &ConvertResponse) -> String {
+    to_json(response)
+}
+
+/// A JSON error body as a JS exception, for exports returning bytes.
+fn error_value(error: &ConversionError) -> JsValue {
+    JsValue::from_str(&error_json(error))
+}
+
+// WASM exports
+#[wasm_bindgen]
+pub struct WasmDocumentConverter {
+    converter: DocumentConverter,
+}
+
+impl Default for WasmDocumentConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmDocumentConverter {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmDocumentConverter {
+        console_log!("🦀 Initializing Rust WASM Document Converter");
+        WasmDocumentConverter {
+            converter: DocumentConverter::new(),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn convert_documents(&mut self, request_json: &str) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => {
+                match self.converter.convert_documents(&request) {
+                    Ok(response) => response_json(&response),
+                    Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+                }
+            }
+            Err(e) => response_json(&ConvertResponse::failure(request_parse_error(&e))),
+        }
+    }
+
+    /// Calls `on_output` with one JSON-encoded `ConvertedFile` per finished
+    /// output, then returns the usual `ConvertResponse` as the summary.
+    #[wasm_bindgen]
+    pub fn convert_documents_streaming(&mut self, request_json: &str, on_output: &js_sys::Function) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => {
+                let result = self.converter.convert_documents_with(&request, |converted| {
+                    if let Ok(line) = serde_json::to_string(converted) {
+                        if let Err(e) = on_output.call1(&JsValue::NULL, &JsValue::from_str(&line)) {
+                            console_warn!("❌ Output callback failed: {:?}", e);
+                        }
+                    }
+                });
+                match result {
+                    Ok(response) => response_json(&response),
+                    Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+                }
+            }
+            Err(e) => response_json(&ConvertResponse::failure(request_parse_error(&e))),
+        }
+    }
+
+    /// Converts a single file sent as raw bytes, for callers that would
+    /// rather not build the JSON envelope: settings come from `query`
+    /// (`exam_type=neet&formats=JPEG,PDF`) and the input type from
+    /// `content_type`. `convert_documents` remains the full interface.
+    #[wasm_bindgen]
+    pub fn convert_file(&mut self, content: Vec<u8>, content_type: &str, query: &str) -> String {
+        match self.converter.convert_file(content, content_type, query) {
+            Ok(response) => response_json(&response),
+            Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+        }
+    }
+
+    /// Converts every supported file in a ZIP archive; `request_json` is a
+    /// normal request whose `files` may be left out.
+    #[wasm_bindgen]
+    pub fn convert_zip(&mut self, zip: &[u8], request_json: &str) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => match self.converter.convert_zip(zip, request) {
+                Ok(response) => response_json(&response),
+                Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+            },
+            Err(e) => response_json(&ConvertResponse::failure(request_parse_error(&e))),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn convert_zip_for_exam(&mut self, zip: &[u8], request_json: &str) -> Result<Vec<u8>, JsValue> {
+        let request = serde_json::from_str::<ConvertRequest>(request_json).map_err(|e| request_parse_error(&e));
+        request
+            .and_then(|request| self.converter.convert_zip_for_exam(zip, request))
+            .map_err(|e| error_value(&e))
+    }
+
+    /// Runs a retained original (see `retain_source`) through a new request.
+    #[wasm_bindgen]
+    pub fn reconvert(&mut self, source_id: &str, request_json: &str) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => match self.converter.reconvert(source_id, request) {
+                Ok(response) => response_json(&response),
+                Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+            },
+            Err(e) => response_json(&ConvertResponse::failure(request_parse_error(&e))),
+        }
+    }
+
+    /// Converts a stored output (by the id in its `download_url`) again;
+    /// `request_json` is a normal request whose `files` may be left out.
+    #[wasm_bindgen]
+    pub fn reconvert_output(&mut self, file_id: &str, request_json: &str) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => match self.converter.reconvert_output(file_id, request) {
+                Ok(response) => response_json(&response),
+                Err(e) => response_json(&ConvertResponse::failure(ConversionError::new("CONVERSION_FAILED", e))),
+            },
+            Err(e) => response_json(&ConvertResponse::failure(request_parse_error(&e))),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn inspect_document(&self, file_json: &str) -> String {
+        match serde_json::from_str::<FileData>(file_json) {
+            Ok(file_data) => to_json(&self.converter.inspect(&file_data)),
+            Err(e) => error_json(&request_parse_error(&e)),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn validate_conversion(&self, request_json: &str) -> String {
+        match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => to_json(&self.converter.validate_conversion(&request)),
+            Err(e) => error_json(&request_parse_error(&e)),
+        }
+    }
+
+    /// Debug builds only: how a request would be read, without converting.
+    #[cfg(feature = "debug")]
+    #[wasm_bindgen]
+    pub fn echo_request(&self, request_json: &str) -> String {
+        let request = match serde_json::from_str::<ConvertRequest>(request_json) {
+            Ok(request) => request,
+            Err(e) => return error_json(&request_parse_error(&e)),
+        };
+        let fields = serde_json::from_str(request_json).unwrap_or_default();
+        to_json(&self.converter.echo_request(&request, &fields))
+    }
+
+    #[wasm_bindgen]
+    pub fn load_exam_configs(&mut self, configs_json: &str) -> bool {
+        match serde_json::from_str::<HashMap<String, ExamConfig>>(configs_json) {
+            Ok(configs) => {
+                console_log!("📋 Loaded {} exam configs", configs.len());
+                self.converter.set_exam_configs(configs);
+                true
+            }
+            Err(e) => {
+                console_warn!("❌ Invalid exam configs: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Lists problems in an exam config map without loading it; an empty
+    /// array means the configs are fine.
+    #[wasm_bindgen]
+    pub fn validate_exam_configs(&self, configs_json: &str) -> String {
+        let problems = match serde_json::from_str::<HashMap<String, ExamConfig>>(configs_json) {
+            Ok(configs) => self.converter.lint_exam_configs(&configs),
+            Err(e) => vec![ConfigProblem::new(None, "INVALID_CONFIG", format!("Invalid exam configs: {}", e))],
+        };
+        to_json(&problems)
+    }
+
+    #[wasm_bindgen]
+    pub fn download_file(&mut self, file_id: &str) -> Result<Vec<u8>, JsValue> {
+        self.converter.download(file_id).map_err(|e| error_value(&e))
+    }
+
+    #[wasm_bindgen]
+    pub fn download_disposition(&self, file_id: &str, filename: Option<String>) -> Result<String, JsValue> {
+        self.converter
+            .download_disposition(file_id, filename.as_deref())
+            .map_err(|e| error_value(&e))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_input_policy(&mut self, policy_json: &str) -> bool {
+        match serde_json::from_str::<InputPolicy>(policy_json) {
+            Ok(policy) => {
+                self.converter.set_input_policy(policy);
+                true
+            }
+            Err(e) => {
+                console_warn!("❌ Invalid input policy: {}", e);
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_total_bytes(&mut self, bytes: f64) {
+        self.converter.batch_limits_mut().max_total_bytes = bytes as u64;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_file_count(&mut self, count: u32) {
+        self.converter.batch_limits_mut().max_file_count = count as usize;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_file_bytes(&mut self, bytes: f64) {
+        self.converter.batch_limits_mut().max_file_bytes = bytes as u64;
+    }
+
+    #[wasm_bindgen]
+    pub fn file_metadata(&self, file_id: &str) -> String {
+        match self.converter.metadata(file_id) {
+            Ok(metadata) => to_json(&metadata),
+            Err(e) => error_json(&e),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn optimize_image(&mut self, request_json: &str) -> String {
+        match serde_json::from_str::<OptimizeRequest>(request_json) {
+            Ok(request) => match self.converter.optimize(&request) {
+                Ok(optimized) => to_json(&optimized),
+                Err(e) => error_json(&e),
+            },
+            Err(e) => error_json(&request_parse_error(&e)),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_pixels(&mut self, max_pixels: f64) {
+        self.converter.set_max_pixels(max_pixels as u64);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_storage_limit(&mut self, limit_json: &str) -> bool {
+        match serde_json::from_str::<StorageLimit>(limit_json) {
+            Ok(limit) => {
+                self.converter.set_storage_limit(limit);
+                true
+            }
+            Err(e) => {
+                console_warn!("❌ Invalid storage limit: {}", e);
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_render_cache_limits(&mut self, limits_json: &str) -> bool {
+        match serde_json::from_str::<render_cache::RenderCacheLimits>(limits_json) {
+            Ok(limits) => {
+                self.converter.set_render_cache_limits(limits);
+                true
+            }
+            Err(e) => {
+                console_warn!("❌ Invalid render cache limits: {}", e);
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_storage_backend(&mut self, backend_json: &str) -> bool {
+        let result = serde_json::from_str::<StorageBackend>(backend_json)
+            .map_err(|e| e.to_string())
+            .and_then(|backend| self.converter.set_storage_backend(backend).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                console_warn!("❌ Invalid storage backend: {}", e);
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_enforce_exam_limits(&mut self, enforce: bool) {
+        self.converter.set_enforce_exam_limits(enforce);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_reject_mime_mismatch(&mut self, reject: bool) {
+        self.converter.set_reject_mime_mismatch(reject);
+    }
+
+    /// Selects the JPEG encoder: "image" (the default) or "jpeg_encoder".
+    /// Returns false and keeps the current one for an unknown name.
+    #[wasm_bindgen]
+    pub fn set_jpeg_backend(&mut self, name: &str) -> bool {
+        match JpegBackend::from_name(name) {
+            Some(backend) => {
+                self.converter.set_jpeg_backend(backend);
+                true
+            }
+            None => {
+                console_warn!("❌ Unknown JPEG backend: {}", name);
+                false
+            }
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_storage_ttl_secs(&mut self, ttl_secs: u32) {
+        self.converter.set_storage_ttl_ms(ttl_secs as f64 * 1000.0);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_conversion_timeout_secs(&mut self, timeout_secs: u32) {
+        self.converter.set_conversion_timeout_secs(timeout_secs as f64);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.converter.set_max_attempts(attempts);
+    }
+
+    #[wasm_bindgen]
+    pub fn cleanup_storage(&mut self) -> String {
+        to_json(&self.converter.cleanup_expired())
+    }
+
+    #[wasm_bindgen]
+    pub fn purge_storage(&mut self) -> String {
+        to_json(&self.converter.purge_all())
+    }
+
+    #[wasm_bindgen]
+    pub fn effective_config(&self) -> String {
+        to_json(&self.converter.effective_config())
+    }
+
+    #[wasm_bindgen]
+    pub fn health(&self, deep: bool) -> String {
+        to_json(&self.converter.health(deep))
+    }
+
+    #[wasm_bindgen]
+    pub fn stats(&self) -> String {
+        to_json(&self.converter.stats())
     }
 }
 
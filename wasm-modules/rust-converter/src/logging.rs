@@ -0,0 +1,159 @@
+// Settings for the converter's console output. Everything is logged through
+// the `console_log!` family of macros, which have no converter at hand, so the
+// level, the line format and the id of the request being converted are kept
+// here per thread; the WASM module only ever runs on one.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::cell::RefCell;
+
+/// How severe a line is, and the most verbose level to log. Ordered from
+/// quietest to most verbose.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Nothing is logged
+    Off,
+    /// Failures of the module itself, such as storage that cannot be written
+    Error,
+    /// Requests or files that were rejected or could not be converted
+    Warn,
+    #[default]
+    Info,
+    /// Each encoding step
+    Debug,
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    fn parse(name: &str) -> Option<LogLevel> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            // The module has nothing finer than debug
+            "debug" | "trace" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// The crate name as it appears in `RUST_LOG` targets.
+const TARGET: &str = "document_converter_wasm";
+
+/// Reads a level in `RUST_LOG` syntax: comma-separated directives, each a
+/// bare level or `target=level`. A directive for this crate (or one of its
+/// modules) wins over a bare level; other crates' directives are ignored, so
+/// a server's `RUST_LOG` value can be passed through as is.
+pub fn parse_directives(directives: &str) -> Result<LogLevel, String> {
+    let (mut default, mut ours) = (None, None);
+    for directive in directives.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        let (target, level) = match directive.split_once('=') {
+            Some((target, level)) => (Some(target.trim()), level),
+            None => (None, directive),
+        };
+        let level = LogLevel::parse(level).ok_or_else(|| {
+            format!("unknown log level {:?} (expected off, error, warn, info, debug or trace)", level.trim())
+        })?;
+        match target {
+            None => default = Some(level),
+            Some(target) if target == TARGET || target.starts_with(&format!("{}::", TARGET)) => ours = Some(level),
+            Some(_) => {}
+        }
+    }
+    Ok(ours.or(default).unwrap_or_default())
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LogLevel, D::Error> {
+    parse_directives(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// The message as is, prefixed with `[request id]` during a conversion
+    #[default]
+    Pretty,
+    /// One JSON object per line: `level`, `message` and `request_id`
+    Json,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+pub struct LogSettings {
+    /// `RUST_LOG`-style, see `parse_directives`
+    #[serde(default, deserialize_with = "deserialize_level")]
+    level: LogLevel,
+    #[serde(default)]
+    format: LogFormat,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    level: &'static str,
+    message: &'a str,
+    request_id: Option<&'a str>,
+}
+
+thread_local! {
+    static SETTINGS: RefCell<LogSettings> = RefCell::new(LogSettings::default());
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn configure(settings: LogSettings) {
+    SETTINGS.with(|current| *current.borrow_mut() = settings);
+}
+
+/// Whether lines at `level` are logged with the current settings.
+pub fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level <= SETTINGS.with(|current| current.borrow().level)
+}
+
+/// The line to log for `message` at `level`, or `None` when the configured
+/// level filters it out.
+pub fn format_line(level: LogLevel, message: &str) -> Option<String> {
+    if !enabled(level) {
+        return None;
+    }
+    let format = SETTINGS.with(|current| current.borrow().format);
+    REQUEST_ID.with(|request_id| {
+        let request_id = request_id.borrow();
+        Some(match (format, request_id.as_deref()) {
+            (LogFormat::Pretty, Some(id)) => format!("[{}] {}", id, message),
+            (LogFormat::Pretty, None) => message.to_string(),
+            (LogFormat::Json, request_id) => serde_json::to_string(&JsonLine {
+                level: level.name(),
+                message,
+                request_id,
+            })
+            .unwrap_or_default(),
+        })
+    })
+}
+
+/// Tags every line logged while it is alive with `request_id`, restoring
+/// the previous id when dropped so nested conversions unwind cleanly.
+pub struct RequestSpan {
+    previous: Option<String>,
+}
+
+impl RequestSpan {
+    pub fn enter(request_id: String) -> Self {
+        let previous = REQUEST_ID.with(|current| current.borrow_mut().replace(request_id));
+        RequestSpan { previous }
+    }
+}
+
+impl Drop for RequestSpan {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        REQUEST_ID.with(|current| *current.borrow_mut() = previous);
+    }
+}
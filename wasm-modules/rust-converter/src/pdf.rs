@@ -0,0 +1,962 @@
+// Lightweight PDF helpers. Inspection scans the raw object data rather than
+// building a full document model, which is enough to tell scanned documents
+// apart from ones with a real text layer and to pull out text set in simple
+// fonts. Writing only covers what the converter produces: A4 pages of
+// embedded JPEG images. Recompressing the images inside an existing PDF and
+// reading out the images a scanned page is made of need the full object
+// model, which comes from lopdf.
+
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Upper bound on how much a single content stream may inflate to while
+/// scanning, so a malicious stream cannot exhaust memory.
+const MAX_INFLATED_STREAM_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Returns true when any content stream contains a text object (`BT` ... `ET`)
+/// that shows text with `Tj`/`TJ`. Image-only scans only paint XObjects.
+pub fn has_text_layer(content: &[u8]) -> bool {
+    streams(content).any(|stream| contains_text_operators(&stream))
+}
+
+/// Counts page objects (`/Type /Page`, not `/Pages`).
+pub fn page_count(content: &[u8]) -> usize {
+    let mut count = 0;
+    let mut rest = content;
+    while let Some(index) = find(rest, b"/Type") {
+        rest = &rest[index + b"/Type".len()..];
+        let trimmed = skip_whitespace(rest);
+        if trimmed.starts_with(b"/Page") && !trimmed.starts_with(b"/Pages") {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Iterates over every stream body, inflating FlateDecode streams.
+fn streams(content: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let mut rest = content;
+    std::iter::from_fn(move || loop {
+        let start = find(rest, b"stream")?;
+        // Skip the `endstream` keyword, which also contains `stream`
+        if start >= 3 && &rest[start - 3..start] == b"end" {
+            rest = &rest[start + b"stream".len()..];
+            continue;
+        }
+        let dictionary = &rest[..start];
+        let mut body_start = start + b"stream".len();
+        if rest.get(body_start) == Some(&b'\r') {
+            body_start += 1;
+        }
+        if rest.get(body_start) == Some(&b'\n') {
+            body_start += 1;
+        }
+        let body_len = find(&rest[body_start..], b"endstream")?;
+        let body = &rest[body_start..body_start + body_len];
+        rest = &rest[body_start + body_len + b"endstream".len()..];
+
+        // Only the dictionary directly preceding the stream keyword matters
+        let dictionary = match find_last(dictionary, b"<<") {
+            Some(index) => &dictionary[index..],
+            None => dictionary,
+        };
+        if find(dictionary, b"/FlateDecode").is_some() {
+            let mut inflated = Vec::new();
+            if ZlibDecoder::new(body)
+                .take(MAX_INFLATED_STREAM_BYTES)
+                .read_to_end(&mut inflated)
+                .is_ok()
+            {
+                return Some(inflated);
+            }
+            continue;
+        }
+        return Some(body.to_vec());
+    })
+}
+
+fn contains_text_operators(stream: &[u8]) -> bool {
+    let tokens: Vec<&[u8]> = stream
+        .split(|b| b.is_ascii_whitespace() || matches!(b, b'[' | b']' | b'(' | b')'))
+        .filter(|token| !token.is_empty())
+        .collect();
+    let mut in_text = false;
+    for token in tokens {
+        match token {
+            b"BT" => in_text = true,
+            b"ET" => in_text = false,
+            b"Tj" | b"TJ" | b"'" | b"\"" if in_text => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Text shown in the content streams, one entry per line of the page, in
+/// stream order. Strings that do not decode to printable text, such as
+/// glyph ids in CID fonts, are left out; positioning is reduced to line
+/// breaks and word gaps.
+pub fn extract_lines(content: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for stream in streams(content).filter(|stream| contains_text_operators(stream)) {
+        let mut current = String::new();
+        let mut operands: Vec<Operand> = Vec::new();
+        let mut in_text = false;
+        let mut tokens = Tokens { rest: &stream };
+        while let Some(token) = tokens.next_token() {
+            let operator = match token {
+                Token::Operand(operand) => {
+                    operands.push(operand);
+                    continue;
+                }
+                Token::Operator(operator) => operator,
+            };
+            let numbers: Vec<f32> = operands
+                .iter()
+                .filter_map(|operand| match operand {
+                    Operand::Number(number) => Some(*number),
+                    _ => None,
+                })
+                .collect();
+            match operator {
+                b"BT" => in_text = true,
+                b"ET" => {
+                    in_text = false;
+                    end_line(&mut current, &mut lines);
+                }
+                b"Td" | b"TD" if in_text => match numbers.as_slice() {
+                    [_, ty] if *ty != 0.0 => end_line(&mut current, &mut lines),
+                    [tx, _] if *tx > 0.0 => push_gap(&mut current),
+                    _ => {}
+                },
+                b"T*" | b"Tm" if in_text => end_line(&mut current, &mut lines),
+                b"Tj" | b"TJ" | b"'" | b"\"" if in_text => {
+                    if matches!(operator, b"'" | b"\"") {
+                        end_line(&mut current, &mut lines);
+                    }
+                    if let Some(operand) = operands.last() {
+                        show(operand, &mut current);
+                    }
+                }
+                // Inline image data is binary and must not be read as operators
+                b"ID" => tokens.skip_inline_image(),
+                _ => {}
+            }
+            operands.clear();
+        }
+        end_line(&mut current, &mut lines);
+    }
+    lines
+}
+
+// A TJ adjustment this far left (in thousandths of an em) reads as a word gap
+const TJ_WORD_GAP: f32 = -200.0;
+
+fn show(operand: &Operand, line: &mut String) {
+    match operand {
+        Operand::String(bytes) => {
+            if let Some(text) = decode_text(bytes) {
+                line.push_str(&text);
+            }
+        }
+        Operand::Number(adjustment) if *adjustment < TJ_WORD_GAP => push_gap(line),
+        Operand::Array(elements) => elements.iter().for_each(|element| show(element, line)),
+        _ => {}
+    }
+}
+
+fn push_gap(line: &mut String) {
+    if !line.is_empty() && !line.ends_with(' ') {
+        line.push(' ');
+    }
+}
+
+fn end_line(line: &mut String, lines: &mut Vec<String>) {
+    let text = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        lines.push(text);
+    }
+    line.clear();
+}
+
+/// UTF-16BE with a byte order mark, otherwise one byte per character as in
+/// PDFDocEncoding, which matches Latin-1 for printable text.
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    let text: String = match bytes {
+        [0xfe, 0xff, rest @ ..] => {
+            let units: Vec<u16> = rest.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => bytes.iter().map(|&b| b as char).collect(),
+    };
+    if text.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+enum Operand {
+    Number(f32),
+    String(Vec<u8>),
+    Array(Vec<Operand>),
+    /// Names, dictionaries and anything else text extraction ignores
+    Other,
+}
+
+enum Token<'a> {
+    Operand(Operand),
+    Operator(&'a [u8]),
+}
+
+/// Splits a content stream into operands and operators.
+struct Tokens<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Tokens<'a> {
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        loop {
+            self.rest = skip_whitespace(self.rest);
+            let &first = self.rest.first()?;
+            match first {
+                b'%' => {
+                    let end = self.rest.iter().position(|&b| b == b'\n' || b == b'\r').unwrap_or(self.rest.len());
+                    self.rest = &self.rest[end..];
+                }
+                b'(' => return Some(Token::Operand(Operand::String(self.literal_string()))),
+                b'<' if self.rest.get(1) == Some(&b'<') => {
+                    self.rest = &self.rest[2..];
+                    return Some(Token::Operand(Operand::Other));
+                }
+                b'<' => return Some(Token::Operand(Operand::String(self.hex_string()))),
+                b'>' => {
+                    self.rest = &self.rest[1..];
+                    return Some(Token::Operand(Operand::Other));
+                }
+                b'[' => {
+                    self.rest = &self.rest[1..];
+                    return Some(Token::Operand(Operand::Array(self.array())));
+                }
+                b']' => {
+                    // Unbalanced; the array that owned it was never opened
+                    self.rest = &self.rest[1..];
+                }
+                b'/' => {
+                    self.rest = &self.rest[1..];
+                    self.regular_run();
+                    return Some(Token::Operand(Operand::Other));
+                }
+                b'+' | b'-' | b'.' | b'0'..=b'9' => {
+                    let run = self.regular_run();
+                    let number = std::str::from_utf8(run).ok().and_then(|run| run.parse().ok());
+                    return Some(Token::Operand(number.map_or(Operand::Other, Operand::Number)));
+                }
+                _ => {
+                    let run = self.regular_run();
+                    if run.is_empty() {
+                        // A stray delimiter such as `)` or `{`
+                        self.rest = &self.rest[1..];
+                        continue;
+                    }
+                    return Some(Token::Operator(run));
+                }
+            }
+        }
+    }
+
+    fn regular_run(&mut self) -> &'a [u8] {
+        let end = self
+            .rest
+            .iter()
+            .position(|&b| b.is_ascii_whitespace() || b"()<>[]{}/%".contains(&b))
+            .unwrap_or(self.rest.len());
+        let (run, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        run
+    }
+
+    fn array(&mut self) -> Vec<Operand> {
+        let mut elements = Vec::new();
+        loop {
+            self.rest = skip_whitespace(self.rest);
+            match self.rest.first() {
+                None => return elements,
+                Some(b']') => {
+                    self.rest = &self.rest[1..];
+                    return elements;
+                }
+                _ => match self.next_token() {
+                    Some(Token::Operand(operand)) => elements.push(operand),
+                    Some(Token::Operator(_)) => {}
+                    None => return elements,
+                },
+            }
+        }
+    }
+
+    /// Reads a `( ... )` string with its escapes and balanced parentheses.
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut depth = 0;
+        let mut index = 1;
+        while let Some(&b) = self.rest.get(index) {
+            index += 1;
+            match b {
+                b'\\' => {
+                    let escaped = match self.rest.get(index) {
+                        Some(&escaped) => escaped,
+                        None => break,
+                    };
+                    index += 1;
+                    match escaped {
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'0'..=b'7' => {
+                            let mut value = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.rest.get(index) {
+                                    Some(&digit @ b'0'..=b'7') => {
+                                        value = value * 8 + (digit - b'0') as u32;
+                                        index += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            bytes.push(value as u8);
+                        }
+                        // A backslash at the end of a line continues the string
+                        b'\r' => {
+                            if self.rest.get(index) == Some(&b'\n') {
+                                index += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        other => bytes.push(other),
+                    }
+                }
+                b'(' => {
+                    depth += 1;
+                    bytes.push(b);
+                }
+                b')' if depth == 0 => break,
+                b')' => {
+                    depth -= 1;
+                    bytes.push(b);
+                }
+                _ => bytes.push(b),
+            }
+        }
+        self.rest = &self.rest[index.min(self.rest.len())..];
+        bytes
+    }
+
+    /// Reads a `< ... >` string; an odd final digit is padded with 0.
+    fn hex_string(&mut self) -> Vec<u8> {
+        let end = self.rest.iter().position(|&b| b == b'>').unwrap_or(self.rest.len());
+        let digits: Vec<u8> = self.rest[1..end]
+            .iter()
+            .filter_map(|&b| (b as char).to_digit(16).map(|digit| digit as u8))
+            .collect();
+        self.rest = &self.rest[(end + 1).min(self.rest.len())..];
+        digits
+            .chunks(2)
+            .map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0))
+            .collect()
+    }
+
+    /// Skips the binary data of an inline image up to its `EI` operator.
+    fn skip_inline_image(&mut self) {
+        let mut offset = 0;
+        while let Some(index) = find(&self.rest[offset..], b"EI") {
+            let end = offset + index + 2;
+            let delimited_before = offset + index == 0 || self.rest[offset + index - 1].is_ascii_whitespace();
+            let delimited_after = !matches!(self.rest.get(end), Some(b) if !b.is_ascii_whitespace());
+            if delimited_before && delimited_after {
+                self.rest = &self.rest[end..];
+                return;
+            }
+            offset = end;
+        }
+        self.rest = &[];
+    }
+}
+
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn find_last(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
+
+/// A4 in PDF points (1/72 inch).
+pub const A4_WIDTH_PT: f32 = 595.0;
+pub const A4_HEIGHT_PT: f32 = 842.0;
+const PAGE_MARGIN_PT: f32 = 36.0;
+const GRID_GAP_PT: f32 = 12.0;
+
+/// A JPEG drawn on a page. Coordinates are in points from the bottom-left
+/// corner, as PDF expects.
+pub struct PlacedImage {
+    pub jpeg: Vec<u8>,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+pub struct Page {
+    pub images: Vec<PlacedImage>,
+}
+
+/// Entries for the document information dictionary. Unset or empty fields
+/// are left out, and a document without any gets no dictionary at all.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub keywords: Option<String>,
+}
+
+impl Metadata {
+    fn info_dictionary(&self) -> Option<String> {
+        let entries: Vec<String> = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| match value.as_deref() {
+            Some(value) if !value.is_empty() => Some(format!("/{} {}", key, text_string(value))),
+            _ => None,
+        })
+        .collect();
+        if entries.is_empty() {
+            None
+        } else {
+            Some(format!("<< {} >>", entries.join(" ")))
+        }
+    }
+}
+
+/// Encodes a PDF text string: a literal string for printable ASCII, UTF-16BE
+/// with a byte order mark in hex otherwise, as PDFDocEncoding covers little
+/// beyond ASCII.
+fn text_string(value: &str) -> String {
+    if value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        let mut literal = String::from("(");
+        for c in value.chars() {
+            if matches!(c, '(' | ')' | '\\') {
+                literal.push('\\');
+            }
+            literal.push(c);
+        }
+        literal.push(')');
+        return literal;
+    }
+    let mut hex = String::from("<FEFF");
+    for unit in value.encode_utf16() {
+        hex.push_str(&format!("{:04X}", unit));
+    }
+    hex.push('>');
+    hex
+}
+
+/// Position of one image on a page, in points.
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub page: usize,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Lays images out on A4 pages, `cols` x `rows` per page, filling rows left
+/// to right and top to bottom. Each image keeps its aspect ratio and is
+/// centred in its cell.
+pub fn layout_grid(dimensions: &[(u32, u32)], cols: u32, rows: u32) -> Vec<Placement> {
+    let per_page = (cols * rows) as usize;
+    let cell_width = (A4_WIDTH_PT - 2.0 * PAGE_MARGIN_PT - (cols - 1) as f32 * GRID_GAP_PT) / cols as f32;
+    let cell_height = (A4_HEIGHT_PT - 2.0 * PAGE_MARGIN_PT - (rows - 1) as f32 * GRID_GAP_PT) / rows as f32;
+
+    dimensions
+        .iter()
+        .enumerate()
+        .map(|(index, &(pixel_width, pixel_height))| {
+            let slot = index % per_page;
+            let (col, row) = ((slot as u32 % cols) as f32, (slot as u32 / cols) as f32);
+            let scale = (cell_width / pixel_width.max(1) as f32).min(cell_height / pixel_height.max(1) as f32);
+            let (width, height) = (pixel_width as f32 * scale, pixel_height as f32 * scale);
+
+            let cell_x = PAGE_MARGIN_PT + col * (cell_width + GRID_GAP_PT);
+            // Rows are counted from the top, PDF y from the bottom
+            let cell_top = A4_HEIGHT_PT - PAGE_MARGIN_PT - row * (cell_height + GRID_GAP_PT);
+            Placement {
+                page: index / per_page,
+                x: cell_x + (cell_width - width) / 2.0,
+                y: cell_top - cell_height + (cell_height - height) / 2.0,
+                width,
+                height,
+            }
+        })
+        .collect()
+}
+
+/// Serializes A4 pages of JPEG images into a PDF document.
+pub fn write_document(pages: &[Page], metadata: &Metadata) -> Vec<u8> {
+    let mut writer = ObjectWriter::new();
+    // Objects 1 and 2 are the catalog and page tree; pages follow
+    let mut page_ids = Vec::new();
+    let mut next_id = 3;
+    let mut bodies = Vec::new();
+
+    for page in pages {
+        let page_id = next_id;
+        let contents_id = next_id + 1;
+        let first_image_id = next_id + 2;
+        next_id += 2 + page.images.len();
+        page_ids.push(page_id);
+
+        let mut resources = String::new();
+        let mut content = String::new();
+        for (index, image) in page.images.iter().enumerate() {
+            resources.push_str(&format!("/Im{} {} 0 R ", index, first_image_id + index));
+            content.push_str(&format!(
+                "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im{} Do Q\n",
+                image.width, image.height, image.x, image.y, index
+            ));
+        }
+
+        bodies.push((
+            page_id,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << {}>> >> /Contents {} 0 R >>",
+                A4_WIDTH_PT, A4_HEIGHT_PT, resources, contents_id
+            )
+            .into_bytes(),
+        ));
+        bodies.push((contents_id, stream_object("", content.as_bytes())));
+        for (index, image) in page.images.iter().enumerate() {
+            let dictionary = format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode",
+                image.pixel_width, image.pixel_height
+            );
+            bodies.push((first_image_id + index, stream_object(&dictionary, &image.jpeg)));
+        }
+    }
+
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    writer.object(1, b"<< /Type /Catalog /Pages 2 0 R >>");
+    writer.object(
+        2,
+        format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), page_ids.len()).as_bytes(),
+    );
+    for (id, body) in &bodies {
+        writer.object(*id, body);
+    }
+    let info_id = metadata.info_dictionary().map(|dictionary| {
+        writer.object(next_id, dictionary.as_bytes());
+        next_id
+    });
+    writer.finish(1, info_id)
+}
+
+fn stream_object(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("<< {} /Length {} >>\nstream\n", dictionary, data.len()).into_bytes();
+    object.extend_from_slice(data);
+    object.extend_from_slice(b"\nendstream");
+    object
+}
+
+/// Writes numbered objects and the cross-reference table that locates them.
+struct ObjectWriter {
+    buffer: Vec<u8>,
+    offsets: Vec<(usize, usize)>,
+}
+
+impl ObjectWriter {
+    fn new() -> Self {
+        // The binary comment marks the file as binary for transfer tools
+        Self {
+            buffer: b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec(),
+            offsets: Vec::new(),
+        }
+    }
+
+    fn object(&mut self, id: usize, body: &[u8]) {
+        self.offsets.push((id, self.buffer.len()));
+        self.buffer.extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+        self.buffer.extend_from_slice(body);
+        self.buffer.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn finish(mut self, root_id: usize, info_id: Option<usize>) -> Vec<u8> {
+        self.offsets.sort_unstable();
+        let size = self.offsets.last().map_or(0, |(id, _)| id + 1);
+        let xref_offset = self.buffer.len();
+
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", size);
+        let mut expected = 1;
+        for (id, offset) in &self.offsets {
+            // Unused ids would break the contiguous table; mark them free
+            while expected < *id {
+                xref.push_str("0000000000 65535 f \n");
+                expected += 1;
+            }
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+            expected += 1;
+        }
+        let info = info_id.map(|id| format!(" /Info {} 0 R", id)).unwrap_or_default();
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R{} >>\nstartxref\n{}\n%%EOF\n",
+            size, root_id, info, xref_offset
+        ));
+        self.buffer.extend_from_slice(xref.as_bytes());
+        self.buffer
+    }
+}
+
+/// An image XObject's pixels as stored in the file.
+pub enum ImageData {
+    /// A `/DCTDecode` stream, which is a JPEG file as is
+    Jpeg(Vec<u8>),
+    /// 8-bit samples, one channel for gray and three for RGB
+    Raw {
+        width: u32,
+        height: u32,
+        channels: u8,
+        samples: Vec<u8>,
+    },
+}
+
+/// A JPEG to put in place of an embedded image.
+pub struct ReplacementImage {
+    pub jpeg: Vec<u8>,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+/// A PDF whose image XObjects can be swapped for recompressed ones while
+/// every other object, text and vector drawing included, is written back
+/// as it was.
+pub struct ImageRewriter {
+    /// The document with the rewritable images' data taken out, so each
+    /// attempt clones only the rest
+    document: lopdf::Document,
+    originals: Vec<(lopdf::ObjectId, lopdf::Stream)>,
+}
+
+impl ImageRewriter {
+    /// Parses `content` and picks out the images that can be re-encoded:
+    /// 8-bit gray or RGB, unfiltered, Flate or JPEG, and not used as
+    /// another image's mask. Anything else is kept untouched.
+    pub fn load(content: &[u8]) -> Result<(Self, Vec<ImageData>), String> {
+        let mut document = lopdf::Document::load_mem(content).map_err(|e| format!("Could not parse PDF: {}", e))?;
+        if document.is_encrypted() {
+            return Err("Encrypted PDFs cannot be rewritten".to_string());
+        }
+
+        let masks: Vec<lopdf::ObjectId> = document
+            .objects
+            .values()
+            .filter_map(|object| object.as_stream().ok())
+            .flat_map(|stream| [b"SMask".as_slice(), b"Mask".as_slice()].map(|key| stream.dict.get(key)))
+            .filter_map(|mask| mask.and_then(lopdf::Object::as_reference).ok())
+            .collect();
+        let mut candidates = Vec::new();
+        for (&id, object) in &document.objects {
+            if masks.contains(&id) {
+                continue;
+            }
+            if let Ok(stream) = object.as_stream() {
+                if let Some(data) = image_data(stream, &document) {
+                    candidates.push((id, data));
+                }
+            }
+        }
+
+        let mut originals = Vec::new();
+        let mut images = Vec::new();
+        for (id, data) in candidates {
+            if let Some(lopdf::Object::Stream(stream)) = document.objects.get_mut(&id) {
+                let original = stream.clone();
+                stream.content = Vec::new();
+                originals.push((id, original));
+                images.push(data);
+            }
+        }
+        Ok((Self { document, originals }, images))
+    }
+
+    /// Serializes the document with the images `load` returned replaced,
+    /// in the same order. An image with no replacement, or one that would
+    /// not come out smaller, keeps its original data.
+    pub fn write(&self, replacements: &[Option<ReplacementImage>]) -> Result<Vec<u8>, String> {
+        let mut document = self.document.clone();
+        for ((id, original), replacement) in self.originals.iter().zip(replacements) {
+            let stream = match replacement {
+                Some(image) if image.jpeg.len() < original.content.len() => {
+                    let mut dict = original.dict.clone();
+                    dict.set("Width", image.pixel_width as i64);
+                    dict.set("Height", image.pixel_height as i64);
+                    dict.set("ColorSpace", lopdf::Object::Name(b"DeviceRGB".to_vec()));
+                    dict.set("BitsPerComponent", 8);
+                    dict.set("Filter", lopdf::Object::Name(b"DCTDecode".to_vec()));
+                    dict.remove(b"DecodeParms");
+                    lopdf::Stream::new(dict, image.jpeg.clone())
+                }
+                _ => original.clone(),
+            };
+            document.objects.insert(*id, lopdf::Object::Stream(stream));
+        }
+        let mut buffer = Vec::new();
+        document
+            .save_to(&mut buffer)
+            .map_err(|e| format!("Could not write PDF: {}", e))?;
+        Ok(buffer)
+    }
+}
+
+/// The samples of an image XObject this module knows how to re-encode.
+fn image_data(stream: &lopdf::Stream, document: &lopdf::Document) -> Option<ImageData> {
+    let dict = &stream.dict;
+    let number = |key: &[u8]| dict.get(key).and_then(lopdf::Object::as_i64).ok();
+    if dict.get(b"Subtype").and_then(lopdf::Object::as_name).ok() != Some(b"Image".as_slice())
+        || matches!(dict.get(b"ImageMask").and_then(lopdf::Object::as_bool), Ok(true))
+        || dict.has(b"Decode")
+        || number(b"BitsPerComponent") != Some(8)
+    {
+        return None;
+    }
+    let channels = color_channels(dict.get(b"ColorSpace").ok()?, document)?;
+    let width = u32::try_from(number(b"Width")?).ok()?;
+    let height = u32::try_from(number(b"Height")?).ok()?;
+
+    let filters = match dict.get(b"Filter") {
+        Ok(_) => stream.filters().ok()?,
+        Err(_) => Vec::new(),
+    };
+    match filters.as_slice() {
+        [] => raw_image(width, height, channels, stream.content.clone()),
+        // Predictors would need undoing first; those images are left alone
+        [filter] if filter == "FlateDecode" && !dict.has(b"DecodeParms") => {
+            let mut samples = Vec::new();
+            ZlibDecoder::new(stream.content.as_slice())
+                .take(MAX_INFLATED_STREAM_BYTES)
+                .read_to_end(&mut samples)
+                .ok()?;
+            raw_image(width, height, channels, samples)
+        }
+        [filter] if filter == "DCTDecode" => Some(ImageData::Jpeg(stream.content.clone())),
+        _ => None,
+    }
+}
+
+fn raw_image(width: u32, height: u32, channels: u8, mut samples: Vec<u8>) -> Option<ImageData> {
+    let len = width as usize * height as usize * channels as usize;
+    if samples.len() < len {
+        return None;
+    }
+    samples.truncate(len);
+    Some(ImageData::Raw {
+        width,
+        height,
+        channels,
+        samples,
+    })
+}
+
+/// 1 for gray and 3 for RGB color spaces, including ICC-based ones; `None`
+/// for anything else (CMYK, indexed, separations).
+fn color_channels(color_space: &lopdf::Object, document: &lopdf::Document) -> Option<u8> {
+    let resolve = |object| document.dereference(object).ok().map(|(_, object)| object);
+    match resolve(color_space)? {
+        lopdf::Object::Name(name) => match name.as_slice() {
+            b"DeviceGray" => Some(1),
+            b"DeviceRGB" => Some(3),
+            _ => None,
+        },
+        lopdf::Object::Array(array) => match array.as_slice() {
+            [lopdf::Object::Name(family), profile] if family == b"ICCBased" => {
+                let profile = resolve(profile)?.as_stream().ok()?;
+                match profile.dict.get(b"N").and_then(lopdf::Object::as_i64).ok()? {
+                    1 => Some(1),
+                    3 => Some(3),
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+/// Transformation matrix `[a b c d e f]` as written by `cm`.
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Painting operators other than image XObjects: text, paths, shadings and
+/// inline images. A page using any of them needs a real rasterizer.
+const UNRENDERABLE_OPERATORS: &[&str] = &["BT", "BI", "sh", "f", "F", "f*", "S", "s", "B", "B*", "b", "b*"];
+
+/// An image XObject as drawn on a page, positioned in points from the
+/// page's bottom-left corner.
+pub struct DrawnImage {
+    pub data: ImageData,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// A page that only paints images, as scanners and the converter's own
+/// writer produce, in the order they are drawn.
+pub struct ImagePage {
+    pub width: f32,
+    pub height: f32,
+    pub images: Vec<DrawnImage>,
+}
+
+pub enum RenderError {
+    /// The document has fewer pages than asked for; carries the count
+    NoSuchPage(usize),
+    /// The page draws something only a full rasterizer could
+    Unsupported(String),
+}
+
+/// Reads page `number` (1-based) as a list of placed images. Pages with
+/// text, vector drawing, rotation or skew, or images `image_data` cannot
+/// decode are refused rather than drawn incompletely.
+pub fn image_page(content: &[u8], number: u32) -> Result<ImagePage, RenderError> {
+    let unsupported = |message: &str| RenderError::Unsupported(message.to_string());
+    let document = lopdf::Document::load_mem(content)
+        .map_err(|e| RenderError::Unsupported(format!("Could not parse PDF: {}", e)))?;
+    if document.is_encrypted() {
+        return Err(unsupported("Encrypted PDFs cannot be rendered"));
+    }
+    let pages = document.get_pages();
+    let page_id = *pages.get(&number).ok_or(RenderError::NoSuchPage(pages.len()))?;
+
+    let media_box = inherited(&document, page_id, b"MediaBox")
+        .and_then(|object| object.as_array().ok())
+        .and_then(|array| array.iter().map(|value| value.as_float().ok()).collect::<Option<Vec<f32>>>())
+        .ok_or_else(|| unsupported("The page has no valid MediaBox"))?;
+    let [left, bottom, right, top] = media_box[..] else {
+        return Err(unsupported("The page has no valid MediaBox"));
+    };
+    let rotate = inherited(&document, page_id, b"Rotate").and_then(|object| object.as_i64().ok());
+    if rotate.is_some_and(|degrees| degrees % 360 != 0) {
+        return Err(unsupported("Rotated pages cannot be rendered"));
+    }
+
+    let operations = document
+        .get_and_decode_page_content(page_id)
+        .map_err(|e| RenderError::Unsupported(format!("Could not read the page's content: {}", e)))?
+        .operations;
+    let mut matrix = IDENTITY;
+    let mut saved = Vec::new();
+    let mut images = Vec::new();
+    for operation in &operations {
+        match operation.operator.as_str() {
+            "q" => saved.push(matrix),
+            "Q" => matrix = saved.pop().unwrap_or(IDENTITY),
+            "cm" => {
+                let operands: Option<Vec<f32>> = operation.operands.iter().map(|value| value.as_float().ok()).collect();
+                let operands: Matrix = operands
+                    .and_then(|operands| operands.try_into().ok())
+                    .ok_or_else(|| unsupported("The page has a malformed cm operator"))?;
+                matrix = multiply(&operands, &matrix);
+            }
+            "Do" => {
+                let name = operation.operands.first().and_then(|name| name.as_name().ok());
+                let stream = name
+                    .and_then(|name| page_xobject(&document, page_id, name))
+                    .ok_or_else(|| unsupported("The page draws an XObject that cannot be found"))?;
+                let data = image_data(stream, &document)
+                    .ok_or_else(|| unsupported("The page draws a form or an image in a format that cannot be decoded"))?;
+                let [a, b, c, d, e, f] = matrix;
+                if b.abs() > f32::EPSILON || c.abs() > f32::EPSILON || a <= 0.0 || d <= 0.0 {
+                    return Err(unsupported("Rotated, skewed or mirrored images cannot be rendered"));
+                }
+                images.push(DrawnImage {
+                    data,
+                    x: e - left,
+                    y: f - bottom,
+                    width: a,
+                    height: d,
+                });
+            }
+            operator if UNRENDERABLE_OPERATORS.contains(&operator) => {
+                return Err(unsupported(
+                    "The page has text or vector drawing; only pages made of images, such as scans, can be rendered",
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(ImagePage {
+        width: right - left,
+        height: top - bottom,
+        images,
+    })
+}
+
+/// `first` applied after `second`, in the row-vector convention PDF uses.
+fn multiply(first: &Matrix, second: &Matrix) -> Matrix {
+    let [a1, b1, c1, d1, e1, f1] = *first;
+    let [a2, b2, c2, d2, e2, f2] = *second;
+    [
+        a1 * a2 + b1 * c2,
+        a1 * b2 + b1 * d2,
+        c1 * a2 + d1 * c2,
+        c1 * b2 + d1 * d2,
+        e1 * a2 + f1 * c2 + e2,
+        e1 * b2 + f1 * d2 + f2,
+    ]
+}
+
+/// A page attribute, looked up through the page tree as PDF inheritance
+/// requires.
+fn inherited<'a>(document: &'a lopdf::Document, page_id: lopdf::ObjectId, key: &[u8]) -> Option<&'a lopdf::Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(value) = node.get(key) {
+            return document.dereference(value).ok().map(|(_, value)| value);
+        }
+        let parent = node.get(b"Parent").and_then(lopdf::Object::as_reference).ok()?;
+        node = document.get_dictionary(parent).ok()?;
+    }
+}
+
+fn page_xobject<'a>(document: &'a lopdf::Document, page_id: lopdf::ObjectId, name: &[u8]) -> Option<&'a lopdf::Stream> {
+    let (resources, resource_ids) = document.get_page_resources(page_id);
+    resources
+        .into_iter()
+        .chain(resource_ids.into_iter().filter_map(|id| document.get_dictionary(id).ok()))
+        .find_map(|resources| {
+            let (_, xobjects) = document.dereference(resources.get(b"XObject").ok()?).ok()?;
+            let (_, xobject) = document.dereference(xobjects.as_dict().ok()?.get(name).ok()?).ok()?;
+            xobject.as_stream().ok()
+        })
+}
\ No newline at end of file
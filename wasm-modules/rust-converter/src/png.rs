@@ -0,0 +1,123 @@
+// Adam7-interlaced PNG writer. The png crate behind `image` can only write
+// non-interlaced images, so interlaced output is assembled here: each of the
+// seven passes is filtered on its own, then all of them are deflated into a
+// single IDAT chunk.
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::io::Write;
+
+/// (x start, y start, x step, y step) for each Adam7 pass.
+const PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Encodes RGB (`channels` = 3) or RGBA (`channels` = 4) pixels with 8 or 16
+/// bits per sample. 16-bit samples are given big-endian, as PNG stores them.
+pub fn encode_interlaced(pixels: &[u8], width: u32, height: u32, channels: u8, bit_depth: u8) -> Result<Vec<u8>, String> {
+    let color_type = match channels {
+        3 => 2,
+        4 => 6,
+        _ => return Err(format!("Unsupported channel count {}", channels)),
+    };
+    if bit_depth != 8 && bit_depth != 16 {
+        return Err(format!("Unsupported bit depth {}", bit_depth));
+    }
+    // Filters work on whole pixels, however many bytes those take
+    let bpp = channels as usize * bit_depth as usize / 8;
+
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize * 7);
+    for &(x0, y0, dx, dy) in &PASSES {
+        if x0 >= width || y0 >= height {
+            continue;
+        }
+        let pass_width = (width - x0).div_ceil(dx) as usize;
+        let mut previous = vec![0u8; pass_width * bpp];
+        for y in (y0..height).step_by(dy as usize) {
+            let mut row = Vec::with_capacity(pass_width * bpp);
+            for x in (x0..width).step_by(dx as usize) {
+                let offset = (y as usize * width as usize + x as usize) * bpp;
+                row.extend_from_slice(&pixels[offset..offset + bpp]);
+            }
+            let (filter, filtered) = best_filter(&row, &previous, bpp);
+            raw.push(filter);
+            raw.extend_from_slice(&filtered);
+            previous = row;
+        }
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&raw).map_err(|e| e.to_string())?;
+    let compressed = encoder.finish().map_err(|e| e.to_string())?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Deflate, adaptive filtering, Adam7
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 1]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &compressed);
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+/// Tries all five filter types and keeps the one with the smallest sum of
+/// absolute values, the usual heuristic for what deflates best.
+fn best_filter(row: &[u8], previous: &[u8], bpp: usize) -> (u8, Vec<u8>) {
+    (0..5u8)
+        .map(|filter| (filter, apply_filter(filter, row, previous, bpp)))
+        .min_by_key(|(_, filtered)| filtered.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+        .expect("five candidates")
+}
+
+fn apply_filter(filter: u8, row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    (0..row.len())
+        .map(|i| {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                _ => paeth(left, up, up_left),
+            };
+            row[i].wrapping_sub(predicted)
+        })
+        .collect()
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
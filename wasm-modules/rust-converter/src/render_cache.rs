@@ -0,0 +1,102 @@
+// First pages of PDFs as last rendered, so a listing that shows the same
+// document's thumbnail again skips rasterizing it. Entries are the bitmaps
+// before encoding, keyed by the PDF's digest and the DPI, and the least
+// recently used go first once either bound is reached. Off until limits are
+// set; the WASM build has no environment to read them from.
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RenderCacheLimits {
+    /// Renders kept at most; 0 turns the cache off
+    #[serde(default)]
+    pub max_entries: usize,
+    /// Bitmap bytes kept at most
+    #[serde(default)]
+    pub max_bytes: u64,
+}
+
+type Key = ([u8; 32], u32);
+
+#[derive(Default)]
+pub struct RenderCache {
+    limits: RenderCacheLimits,
+    /// Least recently used first
+    entries: Vec<(Key, RgbImage)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl RenderCache {
+    pub fn set_limits(&mut self, limits: RenderCacheLimits) {
+        self.limits = limits;
+        self.evict_until(limits.max_entries, limits.max_bytes);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limits.max_entries > 0 && self.limits.max_bytes > 0
+    }
+
+    /// A copy of the render for `key`, marking it as the most recently used.
+    pub fn get(&mut self, key: Key) -> Option<RgbImage> {
+        match self.entries.iter().position(|(cached, _)| *cached == key) {
+            Some(index) => {
+                self.hits += 1;
+                let entry = self.entries.remove(index);
+                let image = entry.1.clone();
+                self.entries.push(entry);
+                Some(image)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps `image` unless it alone is over the byte bound.
+    pub fn insert(&mut self, key: Key, image: RgbImage) {
+        let size = bitmap_bytes(&image);
+        if !self.is_enabled() || size > self.limits.max_bytes {
+            return;
+        }
+        self.entries.retain(|(cached, _)| *cached != key);
+        self.evict_until(self.limits.max_entries - 1, self.limits.max_bytes - size);
+        self.entries.push((key, image));
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            entries: self.entries.len(),
+            bytes: self.bytes(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.iter().map(|(_, image)| bitmap_bytes(image)).sum()
+    }
+
+    /// Drops the least recently used renders until at most `entries` of
+    /// them, taking at most `bytes`, are left.
+    fn evict_until(&mut self, entries: usize, bytes: u64) {
+        while !self.entries.is_empty() && (self.entries.len() > entries || self.bytes() > bytes) {
+            self.entries.remove(0);
+        }
+    }
+}
+
+/// The cache's state and use, as reported by `stats()`.
+#[derive(Serialize, Deserialize)]
+pub struct Summary {
+    pub entries: usize,
+    pub bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn bitmap_bytes(image: &RgbImage) -> u64 {
+    image.as_raw().len() as u64
+}
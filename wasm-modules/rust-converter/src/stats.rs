@@ -0,0 +1,98 @@
+// Running totals of conversion attempts since the converter was created, for
+// the `stats()` export. Latency percentiles are taken over a window of the
+// most recent attempts so their memory use stays fixed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Attempts whose latencies are kept for the percentiles
+const LATENCY_WINDOW: usize = 1000;
+
+#[derive(Default)]
+pub struct Recorder {
+    conversions_by_format: HashMap<String, u64>,
+    errors_by_code: HashMap<String, u64>,
+    bytes_in: u64,
+    bytes_out: u64,
+    latency_total_ms: f64,
+    latency_count: u64,
+    recent_latencies_ms: VecDeque<f64>,
+}
+
+impl Recorder {
+    pub fn record_success(&mut self, format: &str, bytes_in: u64, bytes_out: u64, latency_ms: f64) {
+        *self.conversions_by_format.entry(format.to_lowercase()).or_insert(0) += 1;
+        self.bytes_in += bytes_in;
+        self.bytes_out += bytes_out;
+        self.record_latency(latency_ms);
+    }
+
+    pub fn record_failure(&mut self, code: &str, latency_ms: f64) {
+        *self.errors_by_code.entry(code.to_string()).or_insert(0) += 1;
+        self.record_latency(latency_ms);
+    }
+
+    fn record_latency(&mut self, latency_ms: f64) {
+        self.latency_total_ms += latency_ms;
+        self.latency_count += 1;
+        if self.recent_latencies_ms.len() == LATENCY_WINDOW {
+            self.recent_latencies_ms.pop_front();
+        }
+        self.recent_latencies_ms.push_back(latency_ms);
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut recent: Vec<f64> = self.recent_latencies_ms.iter().copied().collect();
+        recent.sort_by(f64::total_cmp);
+        Summary {
+            conversions: self.conversions_by_format.values().sum(),
+            failures: self.errors_by_code.values().sum(),
+            conversions_by_format: self.conversions_by_format.clone(),
+            errors_by_code: self.errors_by_code.clone(),
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            latency_ms: Latency {
+                average: (self.latency_count > 0).then(|| self.latency_total_ms / self.latency_count as f64),
+                p50: percentile(&recent, 50.0),
+                p95: percentile(&recent, 95.0),
+                p99: percentile(&recent, 99.0),
+                max: recent.last().copied(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Summary {
+    /// Successful attempts; a fallback that succeeds after its primary
+    /// format failed counts once here and once under `failures`
+    conversions: u64,
+    failures: u64,
+    conversions_by_format: HashMap<String, u64>,
+    errors_by_code: HashMap<String, u64>,
+    /// Input bytes of successful attempts, counted once per output
+    bytes_in: u64,
+    bytes_out: u64,
+    latency_ms: Latency,
+}
+
+/// Per-attempt latencies in milliseconds; `None` until the first attempt.
+#[derive(Serialize, Deserialize)]
+pub struct Latency {
+    /// Over every attempt since startup
+    average: Option<f64>,
+    /// The percentiles and maximum cover the most recent attempts only
+    p50: Option<f64>,
+    p95: Option<f64>,
+    p99: Option<f64>,
+    max: Option<f64>,
+}
+
+/// Nearest-rank percentile of already sorted values.
+fn percentile(sorted: &[f64], percent: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
@@ -0,0 +1,79 @@
+// SVG rasterization via resvg. Only the geometry is rendered: text needs
+// system fonts and linked raster images need file access, neither of which
+// the WASM build has.
+
+use image::{Rgba, RgbaImage};
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{self, roxmltree, TreeParsing};
+use std::borrow::Cow;
+
+pub struct Document {
+    tree: usvg::Tree,
+    /// Whether the root element sizes itself. Without a size usvg falls back
+    /// to the bounding box of the content, which is no basis for an output.
+    has_intrinsic_size: bool,
+}
+
+impl Document {
+    pub fn parse(content: &[u8]) -> Result<Self, String> {
+        let content = if content.starts_with(&[0x1f, 0x8b]) {
+            Cow::Owned(usvg::decompress_svgz(content).map_err(|e| format!("Could not decompress SVGZ: {}", e))?)
+        } else {
+            Cow::Borrowed(content)
+        };
+        let mut tree = usvg::Tree::from_data(&content, &usvg::Options::default())
+            .map_err(|e| format!("Could not parse SVG: {}", e))?;
+        // The renderer culls by bounding box and skips nodes without one
+        tree.calculate_abs_transforms();
+        tree.calculate_bounding_boxes();
+        Ok(Document {
+            tree,
+            has_intrinsic_size: declares_size(&content),
+        })
+    }
+
+    /// Size in CSS pixels, or `None` when the document does not define one.
+    pub fn intrinsic_size(&self) -> Option<(u32, u32)> {
+        if !self.has_intrinsic_size {
+            return None;
+        }
+        let size = self.tree.size;
+        Some(((size.width().round() as u32).max(1), (size.height().round() as u32).max(1)))
+    }
+
+    /// Renders the whole document stretched to `width` x `height`, over
+    /// `background` if given and transparent otherwise.
+    pub fn rasterize(&self, width: u32, height: u32, background: Option<[u8; 3]>) -> Result<RgbaImage, String> {
+        let mut pixmap =
+            Pixmap::new(width, height).ok_or_else(|| format!("Cannot render SVG at {}x{}", width, height))?;
+        if let Some([r, g, b]) = background {
+            pixmap.fill(Color::from_rgba8(r, g, b, 255));
+        }
+        let size = self.tree.size;
+        let transform = Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        let mut image = RgbaImage::new(width, height);
+        for (target, pixel) in image.pixels_mut().zip(pixmap.pixels()) {
+            let color = pixel.demultiply();
+            *target = Rgba([color.red(), color.green(), color.blue(), color.alpha()]);
+        }
+        Ok(image)
+    }
+}
+
+/// A `viewBox` on the root element, or both `width` and `height` in absolute
+/// units. Percentages are relative to a viewport the file does not have.
+fn declares_size(content: &[u8]) -> bool {
+    let text = match std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+    let document = match roxmltree::Document::parse(text) {
+        Ok(document) => document,
+        Err(_) => return false,
+    };
+    let root = document.root_element();
+    let absolute = |name: &str| root.attribute(name).is_some_and(|value| !value.trim().ends_with('%'));
+    root.has_attribute("viewBox") || (absolute("width") && absolute("height"))
+}
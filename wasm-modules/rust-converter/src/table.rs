@@ -0,0 +1,118 @@
+// CSV inputs rendered as a ruled table on A4 pages. The first record is the
+// header and is repeated at the top of every page; cells too long for their
+// column are cut short with "..".
+
+use crate::font;
+use image::{Rgba, RgbaImage};
+
+const PAGE_WIDTH: u32 = 794;
+const PAGE_HEIGHT: u32 = 1123;
+const MARGIN: u32 = 48;
+const SCALE: u32 = 2;
+// Narrowest a column is squeezed to when the table is too wide for the page
+const MIN_COLUMN_CHARS: usize = 3;
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const RULE_GREY: Rgba<u8> = Rgba([200, 200, 200, 255]);
+
+/// Parses every record, the header included. Rows may have differing
+/// numbers of fields.
+pub fn parse(text: &str, delimiter: u8) -> Result<Vec<Vec<String>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    reader
+        .records()
+        .map(|record| {
+            record
+                .map(|record| record.iter().map(|field| field.trim().to_string()).collect())
+                .map_err(|e| format!("Invalid CSV: {}", e))
+        })
+        .collect()
+}
+
+/// Lays the rows out as pages. An empty table still produces one blank page.
+pub fn render_pages(rows: &[Vec<String>]) -> Vec<RgbaImage> {
+    let char_width = (font::GLYPH_WIDTH + 1) * SCALE;
+    let row_height = (font::GLYPH_HEIGHT + 5) * SCALE;
+    let widths = column_widths(rows, ((PAGE_WIDTH - 2 * MARGIN) / char_width) as usize);
+
+    let (header, body) = match rows.split_first() {
+        Some((header, body)) => (header, body),
+        None => return vec![blank_page()],
+    };
+    let rows_per_page = (((PAGE_HEIGHT - 2 * MARGIN) / row_height) as usize).saturating_sub(1).max(1);
+    let chunks: Vec<&[Vec<String>]> = if body.is_empty() {
+        vec![&[]]
+    } else {
+        body.chunks(rows_per_page).collect()
+    };
+
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            let mut page = blank_page();
+            let mut y = MARGIN;
+            draw_row(&mut page, y, header, &widths, char_width);
+            y += row_height;
+            draw_rule(&mut page, y - SCALE * 2, BLACK);
+            for row in chunk {
+                draw_row(&mut page, y, row, &widths, char_width);
+                y += row_height;
+                draw_rule(&mut page, y - SCALE * 2, RULE_GREY);
+            }
+            page
+        })
+        .collect()
+}
+
+fn blank_page() -> RgbaImage {
+    RgbaImage::from_pixel(PAGE_WIDTH, PAGE_HEIGHT, Rgba([255, 255, 255, 255]))
+}
+
+/// Each column's width in characters, including one character of gap.
+/// Columns start at their longest cell and the widest are narrowed one
+/// character at a time until the table fits `available`.
+fn column_widths(rows: &[Vec<String>], available: usize) -> Vec<usize> {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![MIN_COLUMN_CHARS; columns];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count() + 1);
+        }
+    }
+    while widths.iter().sum::<usize>() > available {
+        let widest = match widths.iter_mut().max() {
+            Some(widest) if *widest > MIN_COLUMN_CHARS => widest,
+            // Even at the minimum the columns overflow; the rest is clipped
+            _ => break,
+        };
+        *widest -= 1;
+    }
+    widths
+}
+
+fn draw_row(page: &mut RgbaImage, y: u32, row: &[String], widths: &[usize], char_width: u32) {
+    let mut x = MARGIN;
+    for (cell, &width) in row.iter().zip(widths) {
+        let text = truncate(cell, width - 1);
+        font::draw_text(page, x, y, SCALE, &text, BLACK);
+        x += width as u32 * char_width;
+    }
+}
+
+fn draw_rule(page: &mut RgbaImage, y: u32, color: Rgba<u8>) {
+    for x in MARGIN..PAGE_WIDTH - MARGIN {
+        page.put_pixel(x, y, color);
+    }
+}
+
+fn truncate(cell: &str, max_chars: usize) -> String {
+    if cell.chars().count() <= max_chars {
+        return cell.to_string();
+    }
+    let kept = max_chars.saturating_sub(2);
+    cell.chars().take(kept).chain("..".chars().take(max_chars - kept)).collect()
+}
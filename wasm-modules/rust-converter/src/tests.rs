@@ -0,0 +1,2699 @@
+use super::*;
+use serde_json::{json, Value};
+
+fn solid(width: u32, height: u32, color: [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, image::Rgb(color)))
+}
+
+fn encode(image: &DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+    bytes
+}
+
+fn png(image: &DynamicImage) -> Vec<u8> {
+    encode(image, image::ImageFormat::Png)
+}
+
+fn jpeg(image: &DynamicImage) -> Vec<u8> {
+    encode(image, image::ImageFormat::Jpeg)
+}
+
+/// A one-image-per-page PDF as the converter writes them, each image
+/// filling the given rectangle in points.
+fn image_pdf(pages: &[(DynamicImage, [f32; 4])]) -> Vec<u8> {
+    let pages: Vec<pdf::Page> = pages
+        .iter()
+        .map(|(image, [x, y, width, height])| pdf::Page {
+            images: vec![pdf::PlacedImage {
+                jpeg: jpeg(image),
+                pixel_width: image.width(),
+                pixel_height: image.height(),
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+            }],
+        })
+        .collect();
+    pdf::write_document(&pages, &pdf::Metadata::default())
+}
+
+/// White paper with a dark rectangle of ink in the middle, like a signature
+/// scanned with a wide margin.
+fn signature_scan(width: u32, height: u32, ink: (u32, u32)) -> DynamicImage {
+    let (left, top) = ((width - ink.0) / 2, (height - ink.1) / 2);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let inked = (left..left + ink.0).contains(&x) && (top..top + ink.1).contains(&y);
+        image::Rgb(if inked { [20, 20, 30] } else { [255, 255, 255] })
+    }))
+}
+
+fn exam_configs(configs: Value) -> HashMap<String, ExamConfig> {
+    serde_json::from_value(configs).unwrap()
+}
+
+fn file(name: &str, mime_type: &str, content: &[u8]) -> Value {
+    json!({
+        "name": name,
+        "content": base64::engine::general_purpose::STANDARD.encode(content),
+        "mime_type": mime_type,
+        "size": content.len(),
+    })
+}
+
+/// A request with inline outputs and no limits, overridden by `fields`.
+fn request(fields: Value) -> ConvertRequest {
+    let mut request = json!({"exam_type": "test", "target_formats": [], "max_sizes": {}, "inline": true});
+    for (key, value) in fields.as_object().unwrap() {
+        request[key] = value.clone();
+    }
+    serde_json::from_value(request).unwrap()
+}
+
+fn convert(converter: &mut DocumentConverter, fields: Value) -> ConvertResponse {
+    converter.convert_documents(&request(fields)).unwrap()
+}
+
+fn output(response: &ConvertResponse, index: usize) -> Vec<u8> {
+    let content = response.files[index].content_base64.as_ref().expect("inline output");
+    base64::engine::general_purpose::STANDARD.decode(content).unwrap()
+}
+
+fn error_code(response: &ConvertResponse) -> &str {
+    assert!(!response.success, "expected the conversion to fail");
+    &response.error.as_ref().unwrap().code
+}
+
+#[test]
+fn pdf_page_renders_to_a_valid_png() {
+    let red = solid(40, 20, [200, 0, 0]);
+    let scan = image_pdf(&[(red, [72.0, 72.0, 144.0, 72.0])]);
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.pdf", "application/pdf", &scan)], "target_formats": ["PNG"]}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+
+    let bytes = output(&response, 0);
+    assert_eq!(image::guess_format(&bytes).unwrap(), image::ImageFormat::Png);
+    let page = image::load_from_memory(&bytes).unwrap().to_rgb8();
+    // A4 at the 150 DPI used for scans
+    assert_eq!(page.dimensions(), (1240, 1754));
+    // The image spans 1-3 inches across and 1-2 inches up from the bottom
+    let inside = page.get_pixel(300, 1754 - 225).0;
+    assert!(inside[0] > 150 && inside[1] < 60, "{:?}", inside);
+    assert_eq!(page.get_pixel(20, 20).0, [255, 255, 255]);
+}
+
+#[test]
+fn pdf_rendering_honours_page_and_dpi() {
+    let scan = image_pdf(&[
+        (solid(8, 8, [0, 0, 200]), [0.0, 0.0, 595.0, 842.0]),
+        (solid(8, 8, [0, 200, 0]), [0.0, 0.0, 595.0, 842.0]),
+    ]);
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("scan.pdf", "application/pdf", &scan)],
+            "target_formats": ["JPEG"],
+            "options": {"page": 2, "dpi": 72},
+        }),
+    );
+    let page = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(page.dimensions(), (595, 842));
+    let center = page.get_pixel(297, 421).0;
+    assert!(center[1] > 150 && center[2] < 60, "{:?}", center);
+
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("scan.pdf", "application/pdf", &scan)],
+            "target_formats": ["PNG"],
+            "options": {"page": 3},
+        }),
+    );
+    assert_eq!(error_code(&response), "INVALID_OPTION");
+}
+
+#[test]
+fn repeated_first_page_renders_come_from_the_cache() {
+    let scan = image_pdf(&[(solid(8, 8, [0, 0, 200]), [0.0, 0.0, 595.0, 842.0])]);
+    let mut converter = DocumentConverter::new();
+    let thumbnail = |converter: &mut DocumentConverter, dpi: u32| {
+        let fields = json!({"files": [file("scan.pdf", "application/pdf", &scan)], "target_formats": ["PNG"], "options": {"dpi": dpi}});
+        output(&convert(converter, fields), 0)
+    };
+    let cache = |converter: &DocumentConverter| serde_json::to_value(converter.stats()).unwrap()["render_cache"].clone();
+
+    // Off by default
+    thumbnail(&mut converter, 36);
+    assert_eq!(cache(&converter)["entries"], 0);
+
+    converter.set_render_cache_limits(serde_json::from_value(json!({"max_entries": 2, "max_bytes": 1_000_000})).unwrap());
+    let first = thumbnail(&mut converter, 36);
+    let second = thumbnail(&mut converter, 36);
+    assert_eq!(first, second);
+    assert_eq!(cache(&converter), json!({"entries": 1, "bytes": 298 * 421 * 3, "hits": 1, "misses": 1}));
+
+    // Another DPI is another entry; a third evicts the least recently used
+    thumbnail(&mut converter, 18);
+    thumbnail(&mut converter, 36);
+    thumbnail(&mut converter, 24);
+    assert_eq!(cache(&converter)["entries"], 2);
+    thumbnail(&mut converter, 36);
+    thumbnail(&mut converter, 18);
+    assert_eq!(cache(&converter)["hits"], 3);
+    assert_eq!(cache(&converter)["misses"], 4);
+}
+
+/// `image_pdf` with a line of real text added to its first page.
+fn text_pdf(text: &str) -> Vec<u8> {
+    let mut document = lopdf::Document::load_mem(&image_pdf(&[(solid(8, 8, [255, 255, 255]), [0.0, 0.0, 1.0, 1.0])])).unwrap();
+    let page_id = document.get_pages()[&1];
+    let content = format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET", text);
+    document.add_page_contents(page_id, content.into_bytes()).unwrap();
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn pdf_with_text_is_not_rendered() {
+    let text_pdf = text_pdf("Roll number 12345");
+    assert!(pdf::has_text_layer(&text_pdf));
+    let mut converter = DocumentConverter::new();
+
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("notes.pdf", "application/pdf", &text_pdf)], "target_formats": ["PNG"]}),
+    );
+    assert_eq!(error_code(&response), "PDF_RENDER_UNSUPPORTED");
+}
+
+#[test]
+fn neet_signature_preset_applies_under_request_overrides() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "neet": {
+            "name": "NEET",
+            "formats": ["JPEG"],
+            "maxSizes": {"JPEG": 20_000},
+            "presets": {"signature": {"width": 140, "height": 60, "trim_borders": true, "quality": 70}},
+        }
+    })));
+    let scan = png(&signature_scan(400, 200, (100, 50)));
+    let signature = |options: Value| {
+        json!({
+            "files": [file("sign.png", "image/png", &scan)],
+            "exam_type": "neet",
+            "preset": "signature",
+            "target_formats": ["JPEG"],
+            "max_sizes": {"JPEG": 20_000},
+            "options": options,
+        })
+    };
+
+    let response = convert(&mut converter, signature(json!({})));
+    let bytes = output(&response, 0);
+    assert!(bytes.len() <= 20_000);
+    let image = image::load_from_memory(&bytes).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (140, 60));
+    // The margin was trimmed away, so the ink fills the output
+    assert!(image.get_pixel(2, 2).0[0] < 100, "{:?}", image.get_pixel(2, 2));
+
+    // The request's own options win, including switching a preset flag off
+    let response = convert(&mut converter, signature(json!({"width": 200, "trim_borders": false})));
+    let image = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (200, 60));
+    assert!(image.get_pixel(2, 2).0[0] > 200, "{:?}", image.get_pixel(2, 2));
+
+    let mut missing = request(signature(json!({})));
+    missing.preset = Some("thumbprint".to_string());
+    let response = converter.convert_documents(&missing).unwrap();
+    assert_eq!(error_code(&response), "UNKNOWN_PRESET");
+}
+
+/// Centre colour of the first image drawn on each page of `content`.
+fn pdf_page_colors(converter: &DocumentConverter, content: &[u8]) -> Vec<[u8; 3]> {
+    let page_count = lopdf::Document::load_mem(content).unwrap().get_pages().len() as u32;
+    (1..=page_count)
+        .map(|number| {
+            let Ok(page) = pdf::image_page(content, number) else { panic!("page {} is not image-only", number) };
+            let image = page.images.into_iter().next().expect("an image on the page").data;
+            let image = converter.decode_pdf_image(image).unwrap().to_rgb8();
+            image.get_pixel(image.width() / 2, image.height() / 2).0
+        })
+        .collect()
+}
+
+fn close_to(actual: [u8; 3], expected: [u8; 3]) -> bool {
+    actual.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 12)
+}
+
+#[test]
+fn combine_puts_image_inputs_on_pages_of_one_pdf_in_upload_order() {
+    let colors = [[200, 30, 30], [30, 200, 30], [30, 30, 200]];
+    let files: Vec<Value> = colors
+        .iter()
+        .enumerate()
+        .map(|(index, &color)| file(&format!("page{}.png", index + 1), "image/png", &png(&solid(60, 80, color))))
+        .collect();
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": files, "target_formats": ["PDF"], "options": {"combine": true}}),
+    );
+    assert!(response.success);
+    assert_eq!(response.files.len(), 1);
+    let pages = pdf_page_colors(&converter, &output(&response, 0));
+    assert_eq!(pages.len(), 3);
+    for (page, expected) in pages.into_iter().zip(colors) {
+        assert!(close_to(page, expected), "{:?} vs {:?}", page, expected);
+    }
+
+    // Without combine every image still gets a PDF of its own
+    let response = convert(&mut converter, json!({"files": files, "target_formats": ["PDF"]}));
+    assert_eq!(response.files.len(), 3);
+
+    let response = convert(
+        &mut converter,
+        json!({"files": files, "target_formats": ["JPEG"], "options": {"combine": true}}),
+    );
+    assert_eq!(error_code(&response), "INVALID_OPTION_FOR_FORMAT");
+}
+
+/// Noise compresses so badly that a small JPEG budget sends the size search
+/// through every quality and scale it has, which makes it slow on purpose.
+fn noise(width: u32, height: u32) -> DynamicImage {
+    let mut state = 0x2545_f491_u32;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        image::Rgb([r, g, b])
+    }))
+}
+
+#[test]
+fn a_file_past_the_timeout_is_reported_while_the_batch_continues() {
+    let slow = png(&noise(1200, 1200));
+    let quick = png(&solid(4, 4, [0, 120, 0]));
+    let mut converter = DocumentConverter::new();
+    converter.set_conversion_timeout_secs(0.2);
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("slow.png", "image/png", &slow), file("quick.png", "image/png", &quick)],
+            "target_formats": ["JPEG"],
+            "max_sizes": {"JPEG": 2048},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+
+    assert_eq!(response.file_errors.len(), 1);
+    let timed_out = &response.file_errors[0];
+    assert_eq!(timed_out.original_name, "slow.png");
+    assert_eq!(timed_out.format, "JPEG");
+    assert_eq!(timed_out.error.code, "CONVERSION_TIMEOUT");
+
+    assert_eq!(response.files.len(), 1);
+    assert_eq!(response.files[0].original_name, "quick.png");
+    let quick = image::load_from_memory(&output(&response, 0)).unwrap();
+    assert_eq!((quick.width(), quick.height()), (4, 4));
+}
+
+#[test]
+fn a_minimum_size_above_the_maximum_is_rejected_before_converting() {
+    let photo = png(&solid(4, 4, [90, 90, 90]));
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &photo)],
+            "target_formats": ["JPEG"],
+            "max_sizes": {"JPEG": 1000},
+            "min_sizes": {"JPEG": 5000},
+            "pad_to_min": true,
+        }),
+    );
+    assert_eq!(error_code(&response), "INVALID_REQUEST");
+    assert!(response.files.is_empty());
+
+    // The exam's limit counts too when it is the one in effect
+    converter.set_exam_configs(exam_configs(json!({
+        "test": {"name": "Test", "formats": ["JPEG"], "maxSizes": {"JPEG": 1000}}
+    })));
+    converter.set_enforce_exam_limits(true);
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &photo)],
+            "target_formats": ["JPEG"],
+            "min_sizes": {"JPEG": 5000},
+        }),
+    );
+    assert_eq!(error_code(&response), "INVALID_REQUEST");
+}
+
+fn stored_url(response: &ConvertResponse, index: usize) -> String {
+    response.files[index].download_url.clone().expect("stored output")
+}
+
+#[test]
+fn cleanup_removes_expired_files_and_their_disk_blobs() {
+    let dir = std::env::temp_dir().join(format!("converter-cleanup-{}", std::process::id()));
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_backend(StorageBackend::Disk { dir: dir.clone() }).unwrap();
+    let fields = |color: [u8; 3]| {
+        json!({
+            "files": [file("photo.png", "image/png", &png(&solid(8, 8, color)))],
+            "target_formats": ["PNG"],
+            "inline": false,
+        })
+    };
+    let expired = convert(&mut converter, fields([200, 10, 10]));
+    let live = convert(&mut converter, fields([10, 10, 200]));
+    let (expired_url, live_url) = (stored_url(&expired, 0), stored_url(&live, 0));
+    let expired_id = expired_url.strip_prefix("blob:").unwrap();
+    converter.temp_storage.get_mut(expired_id).unwrap().expires_at = 0.0;
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    let report = converter.cleanup_expired();
+    assert_eq!(report.removed_files, 1);
+    assert_eq!(report.freed_bytes, expired.files[0].size);
+    assert_eq!(converter.download(&expired_url).unwrap_err().code, "FILE_NOT_FOUND");
+    assert!(converter.download(&live_url).is_ok());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // The sweep before each batch runs the same cleanup
+    let live_id = live_url.strip_prefix("blob:").unwrap();
+    converter.temp_storage.get_mut(live_id).unwrap().expires_at = 0.0;
+    convert(&mut converter, json!({"files": [file("a.png", "image/png", &png(&solid(2, 2, [0, 0, 0])))], "target_formats": ["PNG"]}));
+    assert!(!converter.temp_storage.contains_key(live_id));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn validation_rules_report_the_failing_rule() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "test": {
+            "name": "Test",
+            "formats": ["JPEG"],
+            "maxSizes": {},
+            "validationRules": {"minAspectRatio": 0.7, "maxAspectRatio": 0.8, "minBackgroundBrightness": 180},
+        }
+    })));
+    let photo = |width, height, background| {
+        json!({"files": [file("photo.png", "image/png", &png(&solid(width, height, background)))], "target_formats": ["JPEG"]})
+    };
+
+    let response = convert(&mut converter, photo(60, 80, [240, 240, 240]));
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+
+    let response = convert(&mut converter, photo(80, 60, [240, 240, 240]));
+    assert_eq!(error_code(&response), "RULE_VIOLATION");
+    assert!(response.error.unwrap().message.contains("above the maximum"));
+
+    let response = convert(&mut converter, photo(60, 80, [40, 40, 40]));
+    assert_eq!(error_code(&response), "RULE_VIOLATION");
+    assert!(response.error.unwrap().message.contains("Background brightness"));
+}
+
+#[test]
+fn single_use_file_downloads_exactly_once() {
+    let mut converter = DocumentConverter::new();
+    let mut upload = file("photo.png", "image/png", &png(&solid(8, 8, [0, 90, 0])));
+    upload["single_use"] = json!(true);
+    let response = convert(&mut converter, json!({"files": [upload], "target_formats": ["PNG"], "inline": false}));
+    let url = stored_url(&response, 0);
+
+    let first = converter.download(&url).unwrap();
+    assert_eq!(image::load_from_memory(&first).unwrap().width(), 8);
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_GONE");
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_GONE");
+}
+
+fn file_data(name: &str, mime_type: &str, content: &[u8]) -> FileData {
+    serde_json::from_value(file(name, mime_type, content)).unwrap()
+}
+
+/// Smooth shading with fine grain, closer to a photograph than flat colour.
+fn photo(width: u32, height: u32) -> DynamicImage {
+    let grain = noise(width, height).blur(1.5).to_rgb8();
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let g = grain.get_pixel(x, y).0;
+        let shade = |base: u32, n: u8| ((base + n as u32) / 2) as u8;
+        image::Rgb([shade(x * 255 / width, g[0]), shade(y * 255 / height, g[1]), shade((x + y) * 127 / width, g[2])])
+    }))
+}
+
+#[test]
+fn size_estimate_is_close_to_the_real_encode() {
+    let mut converter = DocumentConverter::new();
+    // The estimate is calibrated against uploads at the default quality
+    let mut source = Vec::new();
+    photo(320, 240)
+        .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut source, DEFAULT_JPEG_QUALITY))
+        .unwrap();
+    let upload = file_data("photo.jpg", "image/jpeg", &source);
+    for (quality, width) in [(60, 320), (85, 320), (60, 160)] {
+        let options: ConversionOptions = serde_json::from_value(json!({"quality": quality, "width": width})).unwrap();
+        let estimate = converter.estimate_size(&upload, "JPEG", &options);
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [file("photo.jpg", "image/jpeg", &upload.content)],
+                "target_formats": ["JPEG"],
+                "options": {"quality": quality, "width": width},
+            }),
+        );
+        let actual = response.files[0].size as f64;
+        let error = (estimate as f64 - actual).abs() / actual;
+        assert!(error < 0.2, "q{} w{}: estimated {} bytes, encoded {}", quality, width, estimate, actual);
+    }
+}
+
+#[test]
+fn max_downloads_refuses_the_download_after_the_limit() {
+    let mut converter = DocumentConverter::new();
+    let mut upload = file("photo.png", "image/png", &png(&solid(8, 8, [0, 0, 90])));
+    upload["max_downloads"] = json!(2);
+    let response = convert(&mut converter, json!({"files": [upload], "target_formats": ["PNG"], "inline": false}));
+    let url = stored_url(&response, 0);
+
+    assert!(converter.download(&url).is_ok());
+    assert_eq!(converter.metadata(&url).unwrap().remaining_downloads, Some(1));
+    assert!(converter.download(&url).is_ok());
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_GONE");
+
+    // Expiry still wins over downloads left
+    let mut upload = file("photo.png", "image/png", &png(&solid(8, 8, [90, 0, 0])));
+    upload["max_downloads"] = json!(5);
+    let response = convert(&mut converter, json!({"files": [upload], "target_formats": ["PNG"], "inline": false}));
+    let url = stored_url(&response, 0);
+    converter.temp_storage.get_mut(url.strip_prefix("blob:").unwrap()).unwrap().expires_at = 0.0;
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_NOT_FOUND");
+}
+
+#[test]
+fn caption_band_extends_the_image_in_its_background_colour() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&solid(120, 80, [0, 0, 200])))],
+            "target_formats": ["PNG"],
+            "options": {"caption": {"text": "A B", "height_px": 30, "background": "#ffff00", "text_color": "#000000"}},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!((response.files[0].width, response.files[0].height), (Some(120), Some(110)));
+
+    let captioned = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(captioned.dimensions(), (120, 110));
+    assert_eq!(captioned.get_pixel(60, 40).0, [0, 0, 200]);
+    // Band corners are clear of the centred text
+    assert_eq!(captioned.get_pixel(1, 81).0, [255, 255, 0]);
+    assert_eq!(captioned.get_pixel(118, 108).0, [255, 255, 0]);
+    // The text is drawn somewhere in the band
+    let band = (80..110).flat_map(|y| (0..120).map(move |x| (x, y)));
+    assert!(band.into_iter().any(|(x, y)| captioned.get_pixel(x, y).0 == [0, 0, 0]));
+}
+
+#[test]
+fn text_and_scanned_pdfs_are_told_apart() {
+    let converter = DocumentConverter::new();
+    let text = converter.inspect(&file_data("notes.pdf", "application/pdf", &text_pdf("Admit card")));
+    assert_eq!(text.has_text_layer, Some(true));
+    assert_eq!(text.raster_dpi, Some(TEXT_PDF_RASTER_DPI));
+    assert_eq!(text.page_count, Some(1));
+
+    let scan = image_pdf(&[(solid(8, 8, [50, 50, 50]), [0.0, 0.0, 595.0, 842.0])]);
+    let scan = converter.inspect(&file_data("scan.pdf", "application/pdf", &scan));
+    assert_eq!(scan.has_text_layer, Some(false));
+    assert_eq!(scan.raster_dpi, Some(SCANNED_PDF_RASTER_DPI));
+}
+
+#[test]
+fn input_policy_blocks_denied_types_only() {
+    let mut converter = DocumentConverter::new();
+    converter.set_input_policy(serde_json::from_value(json!({"allowed_mime_types": ["image/png", "image/jpeg"]})).unwrap());
+    let image = png(&solid(4, 4, [1, 2, 3]));
+    let response = convert(&mut converter, json!({"files": [file("a.png", "image/png", &image)], "target_formats": ["JPEG"]}));
+    assert!(response.success);
+
+    let scan = image_pdf(&[(solid(4, 4, [1, 2, 3]), [0.0, 0.0, 100.0, 100.0])]);
+    let response = convert(&mut converter, json!({"files": [file("a.pdf", "application/pdf", &scan)], "target_formats": ["PDF"]}));
+    assert_eq!(error_code(&response), "INPUT_TYPE_BLOCKED");
+
+    converter.set_input_policy(serde_json::from_value(json!({"denied_mime_types": ["image/png"]})).unwrap());
+    let response = convert(&mut converter, json!({"files": [file("a.png", "image/png", &image)], "target_formats": ["JPEG"]}));
+    assert_eq!(error_code(&response), "INPUT_TYPE_BLOCKED");
+}
+
+#[test]
+fn auto_target_picks_jpeg_for_photos_and_png_for_flat_colour() {
+    let mut converter = DocumentConverter::new();
+    let screenshot = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([[255, 255, 255], [0, 0, 0], [220, 30, 30], [30, 30, 220]][((x / 16 + y / 16) % 4) as usize])
+    }));
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&photo(64, 64))), file("shot.png", "image/png", &png(&screenshot))],
+            "target_formats": ["AUTO"],
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(response.files[0].format, "JPEG");
+    assert_eq!(image::guess_format(&output(&response, 0)).unwrap(), image::ImageFormat::Jpeg);
+    assert_eq!(response.files[1].format, "PNG");
+    assert_eq!(image::guess_format(&output(&response, 1)).unwrap(), image::ImageFormat::Png);
+}
+
+#[test]
+fn mislabelled_input_converts_with_a_mime_mismatch_warning() {
+    let mut converter = DocumentConverter::new();
+    let disguised = png(&solid(6, 6, [10, 200, 10]));
+    let response = convert(&mut converter, json!({"files": [file("photo.jpg", "image/jpeg", &disguised)], "target_formats": ["JPEG"]}));
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(response.warnings.len(), 1);
+    assert_eq!(response.warnings[0].code, "MIME_MISMATCH");
+    assert!(response.warnings[0].message.contains("image/png"));
+    assert_eq!(image::guess_format(&output(&response, 0)).unwrap(), image::ImageFormat::Jpeg);
+
+    let correct = convert(&mut converter, json!({"files": [file("photo.png", "image/png", &disguised)], "target_formats": ["JPEG"]}));
+    assert!(serde_json::to_value(&correct).unwrap().get("warnings").is_none());
+}
+
+#[test]
+fn oversized_batch_is_rejected_through_the_wasm_api() {
+    let mut converter = WasmDocumentConverter::new();
+    let image = png(&solid(4, 4, [0, 0, 0]));
+    let request = json!({
+        "files": [file("a.png", "image/png", &image), file("b.png", "image/png", &image)],
+        "exam_type": "test",
+        "target_formats": ["PNG"],
+        "max_sizes": {},
+    })
+    .to_string();
+    let rejection = |converter: &mut WasmDocumentConverter| {
+        let response: Value = serde_json::from_str(&converter.convert_documents(&request)).unwrap();
+        assert_eq!(response["success"], false);
+        response["error"]["code"].as_str().unwrap().to_string()
+    };
+
+    converter.set_max_file_count(1);
+    assert_eq!(rejection(&mut converter), "TOO_MANY_FILES");
+
+    converter.set_max_file_count(10);
+    converter.set_max_file_bytes(image.len() as f64 - 1.0);
+    assert_eq!(rejection(&mut converter), "FILE_TOO_LARGE");
+
+    converter.set_max_file_bytes(1e9);
+    converter.set_max_total_bytes(image.len() as f64 * 1.5);
+    assert_eq!(rejection(&mut converter), "BATCH_TOO_LARGE");
+}
+
+#[test]
+fn stored_file_metadata_matches_the_conversion() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(30, 20, [9, 9, 9])))], "target_formats": ["JPEG"], "inline": false}),
+    );
+    let converted = &response.files[0];
+    let url = stored_url(&response, 0);
+    let metadata = converter.metadata(&url).unwrap();
+    assert_eq!(metadata.format, "JPEG");
+    assert_eq!(metadata.content_type, "image/jpeg");
+    assert_eq!(metadata.size, converted.size);
+    assert_eq!((metadata.width, metadata.height), (Some(30), Some(20)));
+    assert_eq!(metadata.sha256, converted.sha256);
+    // Reading metadata is not a download, and the bytes agree with it
+    assert_eq!(converter.download(&url).unwrap().len() as u64, metadata.size);
+
+    assert_eq!(converter.metadata("blob:no-such-file").err().unwrap().code, "FILE_NOT_FOUND");
+}
+
+#[test]
+fn streaming_hands_over_each_output_before_the_summary() {
+    let mut converter = DocumentConverter::new();
+    let image = png(&solid(6, 6, [40, 80, 120]));
+    let request = request(json!({
+        "files": [file("a.png", "image/png", &image), file("b.png", "image/png", &image)],
+        "target_formats": ["JPEG", "PNG"],
+    }));
+    let mut lines = Vec::new();
+    let summary = converter
+        .convert_documents_with(&request, |converted| lines.push(serde_json::to_string(converted).unwrap()))
+        .unwrap();
+    lines.push(serde_json::to_string(&summary).unwrap());
+
+    assert_eq!(lines.len(), 4 + 1);
+    let streamed: Vec<Value> = lines[..4].iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let names: Vec<&str> = streamed.iter().map(|file| file["converted_name"].as_str().unwrap()).collect();
+    let summary_names: Vec<&str> = summary.files.iter().map(|file| file.converted_name.as_str()).collect();
+    assert_eq!(names, summary_names);
+    assert_eq!(serde_json::from_str::<Value>(&lines[4]).unwrap()["success"], true);
+}
+
+#[test]
+fn fallback_format_takes_over_only_on_size_failures() {
+    let mut converter = DocumentConverter::new();
+    let scan = png(&photo(96, 96));
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("scan.png", "image/png", &scan)],
+            "target_formats": ["PNG"],
+            "max_sizes": {"PNG": 6000},
+            "format_fallbacks": {"PNG": ["JPEG"]},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let converted = &response.files[0];
+    assert_eq!(converted.format, "JPEG");
+    assert_eq!(converted.fallback_from.as_deref(), Some("PNG"));
+    assert!(converted.size <= 6000);
+    assert_eq!(image::guess_format(&output(&response, 0)).unwrap(), image::ImageFormat::Jpeg);
+
+    // Without a fallback the same limit fails
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.png", "image/png", &scan)], "target_formats": ["PNG"], "max_sizes": {"PNG": 6000}}),
+    );
+    assert_eq!(error_code(&response), "SIZE_LIMIT_EXCEEDED");
+
+    // A conversion that cannot happen at all is not retried as the fallback
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.png", "image/png", &scan)], "target_formats": ["DOCX"], "format_fallbacks": {"DOCX": ["PNG"]}}),
+    );
+    assert_ne!(error_code(&response), "SIZE_LIMIT_EXCEEDED");
+    assert!(response.files.is_empty());
+}
+
+#[test]
+fn docx_renders_a_non_blank_preview() {
+    let document = docx::write_paragraphs(&["Hall ticket".to_string(), "Roll number 42".to_string()]).unwrap();
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("ticket.docx", DOCX_MIME_TYPE, &document)], "target_formats": ["PNG"]}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let preview = image::load_from_memory(&output(&response, 0)).unwrap().to_luma8();
+    assert!(preview.height() > preview.width(), "a portrait page");
+    let inked = preview.pixels().filter(|pixel| pixel.0[0] < 128).count();
+    assert!(inked > 50, "only {} dark pixels", inked);
+
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("broken.docx", DOCX_MIME_TYPE, b"PK not a document")], "target_formats": ["PNG"]}),
+    );
+    assert_eq!(error_code(&response), "CONVERSION_NOT_SUPPORTED");
+}
+
+/// A tiny PNG whose header claims `width` x `height` pixels, with a few
+/// bytes of image data that would inflate to far less than that.
+fn png_bomb(width: u32, height: u32) -> Vec<u8> {
+    fn chunk(bytes: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        let mut crc = flate2::Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    let mut header = width.to_be_bytes().to_vec();
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut data = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    std::io::Write::write_all(&mut data, &[0; 64]).unwrap();
+
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut bytes, b"IHDR", &header);
+    chunk(&mut bytes, b"IDAT", &data.finish().unwrap());
+    chunk(&mut bytes, b"IEND", &[]);
+    bytes
+}
+
+#[test]
+fn huge_declared_dimensions_are_refused_before_decoding() {
+    let bomb = png_bomb(100_000, 100_000);
+    assert!(bomb.len() < 100);
+    let mut converter = DocumentConverter::new();
+    let response = convert(&mut converter, json!({"files": [file("bomb.png", "image/png", &bomb)], "target_formats": ["JPEG"]}));
+    assert_eq!(error_code(&response), "IMAGE_TOO_LARGE");
+
+    converter.set_max_pixels(15);
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("small.png", "image/png", &png(&solid(4, 4, [0, 0, 0])))], "target_formats": ["JPEG"]}),
+    );
+    assert_eq!(error_code(&response), "IMAGE_TOO_LARGE");
+}
+
+#[test]
+fn malicious_names_are_made_safe_for_downloads() {
+    assert_eq!(sanitize_filename("../../etc/passwd"), "etcpasswd");
+    assert_eq!(sanitize_filename("..\\..\\boot.ini"), "boot.ini");
+    assert_eq!(sanitize_filename("a\r\nSet-Cookie: x.png"), "aSet-Cookie: x.png");
+    let header = content_disposition("say \"hi\".jpg");
+    assert!(header.starts_with("attachment; filename=\"say \\\"hi\\\".jpg\""), "{}", header);
+    assert!(header.contains("filename*=UTF-8''say%20%22hi%22.jpg"), "{}", header);
+
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("../evil\"\r\nX-Injected: 1/photo.png", "image/png", &png(&solid(4, 4, [5, 5, 5])))],
+            "target_formats": ["JPEG"],
+            "inline": false,
+        }),
+    );
+    let name = &response.files[0].converted_name;
+    assert!(!name.contains(['/', '\\', '\r', '\n']) && !name.starts_with('.'), "{:?}", name);
+    let header = converter.download_disposition(&stored_url(&response, 0), None).unwrap();
+    assert!(!header.contains(['\r', '\n']), "{:?}", header);
+}
+
+#[test]
+fn size_failure_carries_retry_suggestions() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.png", "image/png", &png(&photo(96, 96)))], "target_formats": ["PNG"], "max_sizes": {"PNG": 3000}}),
+    );
+    assert_eq!(error_code(&response), "SIZE_LIMIT_EXCEEDED");
+    let error = response.error.unwrap();
+    assert_eq!(error.limit_bytes, Some(3000));
+    let suggestions = error.suggestions.expect("suggestions on a size failure");
+    assert_eq!(suggestions.max_size, 3000);
+    assert!(suggestions.smallest_size > 3000);
+    assert_eq!((suggestions.current_width, suggestions.current_height), (Some(96), Some(96)));
+    assert!(suggestions.resize_would_help);
+    let (width, height) = (suggestions.suggested_width.unwrap(), suggestions.suggested_height.unwrap());
+    assert!(width < 96 && width == height, "{}x{}", width, height);
+}
+
+#[test]
+fn input_is_decoded_once_for_several_targets() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&photo(32, 32)))],
+            "target_formats": ["JPEG", "PNG", "TIFF"],
+            "options": {"width": 16},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(response.files.len(), 3);
+    assert_eq!(converter.input_decodes.get(), 1);
+
+    // The next file is decoded afresh
+    let second = png(&photo(32, 32).fliph());
+    convert(&mut converter, json!({"files": [file("other.png", "image/png", &second)], "target_formats": ["JPEG", "TIFF"], "options": {"width": 16}}));
+    assert_eq!(converter.input_decodes.get(), 2);
+}
+
+#[test]
+fn grid_tiles_six_images_onto_two_pages() {
+    let mut converter = DocumentConverter::new();
+    let files: Vec<Value> = (0..6u8)
+        .map(|i| file(&format!("{}.png", i), "image/png", &png(&solid(20, 30, [i * 40, 0, 0]))))
+        .collect();
+    let response = convert(&mut converter, json!({"files": files, "target_formats": ["PDF"], "options": {"grid": [2, 2]}}));
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(response.files.len(), 1);
+
+    let document = output(&response, 0);
+    assert_eq!(pdf::page_count(&document), 2);
+    let first = pdf::image_page(&document, 1).ok().unwrap();
+    let second = pdf::image_page(&document, 2).ok().unwrap();
+    assert_eq!((first.images.len(), second.images.len()), (4, 2));
+    for image in first.images.iter().chain(&second.images) {
+        // Each keeps its 2:3 aspect inside its cell
+        assert!((image.width / image.height - 2.0 / 3.0).abs() < 0.01);
+        assert!(image.width < pdf::A4_WIDTH_PT / 2.0);
+    }
+}
+
+#[test]
+fn version_reports_the_crate_version_and_formats() {
+    let info: Value = serde_json::from_str(&version()).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!info["git_sha"].as_str().unwrap().is_empty());
+    let formats: Vec<&str> = info["target_formats"].as_array().unwrap().iter().map(|f| f.as_str().unwrap()).collect();
+    assert_eq!(formats, TARGET_FORMATS);
+    let features: Vec<&str> = info["features"].as_array().unwrap().iter().map(|f| f.as_str().unwrap()).collect();
+    assert_eq!(features.contains(&"avif"), cfg!(feature = "avif"));
+    assert_eq!(features.contains(&"debug"), cfg!(feature = "debug"));
+}
+
+/// A dark-to-light edge softened over a few pixels.
+fn soft_edge(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+        let level = (x as i32 - width as i32 / 2).clamp(-4, 4) * 20 + 128;
+        image::Rgb([level as u8; 3])
+    }))
+}
+
+fn edge_contrast(image: &[u8]) -> u8 {
+    let luma = image::load_from_memory(image).unwrap().to_luma8();
+    let row = luma.height() / 2;
+    let (darkest, lightest) = (0..luma.width()).fold((255, 0), |(low, high), x| {
+        let value = luma.get_pixel(x, row).0[0];
+        (value.min(low), value.max(high))
+    });
+    lightest - darkest
+}
+
+#[test]
+fn sharpen_raises_contrast_across_edges() {
+    let mut converter = DocumentConverter::new();
+    let edge = png(&soft_edge(40, 10));
+    let fields = |options: Value| json!({"files": [file("edge.png", "image/png", &edge)], "target_formats": ["PNG"], "options": options});
+    let plain = output(&convert(&mut converter, fields(json!({"strip_metadata": true}))), 0);
+    let sharpened = output(&convert(&mut converter, fields(json!({"sharpen": 2.0}))), 0);
+
+    assert_ne!(plain, sharpened);
+    assert!(edge_contrast(&sharpened) > edge_contrast(&plain), "{} vs {}", edge_contrast(&sharpened), edge_contrast(&plain));
+}
+
+fn luma_stats(image: &[u8]) -> (f64, f64) {
+    let luma = image::load_from_memory(image).unwrap().to_luma8();
+    let values: Vec<f64> = luma.pixels().map(|pixel| pixel.0[0] as f64).collect();
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    (mean, variance)
+}
+
+#[test]
+fn brightness_and_contrast_move_luminance_as_asked() {
+    let mut converter = DocumentConverter::new();
+    let gradient = png(&DynamicImage::ImageRgb8(RgbImage::from_fn(32, 8, |x, _| image::Rgb([(64 + x * 4) as u8; 3]))));
+    let mut adjusted = |options: Value| {
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("g.png", "image/png", &gradient)], "target_formats": ["PNG"], "options": options}),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        luma_stats(&output(&response, 0))
+    };
+    let (mean, variance) = adjusted(json!({"strip_metadata": true}));
+    let (brighter, _) = adjusted(json!({"brightness": 40}));
+    let (_, contrasted) = adjusted(json!({"contrast": 1.5}));
+    let (lightened, _) = adjusted(json!({"gamma": 1.8}));
+    assert!(brighter > mean + 30.0, "{} vs {}", brighter, mean);
+    assert!(contrasted > variance * 1.5, "{} vs {}", contrasted, variance);
+    assert!(lightened > mean, "{} vs {}", lightened, mean);
+
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("g.png", "image/png", &gradient)], "target_formats": ["PNG"], "options": {"brightness": 300}}),
+    );
+    assert_eq!(error_code(&response), "INVALID_OPTION");
+}
+
+#[test]
+fn utf16_text_with_a_bom_converts_like_utf8() {
+    let text = "Admit card\n\nCandidate: Zoë";
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    assert_eq!(text::decode(&utf16, None).unwrap(), text);
+    assert_eq!(text::decode(&[&[0xEF, 0xBB, 0xBF][..], text.as_bytes()].concat(), None).unwrap(), text);
+
+    let mut converter = DocumentConverter::new();
+    let mut to_pdf = |content: &[u8]| {
+        convert(&mut converter, json!({"files": [file("card.txt", "text/plain", content)], "target_formats": ["PDF"]}))
+    };
+    let from_utf16 = to_pdf(&utf16);
+    assert!(from_utf16.success, "{:?}", from_utf16.error.map(|e| e.to_string()));
+    assert_eq!(output(&from_utf16, 0), output(&to_pdf(text.as_bytes()), 0));
+
+    assert_eq!(error_code(&to_pdf(&utf16[..utf16.len() - 1])), "TEXT_DECODE_ERROR");
+}
+
+/// Colour components in a JPEG's start-of-frame header.
+fn jpeg_components(bytes: &[u8]) -> u8 {
+    let sof = bytes.windows(2).position(|marker| marker[0] == 0xFF && matches!(marker[1], 0xC0 | 0xC2)).unwrap();
+    bytes[sof + 9]
+}
+
+#[test]
+fn cmyk_output_is_refused_without_a_colour_profile() {
+    let mut converter = DocumentConverter::new();
+    let fields = |color_space: &str| {
+        json!({
+            "files": [file("photo.png", "image/png", &png(&solid(8, 8, [200, 30, 30])))],
+            "target_formats": ["JPEG"],
+            "options": {"color_space": color_space},
+        })
+    };
+    let response = convert(&mut converter, fields("cmyk"));
+    assert_eq!(error_code(&response), "CMYK_UNSUPPORTED");
+
+    let response = convert(&mut converter, fields("rgb"));
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(jpeg_components(&output(&response, 0)), 3);
+
+    assert_eq!(error_code(&convert(&mut converter, fields("lab"))), "INVALID_OPTION");
+}
+
+#[test]
+fn effective_config_reflects_overridden_settings() {
+    let mut wasm = WasmDocumentConverter::new();
+    let defaults: Value = serde_json::from_str(&wasm.effective_config()).unwrap();
+    assert_eq!(defaults["conversion_timeout_secs"], 30.0);
+
+    wasm.set_storage_ttl_secs(90);
+    wasm.set_conversion_timeout_secs(5);
+    wasm.set_max_file_count(7);
+    wasm.set_enforce_exam_limits(true);
+    assert!(wasm.set_jpeg_backend("jpeg_encoder"));
+    let config: Value = serde_json::from_str(&wasm.effective_config()).unwrap();
+    assert_eq!(config["storage_ttl_secs"], 90.0);
+    assert_eq!(config["conversion_timeout_secs"], 5.0);
+    assert_eq!(config["batch_limits"]["max_file_count"], 7);
+    assert_eq!(config["enforce_exam_limits"], true);
+    assert_eq!(config["jpeg_backend"], "jpeg_encoder");
+    assert_eq!(config["storage_backend"]["backend"], "memory");
+}
+
+#[test]
+fn unknown_target_format_is_named_before_any_file_is_decoded() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(8, 8, [1, 1, 1])))], "target_formats": ["PDF", "XYZ"]}),
+    );
+    assert_eq!(error_code(&response), "UNSUPPORTED_FORMAT");
+    assert!(response.error.unwrap().message.starts_with("Unsupported format: XYZ"));
+    assert!(response.files.is_empty());
+    assert_eq!(converter.input_decodes.get(), 0);
+    assert!(converter.temp_storage.is_empty());
+}
+
+#[test]
+fn filename_templates_fill_placeholders_and_avoid_collisions() {
+    let mut converter = DocumentConverter::new();
+    let image = png(&solid(4, 4, [7, 7, 7]));
+    let names = |converter: &mut DocumentConverter, template: &str| -> Vec<String> {
+        let response = convert(
+            converter,
+            json!({
+                "files": [file("photo.png", "image/png", &image), file("sign.png", "image/png", &image)],
+                "target_formats": ["JPEG", "PDF"],
+                "filename_template": template,
+            }),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        response.files.iter().map(|file| file.converted_name.clone()).collect()
+    };
+
+    assert_eq!(
+        names(&mut converter, "{base}_{index}.{ext}"),
+        ["photo_1.jpg", "photo_1.pdf", "sign_2.jpg", "sign_2.pdf"]
+    );
+    assert_eq!(
+        names(&mut converter, "neet-{format}.{ext}"),
+        ["neet-JPEG.jpg", "neet-PDF.pdf", "neet-JPEG_2.jpg", "neet-PDF_2.pdf"]
+    );
+    assert_eq!(names(&mut converter, "upload"), ["upload", "upload_2", "upload_3", "upload_4"]);
+
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &image)], "target_formats": ["JPEG"], "filename_template": "{name}.{ext}"}),
+    );
+    assert_eq!(error_code(&response), "INVALID_OPTION");
+}
+
+#[test]
+fn enforced_exam_limit_caps_a_larger_requested_size() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "neet": {"name": "NEET", "formats": ["JPEG"], "maxSizes": {"JPEG": 20_000}}
+    })));
+    let upload = png(&photo(400, 300));
+    let fields = json!({
+        "files": [file("photo.png", "image/png", &upload)],
+        "exam_type": "NEET",
+        "target_formats": ["JPEG"],
+        "max_sizes": {"JPEG": 5_000_000},
+    });
+
+    let unenforced = convert(&mut converter, fields.clone());
+    assert!(unenforced.files[0].size > 20_000, "{}", unenforced.files[0].size);
+
+    converter.set_enforce_exam_limits(true);
+    let enforced = convert(&mut converter, fields);
+    assert!(enforced.success, "{:?}", enforced.error.map(|e| e.to_string()));
+    assert!(enforced.files[0].size <= 20_000, "{}", enforced.files[0].size);
+    assert!(output(&enforced, 0).len() <= 20_000);
+}
+
+#[test]
+fn repeated_idempotency_key_replays_the_first_response() {
+    let mut converter = DocumentConverter::new();
+    let fields = json!({
+        "files": [file("photo.png", "image/png", &png(&photo(24, 24)))],
+        "target_formats": ["JPEG"],
+        "idempotency_key": "submit-42",
+        "inline": false,
+    });
+    let first = convert(&mut converter, fields.clone());
+    let second = convert(&mut converter, fields);
+    assert!(first.success);
+    assert_eq!(serde_json::to_value(&first).unwrap(), serde_json::to_value(&second).unwrap());
+    assert_eq!(converter.input_decodes.get(), 1);
+    assert_eq!(converter.temp_storage.len(), 1);
+}
+
+#[test]
+fn storage_cap_rejects_or_evicts_by_policy() {
+    let outputs = |converter: &mut DocumentConverter| -> Vec<ConvertResponse> {
+        // Distinct outputs, as identical ones would share a stored copy
+        [0.5, 1.0, 2.0]
+            .into_iter()
+            .map(|sigma| {
+                let upload = png(&noise(16, 16).blur(sigma));
+                convert(converter, json!({"files": [file("a.png", "image/png", &upload)], "target_formats": ["PNG"], "inline": false}))
+            })
+            .collect()
+    };
+    let mut converter = DocumentConverter::new();
+    let sizes: Vec<u64> = outputs(&mut converter).iter().map(|response| response.files[0].size).collect();
+    let cap = sizes[0] + sizes[1] + sizes[2] / 2;
+
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_limit(serde_json::from_value(json!({"max_bytes": cap, "when_full": "reject"})).unwrap());
+    let responses = outputs(&mut converter);
+    assert!(responses[0].success && responses[1].success);
+    assert_eq!(error_code(&responses[2]), "STORAGE_FULL");
+    assert_eq!(converter.stored_bytes(), sizes[0] + sizes[1]);
+
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_limit(serde_json::from_value(json!({"max_bytes": cap, "when_full": "evict_least_recently_used"})).unwrap());
+    let responses = outputs(&mut converter);
+    assert!(responses.iter().all(|response| response.success));
+    assert!(converter.stored_bytes() <= cap);
+    assert_eq!(converter.download(&stored_url(&responses[0], 0)).unwrap_err().code, "FILE_NOT_FOUND");
+    assert!(converter.download(&stored_url(&responses[2], 0)).is_ok());
+}
+
+#[test]
+fn every_target_format_has_its_conventional_extension() {
+    let expected = [
+        ("PDF", "pdf"),
+        ("JPEG", "jpg"),
+        ("JPG", "jpg"),
+        ("PNG", "png"),
+        ("DOCX", "docx"),
+        ("AVIF", "avif"),
+        ("TIFF", "tiff"),
+    ];
+    for format in TARGET_FORMATS.iter().filter(|format| **format != "AUTO") {
+        let (_, extension) = expected.iter().find(|(name, _)| name == format).expect(format);
+        assert_eq!(extension_for(format), *extension, "{}", format);
+        assert_eq!(extension_for(&format.to_lowercase()), *extension, "{}", format);
+    }
+
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.png", "image/png", &png(&solid(4, 4, [1, 2, 3])))], "target_formats": ["JPEG", "TIFF", "PDF"]}),
+    );
+    let names: Vec<&str> = response.files.iter().map(|file| file.converted_name.as_str()).collect();
+    assert_eq!(names, ["scan.jpg", "scan.tiff", "scan.pdf"]);
+}
+
+#[test]
+fn purge_removes_every_stored_file() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(6, 6, [3, 3, 3])))], "target_formats": ["JPEG", "PNG"], "inline": false}),
+    );
+    let url = stored_url(&response, 0);
+    assert!(converter.download(&url).is_ok());
+
+    let report = converter.purge_all();
+    assert_eq!(report.removed_files, 2);
+    assert_eq!(report.freed_bytes, response.files[0].size + response.files[1].size);
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_NOT_FOUND");
+    assert_eq!(converter.stored_bytes(), 0);
+}
+
+#[test]
+fn qr_stamp_holds_the_code_for_its_data() {
+    let data = "NEET-2026-000123";
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&solid(200, 160, [120, 60, 200])))],
+            "target_formats": ["PNG"],
+            "options": {"qr": {"data": data, "position": "bottom_right", "size_px": 100}},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let stamped = image::load_from_memory(&output(&response, 0)).unwrap().to_luma8();
+
+    // No QR reader is bundled, so read the module grid back and compare it
+    // with the code for `data`
+    let code = qrcode::QrCode::new(data.as_bytes()).unwrap();
+    let modules = code.width() as u32;
+    let scale = 100 / (modules + 2 * QR_QUIET_ZONE_MODULES);
+    let margin = (100 - (modules + 2 * QR_QUIET_ZONE_MODULES) * scale) / 2 + QR_QUIET_ZONE_MODULES * scale;
+    let (left, top) = (200 - 100, 160 - 100);
+    let read: Vec<qrcode::Color> = (0..modules * modules)
+        .map(|i| {
+            let (col, row) = (i % modules, i / modules);
+            let pixel = stamped.get_pixel(left + margin + col * scale + scale / 2, top + margin + row * scale + scale / 2);
+            if pixel.0[0] < 128 { qrcode::Color::Dark } else { qrcode::Color::Light }
+        })
+        .collect();
+    assert_eq!(read, code.to_colors());
+    // The quiet zone is white and the photo outside the stamp untouched
+    assert_eq!(stamped.get_pixel(left + 1, top + 1).0[0], 255);
+    assert_ne!(stamped.get_pixel(10, 10).0[0], 255);
+}
+
+/// The interlace method byte of a PNG's IHDR chunk.
+fn png_interlace_method(bytes: &[u8]) -> u8 {
+    assert_eq!(&bytes[12..16], b"IHDR");
+    bytes[28]
+}
+
+#[test]
+fn interlace_sets_the_adam7_flag_and_keeps_the_pixels() {
+    let mut converter = DocumentConverter::new();
+    let source = photo(20, 12);
+    let fields = |interlace: bool| {
+        json!({"files": [file("photo.jpg", "image/jpeg", &jpeg(&source))], "target_formats": ["PNG"], "options": {"interlace": interlace}})
+    };
+    let plain = output(&convert(&mut converter, fields(false)), 0);
+    let interlaced = output(&convert(&mut converter, fields(true)), 0);
+    assert_eq!(png_interlace_method(&plain), 0);
+    assert_eq!(png_interlace_method(&interlaced), 1);
+    assert_eq!(
+        image::load_from_memory(&plain).unwrap().to_rgb8(),
+        image::load_from_memory(&interlaced).unwrap().to_rgb8()
+    );
+}
+
+#[test]
+fn malformed_json_gets_a_structured_error() {
+    let mut converter = WasmDocumentConverter::new();
+    let response: Value = serde_json::from_str(&converter.convert_documents("{bad json")).unwrap();
+    assert_eq!(response["success"], false);
+    assert_eq!(response["error"]["code"], "INVALID_JSON");
+    assert!(response["error"]["message"].as_str().unwrap().len() > 10);
+
+    let response: Value = serde_json::from_str(&converter.convert_documents(r#"{"files": 3}"#)).unwrap();
+    assert_eq!(response["error"]["code"], "INVALID_REQUEST");
+}
+
+#[cfg(not(feature = "avif"))]
+#[test]
+fn avif_output_needs_the_avif_feature() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(8, 8, [9, 90, 9])))], "target_formats": ["AVIF"]}),
+    );
+    assert_eq!(error_code(&response), "AVIF_UNAVAILABLE");
+
+    // AVIF input can't be decoded either, but is still sized and passed through
+    let avif = avif_header(40, 30);
+    assert!(!INPUT_TYPES.contains(&"image/avif"));
+    let response = convert(&mut converter, json!({"files": [file("photo.avif", "image/avif", &avif)], "target_formats": ["PNG"]}));
+    assert_eq!(error_code(&response), "AVIF_UNAVAILABLE");
+    let response = convert(&mut converter, json!({"files": [file("photo.avif", "image/avif", &avif)], "target_formats": ["AVIF"]}));
+    assert_eq!(output(&response, 0), avif);
+}
+
+/// The boxes of an AVIF file up to its `ispe` property, without image data.
+fn avif_header(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = b"\0\0\0\x14ftypavif\0\0\0\0avif".to_vec();
+    bytes.extend(b"\0\0\0\x14ispe\0\0\0\0");
+    bytes.extend(width.to_be_bytes());
+    bytes.extend(height.to_be_bytes());
+    bytes
+}
+
+#[test]
+fn avif_dimensions_come_from_the_ispe_property() {
+    assert_eq!(image_dimensions(&avif_header(4032, 3024)), Some((4032, 3024)));
+    assert_eq!(avif_dimensions(&avif_header(0, 3024)), None);
+    assert_eq!(avif_dimensions(&avif_header(40, 30)[..30]), None);
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn avif_round_trips_through_png() {
+    let mut converter = DocumentConverter::new();
+    let source = photo(32, 24);
+    let to_avif = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&source))], "target_formats": ["AVIF"], "options": {"quality": 90}}),
+    );
+    assert!(to_avif.success, "{:?}", to_avif.error.map(|e| e.to_string()));
+    let avif = output(&to_avif, 0);
+    assert_eq!(&avif[4..12], b"ftypavif");
+    assert_eq!((to_avif.files[0].width, to_avif.files[0].height), (Some(32), Some(24)));
+    assert!(INPUT_TYPES.contains(&"image/avif"));
+
+    // AVIF -> PNG decodes the pixels back
+    let to_png = convert(&mut converter, json!({"files": [file("photo.avif", "image/avif", &avif)], "target_formats": ["PNG"]}));
+    assert!(to_png.success, "{:?}", to_png.error.map(|e| e.to_string()));
+    let decoded = image::load_from_memory(&output(&to_png, 0)).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), (32, 24));
+    let (expected, actual) = (source.to_rgb8().get_pixel(16, 12).0, decoded.get_pixel(16, 12).0);
+    assert!(expected.iter().zip(actual).all(|(&a, b)| a.abs_diff(b) < 40), "{:?} vs {:?}", expected, actual);
+
+    // ...and PNG -> AVIF again
+    let png_bytes = output(&to_png, 0);
+    let again = convert(&mut converter, json!({"files": [file("photo.png", "image/png", &png_bytes)], "target_formats": ["AVIF"]}));
+    assert!(again.success, "{:?}", again.error.map(|e| e.to_string()));
+    assert_eq!(&output(&again, 0)[4..12], b"ftypavif");
+    assert_eq!((again.files[0].width, again.files[0].height), (Some(32), Some(24)));
+}
+
+#[test]
+fn idempotent_retries_get_the_same_file_ids() {
+    let upload = png(&photo(16, 16));
+    let submit = |key: Option<&str>| {
+        // A fresh converter stands in for a retry that reached another instance
+        let mut converter = DocumentConverter::new();
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("photo.png", "image/png", &upload)], "target_formats": ["JPEG", "PNG"], "idempotency_key": key, "inline": false}),
+        );
+        (stored_url(&response, 0), stored_url(&response, 1))
+    };
+    let first = submit(Some("candidate-7"));
+    assert_eq!(submit(Some("candidate-7")), first);
+    assert_ne!(first.0, first.1);
+    assert_ne!(submit(Some("candidate-8")), first);
+    assert_ne!(submit(None), submit(None));
+}
+
+#[test]
+fn identical_uploads_in_one_idempotent_batch_get_distinct_ids() {
+    let upload = png(&photo(16, 16));
+    let submit = || {
+        let mut converter = DocumentConverter::new();
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("a.png", "image/png", &upload), file("b.png", "image/png", &upload)], "target_formats": ["JPEG"], "idempotency_key": "candidate-9", "inline": false}),
+        );
+        assert_eq!(converter.temp_storage.len(), 2);
+        (stored_url(&response, 0), stored_url(&response, 1))
+    };
+    let first = submit();
+    assert_ne!(first.0, first.1);
+    assert_eq!(submit(), first);
+}
+
+#[test]
+fn identical_outputs_share_one_stored_blob() {
+    let dir = std::env::temp_dir().join(format!("converter-dedup-{}", std::process::id()));
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_backend(StorageBackend::Disk { dir: dir.clone() }).unwrap();
+    let upload = png(&solid(12, 12, [30, 60, 90]));
+    let fields = json!({"files": [file("photo.png", "image/png", &upload)], "target_formats": ["JPEG"], "inline": false});
+    let first = convert(&mut converter, fields.clone());
+    let second = convert(&mut converter, fields);
+    let (first_url, second_url) = (stored_url(&first, 0), stored_url(&second, 0));
+
+    assert_ne!(first_url, second_url);
+    assert_eq!(converter.temp_storage.len(), 2);
+    assert_eq!(converter.blobs().count(), 1);
+    assert_eq!(converter.stored_bytes(), first.files[0].size);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // The blob outlives the first id that referred to it
+    converter.temp_storage.get_mut(first_url.strip_prefix("blob:").unwrap()).unwrap().expires_at = 0.0;
+    converter.cleanup_expired();
+    assert_eq!(converter.download(&second_url).unwrap().len() as u64, second.files[0].size);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    converter.purge_all();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn retained_source_reconverts_to_a_smaller_size() {
+    let mut converter = DocumentConverter::new();
+    let mut upload = file("photo.png", "image/png", &png(&photo(200, 150)));
+    upload["retain_source"] = json!(true);
+    let first = convert(&mut converter, json!({"files": [upload], "target_formats": ["JPEG"]}));
+    let source_id = first.files[0].source_id.clone().expect("retained source");
+
+    let smaller = converter
+        .reconvert(&source_id, request(json!({"target_formats": ["JPEG"], "max_sizes": {"JPEG": first.files[0].size / 2}})))
+        .unwrap();
+    assert!(smaller.success, "{:?}", smaller.error.map(|e| e.to_string()));
+    assert!(smaller.files[0].size <= first.files[0].size / 2);
+    assert_eq!(smaller.files[0].original_name, "photo.png");
+    assert_eq!(image::guess_format(&output(&smaller, 0)).unwrap(), image::ImageFormat::Jpeg);
+
+    let missing = converter.reconvert("no-such-source", request(json!({"target_formats": ["JPEG"]}))).unwrap();
+    assert_eq!(error_code(&missing), "FILE_NOT_FOUND");
+    let not_retained = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(4, 4, [0, 0, 0])))], "target_formats": ["JPEG"]}),
+    );
+    assert!(not_retained.files[0].source_id.is_none());
+}
+
+#[test]
+fn inline_output_is_returned_instead_of_stored() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(10, 6, [0, 0, 0])))], "target_formats": ["JPEG"], "inline": true}),
+    );
+    let converted = &response.files[0];
+    assert!(converted.download_url.is_none());
+    let bytes = output(&response, 0);
+    assert_eq!(bytes.len() as u64, converted.size);
+    let decoded = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (10, 6));
+    assert!(converter.temp_storage.is_empty());
+}
+
+#[test]
+fn svg_renders_at_the_requested_size() {
+    let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10"><rect width="5" height="10" fill="#ff0000"/><rect x="5" width="5" height="10" fill="#0000ff"/></svg>"##;
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("logo.svg", SVG_MIME_TYPE, svg)], "target_formats": ["PNG"], "options": {"width": 256, "height": 256}}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let raster = image::load_from_memory(&output(&response, 0)).unwrap().to_rgba8();
+    assert_eq!(raster.dimensions(), (256, 256));
+    assert_eq!(raster.get_pixel(64, 128).0, [255, 0, 0, 255]);
+    assert_eq!(raster.get_pixel(192, 128).0, [0, 0, 255, 255]);
+
+    let unsized_svg = br##"<svg xmlns="http://www.w3.org/2000/svg"><rect width="5" height="5"/></svg>"##;
+    let response = convert(&mut converter, json!({"files": [file("logo.svg", SVG_MIME_TYPE, unsized_svg)], "target_formats": ["PNG"]}));
+    assert!(!response.success);
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("logo.svg", SVG_MIME_TYPE, unsized_svg)], "target_formats": ["PNG"], "options": {"width": 40, "height": 20}}),
+    );
+    assert_eq!((response.files[0].width, response.files[0].height), (Some(40), Some(20)));
+}
+
+#[test]
+fn wasm_converter_rejects_mislabelled_bytes_when_asked() {
+    let mut converter = WasmDocumentConverter::new();
+    converter.set_reject_mime_mismatch(true);
+    let request = json!({
+        "files": [file("scan.pdf", "application/pdf", &png(&solid(4, 4, [0, 0, 0])))],
+        "exam_type": "test",
+        "target_formats": ["PDF"],
+        "max_sizes": {},
+    });
+    let response: Value = serde_json::from_str(&converter.convert_documents(&request.to_string())).unwrap();
+    assert_eq!(response["success"], false);
+    assert_eq!(response["error"]["code"], "MIME_MISMATCH");
+    let message = response["error"]["message"].as_str().unwrap();
+    assert!(message.contains("application/pdf") && message.contains("image/png"), "{}", message);
+}
+
+#[test]
+fn long_csv_spans_pages_with_the_header_repeated() {
+    let mut csv = String::from("roll;name;score\n");
+    for row in 1..=100 {
+        csv.push_str(&format!("{};Candidate {};{}\n", row, row, row % 97));
+    }
+    let rows = table::parse(&csv, b';').unwrap();
+    assert_eq!(rows[0], ["roll", "name", "score"]);
+    let pages = table::render_pages(&rows);
+    assert!(pages.len() > 1);
+    // The header row at the top of each page is drawn the same
+    let header_height = 48 + (font::GLYPH_HEIGHT + 5) * 2;
+    let header = |page: &RgbaImage| image::imageops::crop_imm(page, 0, 0, page.width(), header_height).to_image();
+    assert!(header(&pages[0]).pixels().any(|pixel| pixel.0[0] == 0));
+    assert_eq!(header(&pages[0]), header(&pages[1]));
+    assert_ne!(pages[0], pages[1]);
+
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("marks.csv", "text/csv", csv.as_bytes())], "target_formats": ["PDF"], "options": {"delimiter": ";"}}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(pdf::page_count(&output(&response, 0)), pages.len());
+}
+
+/// The bit depth byte of a PNG's IHDR chunk.
+fn png_bit_depth(bytes: &[u8]) -> u8 {
+    assert_eq!(&bytes[12..16], b"IHDR");
+    bytes[24]
+}
+
+#[test]
+fn png_bit_depth_follows_the_request() {
+    let mut converter = DocumentConverter::new();
+    let upload = jpeg(&photo(8, 8));
+    let mut depth = |bit_depth: Option<u8>| {
+        convert(
+            &mut converter,
+            json!({"files": [file("photo.jpg", "image/jpeg", &upload)], "target_formats": ["PNG"], "options": {"bit_depth": bit_depth}}),
+        )
+    };
+    assert_eq!(png_bit_depth(&output(&depth(None), 0)), 8);
+    assert_eq!(png_bit_depth(&output(&depth(Some(8)), 0)), 8);
+    let deep = output(&depth(Some(16)), 0);
+    assert_eq!(png_bit_depth(&deep), 16);
+    // Widening keeps every 8-bit value exactly
+    let wide = image::load_from_memory(&deep).unwrap().to_rgb16();
+    let narrow = image::load_from_memory(&output(&depth(Some(8)), 0)).unwrap().to_rgb8();
+    assert!(wide.pixels().zip(narrow.pixels()).all(|(w, n)| (0..3).all(|c| w.0[c] == n.0[c] as u16 * 257)));
+
+    assert_eq!(error_code(&depth(Some(12))), "INVALID_BIT_DEPTH");
+}
+
+#[test]
+fn small_output_is_grown_into_the_size_window() {
+    let mut converter = DocumentConverter::new();
+    let upload = png(&photo(24, 24));
+    let fields = |pad: bool| {
+        json!({
+            "files": [file("photo.png", "image/png", &upload)],
+            "target_formats": ["JPEG"],
+            "min_sizes": {"JPEG": 4000},
+            "max_sizes": {"JPEG": 12_000},
+            "pad_to_min": pad,
+        })
+    };
+
+    assert_eq!(error_code(&convert(&mut converter, fields(false))), "SIZE_BELOW_MINIMUM");
+
+    let grown = convert(&mut converter, fields(true));
+    assert!(grown.success, "{:?}", grown.error.map(|e| e.to_string()));
+    let size = grown.files[0].size;
+    assert!((4000..=12_000).contains(&size), "{}", size);
+    let image = image::load_from_memory(&output(&grown, 0)).unwrap();
+    assert!(image.width() >= 24 && image.width() == image.height());
+}
+
+#[test]
+fn zip_of_two_files_converts_each_entry() {
+    let (photo, sign) = (png(&solid(6, 4, [1, 100, 1])), jpeg(&solid(4, 6, [100, 1, 1])));
+    let zip = archive::pack(archive::BundleFormat::Zip, &[
+        ("photo.png".to_string(), &photo[..]),
+        ("docs/sign.jpg".to_string(), &sign[..]),
+        ("__MACOSX/._photo.png".to_string(), &[0; 8][..]),
+    ])
+    .unwrap();
+    let mut converter = DocumentConverter::new();
+    let response = converter.convert_zip(&zip, request(json!({"target_formats": ["PNG"]}))).unwrap();
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let converted: Vec<(&str, Option<u32>)> =
+        response.files.iter().map(|file| (file.original_name.as_str(), file.width)).collect();
+    assert_eq!(converted, [("photo.png", Some(6)), ("sign.jpg", Some(4))]);
+
+    // Inflation is capped by the batch's byte limit
+    converter.batch_limits_mut().max_total_bytes = 64;
+    let response = converter.convert_zip(&zip, request(json!({"target_formats": ["PNG"]}))).unwrap();
+    assert!(!response.success);
+}
+
+#[test]
+fn config_lint_reports_each_class_of_problem() {
+    let mut converter = DocumentConverter::new();
+    let running = exam_configs(json!({"neet": {"name": "NEET", "formats": ["JPEG"], "maxSizes": {"JPEG": 1000}}}));
+    converter.set_exam_configs(running);
+    let candidate = exam_configs(json!({
+        "jee": {"name": "JEE", "formats": ["JPEG", "HEIC"], "maxSizes": {"JPEG": 1000}},
+        "jee-main": {"name": "jee", "formats": ["PDF"], "maxSizes": {"PDF": 0}},
+        "ssc": {
+            "name": "SSC",
+            "formats": ["JPEG"],
+            "maxSizes": {"JPEG": 1000},
+            "validationRules": {"minAspectRatio": 1.2, "maxAspectRatio": 0.8},
+        },
+    }));
+    let problems = converter.lint_exam_configs(&candidate);
+    let found: Vec<(Option<&str>, &str)> =
+        problems.iter().map(|problem| (problem.exam.as_deref(), problem.code.as_str())).collect();
+    assert!(found.contains(&(Some("jee"), "UNSUPPORTED_FORMAT")), "{:?}", found);
+    assert!(found.contains(&(Some("jee-main"), "DUPLICATE_NAME")), "{:?}", found);
+    assert!(found.contains(&(Some("jee-main"), "CONTRADICTORY_LIMITS")), "{:?}", found);
+    assert!(found.contains(&(Some("ssc"), "CONTRADICTORY_LIMITS")), "{:?}", found);
+    assert_eq!(found.len(), 4, "{:?}", found);
+
+    assert!(converter.lint_exam_configs(&exam_configs(json!({}))).is_empty());
+    // Linting leaves the configs in use alone
+    assert_eq!(converter.exam_configs.keys().collect::<Vec<_>>(), ["neet"]);
+}
+
+#[test]
+fn max_dimension_clamps_the_long_edge_and_exact_size_wins() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "test": {"name": "Test", "formats": ["JPEG"], "maxSizes": {}, "maxDimensions": {"JPEG": 100}}
+    })));
+    let wide = png(&solid(400, 100, [80, 80, 80]));
+    let dimensions = |converter: &mut DocumentConverter, extra: Value| {
+        let mut fields = json!({"files": [file("wide.png", "image/png", &wide)], "target_formats": ["JPEG"]});
+        fields.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let response = convert(converter, fields);
+        let image = image::load_from_memory(&output(&response, 0)).unwrap();
+        (image.width(), image.height())
+    };
+    assert_eq!(dimensions(&mut converter, json!({})), (100, 25));
+    assert_eq!(dimensions(&mut converter, json!({"max_dimensions": {"JPEG": 200}})), (200, 50));
+    assert_eq!(dimensions(&mut converter, json!({"max_dimensions": {"JPEG": [80, 40]}})), (80, 20));
+    assert_eq!(dimensions(&mut converter, json!({"options": {"width": 300, "height": 60}})), (300, 60));
+}
+
+/// An animated WebP of full-canvas lossless frames, one per colour.
+fn animated_webp(width: u32, height: u32, colors: &[[u8; 3]]) -> Vec<u8> {
+    fn chunk(id: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((payload.len() as u32).to_le_bytes());
+        chunk.extend(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+    let u24 = |value: u32| value.to_le_bytes()[..3].to_vec();
+    let mut vp8x = vec![0x02, 0, 0, 0];
+    vp8x.extend(u24(width - 1));
+    vp8x.extend(u24(height - 1));
+    let mut body = b"WEBP".to_vec();
+    body.extend(chunk(b"VP8X", &vp8x));
+    body.extend(chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]));
+    for color in colors {
+        let frame = encode(&solid(width, height, *color), image::ImageFormat::WebP);
+        let mut anmf = [u24(0), u24(0), u24(width - 1), u24(height - 1), u24(100)].concat();
+        anmf.push(0);
+        // The still image's VP8L chunk, after the 12-byte RIFF header
+        anmf.extend(&frame[12..]);
+        body.extend(chunk(b"ANMF", &anmf));
+    }
+    let mut riff = b"RIFF".to_vec();
+    riff.extend((body.len() as u32).to_le_bytes());
+    riff.extend(body);
+    riff
+}
+
+#[test]
+fn animated_webp_converts_one_chosen_frame() {
+    let webp = animated_webp(6, 4, &[[200, 0, 0], [0, 0, 200]]);
+    let mut converter = DocumentConverter::new();
+    for (frame, expected) in [(None, [200, 0, 0]), (Some(1), [0, 0, 200])] {
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("anim.webp", "image/webp", &webp)], "target_formats": ["PNG"], "options": {"frame": frame}}),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        assert_eq!(response.files.len(), 1);
+        let still = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+        assert_eq!(still.dimensions(), (6, 4));
+        assert!(still.pixels().all(|pixel| pixel.0 == expected), "{:?}", still.get_pixel(0, 0));
+    }
+
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("anim.webp", "image/webp", &webp)], "target_formats": ["PNG"], "options": {"frame": 2}}),
+    );
+    assert_eq!(error_code(&response), "INVALID_OPTION");
+}
+
+#[test]
+fn preserve_name_keeps_same_format_names_verbatim() {
+    let mut converter = DocumentConverter::new();
+    let mut names = |preserve: Option<bool>| {
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [
+                    file("IMG_01.JPG", "image/jpeg", &jpeg(&solid(4, 4, [90, 90, 90]))),
+                    file("scan.jpeg", "image/jpeg", &jpeg(&solid(4, 4, [30, 30, 30]))),
+                ],
+                "target_formats": ["JPEG", "PNG"],
+                "preserve_name": preserve,
+            }),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        let mut names: Vec<String> = response.files.into_iter().map(|file| file.converted_name).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(Some(true)), ["IMG_01.JPG", "IMG_01.png", "scan.jpeg", "scan.png"]);
+    assert_eq!(names(Some(false)), ["IMG_01.jpg", "IMG_01.png", "scan.jpg", "scan.png"]);
+    assert_eq!(names(None), names(Some(false)));
+}
+
+#[test]
+fn full_storage_falls_back_to_inline_output_when_configured() {
+    let mut converter = DocumentConverter::new();
+    let fields = json!({
+        "files": [file("photo.png", "image/png", &png(&solid(8, 8, [20, 140, 20])))],
+        "target_formats": ["PNG"],
+        "inline": false,
+    });
+    converter.set_storage_limit(serde_json::from_value(json!({"max_bytes": 16, "when_full": "reject"})).unwrap());
+    assert_eq!(error_code(&convert(&mut converter, fields.clone())), "STORAGE_FULL");
+
+    converter.set_storage_limit(serde_json::from_value(json!({"max_bytes": 16, "when_full": "inline"})).unwrap());
+    let response = convert(&mut converter, fields);
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert!(response.files[0].download_url.is_none());
+    let image = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(image.dimensions(), (8, 8));
+    assert_eq!(image.get_pixel(3, 3).0, [20, 140, 20]);
+    assert!(converter.temp_storage.is_empty());
+}
+
+#[test]
+fn both_jpeg_backends_write_decodable_jpegs() {
+    let source = png(&photo(64, 48));
+    let reference = image::load_from_memory(&source).unwrap().to_rgb8();
+    let mut converter = DocumentConverter::new();
+    for backend in [JpegBackend::Image, JpegBackend::JpegEncoder] {
+        converter.set_jpeg_backend(backend);
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("photo.png", "image/png", &source)], "target_formats": ["JPEG"]}),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        let bytes = output(&response, 0);
+        assert_eq!(image::guess_format(&bytes).unwrap(), image::ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(&bytes).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (64, 48));
+        let error: f64 = decoded
+            .pixels()
+            .zip(reference.pixels())
+            .map(|(a, b)| (0..3).map(|c| (a.0[c] as f64 - b.0[c] as f64).abs()).sum::<f64>())
+            .sum::<f64>()
+            / (64.0 * 48.0 * 3.0);
+        assert!(error < 8.0, "{:?} mean error {}", backend, error);
+    }
+}
+
+/// The info dictionary a PDF's trailer points at, if any.
+fn pdf_info(content: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(content);
+    let trailer = &text[text.rfind("trailer")?..];
+    let id: usize = trailer.split("/Info ").nth(1)?.split(' ').next()?.parse().ok()?;
+    let object = &text[text.find(&format!("\n{} 0 obj", id))?..];
+    Some(object[..object.find("endobj")?].to_string())
+}
+
+#[test]
+fn pdf_metadata_is_written_to_the_info_dictionary() {
+    let mut converter = DocumentConverter::new();
+    let mut pdf = |metadata: Value| {
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [file("photo.png", "image/png", &png(&solid(8, 8, [0, 0, 0])))],
+                "target_formats": ["PDF"],
+                "pdf_metadata": metadata,
+            }),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        output(&response, 0)
+    };
+    let info = pdf_info(&pdf(json!({"title": "Admit card (2025)", "author": "Exam cell", "keywords": ""}))).unwrap();
+    assert!(info.contains(r"/Title (Admit card \(2025\))"), "{}", info);
+    assert!(info.contains("/Author (Exam cell)"), "{}", info);
+    assert!(!info.contains("/Keywords") && !info.contains("/Subject"), "{}", info);
+
+    let info = pdf_info(&pdf(json!({"title": "Résumé"}))).unwrap();
+    assert!(info.contains("/Title <FEFF005200E900730075006D00E9>"), "{}", info);
+
+    assert!(pdf_info(&pdf(Value::Null)).is_none());
+}
+
+#[test]
+fn exam_max_dimensions_downscale_unless_the_request_overrides_them() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "capped": {"name": "Capped", "formats": ["JPEG"], "maxSizes": {}, "maxDimensions": {"JPEG": 1200}},
+        "boxed": {"name": "Boxed", "formats": ["JPEG"], "maxSizes": {}, "maxDimensions": {"JPEG": [900, 300]}},
+    })));
+    let oversized = png(&solid(1600, 400, [120, 60, 30]));
+    let mut dimensions = |exam_type: &str, extra: Value| {
+        let mut fields = json!({
+            "files": [file("scan.png", "image/png", &oversized)],
+            "exam_type": exam_type,
+            "target_formats": ["JPEG"],
+        });
+        fields.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let response = convert(&mut converter, fields);
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        image_dimensions(&output(&response, 0)).unwrap()
+    };
+    assert_eq!(dimensions("capped", json!({})), (1200, 300));
+    assert_eq!(dimensions("boxed", json!({})), (900, 225));
+    assert_eq!(dimensions("capped", json!({"max_dimensions": {"JPEG": 1400}})), (1400, 350));
+    assert_eq!(dimensions("unknown", json!({})), (1600, 400));
+}
+
+#[test]
+fn stats_add_up_over_several_conversions() {
+    let mut converter = DocumentConverter::new();
+    let photo = png(&photo(32, 32));
+    let ok = convert(
+        &mut converter,
+        json!({"files": [file("a.png", "image/png", &photo), file("b.png", "image/png", &photo)], "target_formats": ["JPEG", "PNG"]}),
+    );
+    assert!(ok.success, "{:?}", ok.error.map(|e| e.to_string()));
+    let failed = convert(
+        &mut converter,
+        json!({"files": [file("a.png", "image/png", &photo)], "target_formats": ["PNG"], "max_sizes": {"PNG": 10}}),
+    );
+    let failed_code = error_code(&failed).to_string();
+
+    let stats = serde_json::to_value(converter.stats()).unwrap();
+    assert_eq!(stats["conversions"], 4);
+    assert_eq!(stats["conversions_by_format"], json!({"jpeg": 2, "png": 2}));
+    assert_eq!(stats["failures"], 1);
+    assert_eq!(stats["errors_by_code"], json!({ failed_code: 1 }));
+    assert_eq!(stats["bytes_in"], 4 * photo.len() as u64);
+    assert_eq!(stats["bytes_out"], ok.files.iter().map(|file| file.size).sum::<u64>());
+    let latency = &stats["latency_ms"];
+    assert!(latency["average"].as_f64().unwrap() >= 0.0);
+    assert!(latency["p50"].as_f64().unwrap() <= latency["max"].as_f64().unwrap());
+    assert_eq!(stats["stored_files"], 0);
+}
+
+#[test]
+fn deep_health_runs_a_timed_self_test() {
+    let converter = DocumentConverter::new();
+    let shallow = serde_json::to_value(converter.health(false)).unwrap();
+    assert_eq!(shallow, json!({"status": "ok"}));
+
+    let deep = serde_json::to_value(converter.health(true)).unwrap();
+    assert_eq!(deep["status"], "ok");
+    assert_eq!(deep["self_test"]["passed"], true);
+    assert!(deep["self_test"]["elapsed_ms"].as_f64().unwrap() >= 0.0);
+    assert!(deep["self_test"].get("error").is_none());
+}
+
+#[test]
+fn unwritable_disk_storage_is_reported() {
+    let dir = std::env::temp_dir().join(format!("converter-broken-{}", std::process::id()));
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_backend(StorageBackend::Disk { dir: dir.clone() }).unwrap();
+    // A file where the directory was; permissions alone don't stop root
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::write(&dir, b"").unwrap();
+    let fields = json!({"files": [file("photo.png", "image/png", &png(&solid(8, 8, [30, 60, 90])))], "target_formats": ["PNG"], "inline": false});
+
+    let rejected = convert(&mut converter, fields.clone());
+    assert_eq!(error_code(&rejected), "STORAGE_UNAVAILABLE");
+
+    converter.set_storage_limit(serde_json::from_value(json!({"when_full": "inline"})).unwrap());
+    let inlined = convert(&mut converter, fields);
+    assert!(inlined.files[0].download_url.is_none());
+    assert_eq!(image_dimensions(&output(&inlined, 0)), Some((8, 8)));
+    assert!(converter.temp_storage.is_empty());
+
+    let deep = serde_json::to_value(converter.health(true)).unwrap();
+    assert_eq!(deep["status"], "degraded");
+    assert_eq!(deep["self_test"]["error"]["code"], "STORAGE_UNAVAILABLE");
+    std::fs::remove_file(&dir).unwrap();
+}
+
+#[test]
+fn content_decodes_from_standard_and_url_safe_base64() {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE, URL_SAFE_NO_PAD};
+    // Bytes whose standard encoding uses both `+` and `/`
+    let mut bytes = png(&solid(3, 3, [251, 239, 190]));
+    bytes.extend([0xfb, 0xff, 0xbf]);
+    let standard = STANDARD.encode(&bytes);
+    assert!(standard.contains('+') && standard.contains('/'));
+
+    let mut converter = DocumentConverter::new();
+    let mut outputs = Vec::new();
+    for content in [standard, URL_SAFE.encode(&bytes), URL_SAFE_NO_PAD.encode(&bytes)] {
+        let fields = json!({
+            "files": [{"name": "a.png", "mime_type": "image/png", "size": bytes.len(), "content": content}],
+            "target_formats": ["PNG"],
+        });
+        let request = request(fields);
+        assert_eq!(request.files[0].content, bytes);
+        let response = converter.convert_documents(&request).unwrap();
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        outputs.push(output(&response, 0));
+    }
+    assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]));
+
+    let garbage = json!({"name": "a.png", "mime_type": "image/png", "size": 3, "content": "not base64!"});
+    assert!(serde_json::from_value::<FileData>(garbage).is_err());
+}
+
+#[test]
+fn large_base64_content_decodes_once_into_a_sized_buffer() {
+    use base64::engine::general_purpose::STANDARD;
+    let bytes: Vec<u8> = (0..3_000_001u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    let encoded = STANDARD.encode(&bytes);
+    let decoded = decode_base64(&encoded).unwrap();
+    assert_eq!(decoded, bytes);
+    // No regrowth past the estimate taken from the text's length
+    assert!(decoded.capacity() <= base64::decoded_len_estimate(encoded.len()));
+    let parsed: FileData =
+        serde_json::from_str(&json!({"name": "a.bin", "mime_type": "image/png", "size": 1, "content": encoded}).to_string())
+            .unwrap();
+    assert_eq!(parsed.content, bytes);
+
+    assert!(decode_base64("ab+/cd-_").unwrap_err().contains("mixes"));
+    let mut converter = WasmDocumentConverter::new();
+    for content in ["not base64!", "QUJD=A"] {
+        let fields = json!({"files": [{"name": "a.png", "mime_type": "image/png", "size": 3, "content": content}], "target_formats": ["PNG"]});
+        let response: Value = serde_json::from_str(&converter.convert_documents(&fields.to_string())).unwrap();
+        assert_eq!(response["error"]["code"], "DECODE_ERROR", "{}", content);
+    }
+}
+
+#[test]
+fn ensure_orientation_rotates_a_mismatched_image_clockwise() {
+    let mut landscape = solid(60, 40, [200, 200, 200]).to_rgb8();
+    landscape.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+    let landscape = png(&DynamicImage::ImageRgb8(landscape));
+    let mut converter = DocumentConverter::new();
+    let mut convert_as = |orientation: &str| {
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [file("wide.png", "image/png", &landscape)],
+                "target_formats": ["PNG"],
+                "options": {"ensure_orientation": orientation},
+            }),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        output(&response, 0)
+    };
+
+    let portrait = image::load_from_memory(&convert_as("portrait")).unwrap().to_rgb8();
+    assert_eq!(portrait.dimensions(), (40, 60));
+    assert!(portrait.height() > portrait.width());
+    // Turned clockwise, the top-left corner ends up top-right
+    assert_eq!(portrait.get_pixel(39, 0).0, [255, 0, 0]);
+    assert_eq!(convert_as("landscape"), landscape);
+}
+
+#[test]
+fn contain_pads_with_the_background_image() {
+    // Green on the left half, blue on the right, already at the canvas size
+    let background = png(&DynamicImage::ImageRgb8(RgbImage::from_fn(40, 40, |x, _| {
+        if x < 20 { image::Rgb([0, 160, 0]) } else { image::Rgb([0, 0, 160]) }
+    })));
+    let photo = png(&solid(20, 10, [200, 0, 0]));
+    let mut converter = DocumentConverter::new();
+    let mut contain = |pad: Value| {
+        let mut options = json!({"width": 40, "height": 40, "fit": "contain"});
+        options.as_object_mut().unwrap().extend(pad.as_object().unwrap().clone());
+        convert(
+            &mut converter,
+            json!({"files": [file("photo.png", "image/png", &photo)], "target_formats": ["PNG"], "options": options}),
+        )
+    };
+
+    let response = contain(json!({"pad_image": base64::engine::general_purpose::STANDARD.encode(&background)}));
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let padded = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(padded.dimensions(), (40, 40));
+    // The photo scales to 40x20 and sits between two 10-pixel bands
+    for (x, y) in [(2, 2), (15, 8), (5, 35)] {
+        assert_eq!(padded.get_pixel(x, y).0, [0, 160, 0], "at {},{}", x, y);
+    }
+    for (x, y) in [(37, 2), (25, 37)] {
+        assert_eq!(padded.get_pixel(x, y).0, [0, 0, 160], "at {},{}", x, y);
+    }
+    assert!(close_to(padded.get_pixel(20, 20).0, [200, 0, 0]));
+
+    let response = contain(json!({"pad_color": "#ffff00"}));
+    let padded = image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8();
+    assert_eq!(padded.get_pixel(20, 2).0, [255, 255, 0]);
+
+    let response = contain(json!({"pad_image": base64::engine::general_purpose::STANDARD.encode(b"not an image")}));
+    assert_eq!(error_code(&response), "INVALID_OPTION");
+}
+
+#[test]
+fn empty_batches_and_empty_files_are_rejected_up_front() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(&mut converter, json!({"files": [], "target_formats": ["PNG"]}));
+    assert_eq!(error_code(&response), "NO_FILES");
+
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("ok.png", "image/png", &png(&solid(2, 2, [0, 0, 0]))), file("blank.png", "image/png", &[])],
+            "target_formats": ["PNG"],
+        }),
+    );
+    assert_eq!(error_code(&response), "EMPTY_FILE");
+    assert!(response.error.unwrap().message.contains("blank.png"));
+    assert!(response.files.is_empty());
+}
+
+#[test]
+fn exam_default_quality_sets_the_starting_jpeg_quality() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "lowq": {"name": "Low quality", "formats": ["JPEG"], "maxSizes": {}, "defaultQuality": {"JPEG": 40}}
+    })));
+    let source = png(&photo(96, 96));
+    let mut size = |exam_type: &str, options: Value| {
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [file("photo.png", "image/png", &source)],
+                "exam_type": exam_type,
+                "target_formats": ["JPEG"],
+                "options": options,
+            }),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        response.files[0].size
+    };
+    let global = size("test", json!({}));
+    let exam = size("lowq", json!({}));
+    assert!(exam < global, "exam default {} vs global default {}", exam, global);
+    // The request's own quality still wins
+    assert_eq!(size("lowq", json!({"quality": DEFAULT_JPEG_QUALITY})), global);
+}
+
+#[test]
+fn text_pdf_converts_to_a_docx_with_its_words() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("notice.pdf", "application/pdf", &text_pdf("Admit card issued today"))], "target_formats": ["DOCX"]}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let docx = output(&response, 0);
+    assert_eq!(docx::extract_paragraphs(&docx).unwrap(), ["Admit card issued today"]);
+
+    let scanned = image_pdf(&[(solid(8, 8, [0, 0, 0]), [0.0, 0.0, 100.0, 100.0])]);
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("scan.pdf", "application/pdf", &scanned)], "target_formats": ["DOCX"]}),
+    );
+    assert_eq!(error_code(&response), "NO_TEXT_LAYER");
+}
+
+#[test]
+fn optimize_fits_the_budget_and_reports_its_choices() {
+    let mut converter = DocumentConverter::new();
+    let source = png(&photo(200, 150));
+    let mut optimize = |max_bytes: u64| {
+        let request: OptimizeRequest = serde_json::from_value(json!({
+            "file": file("photo.png", "image/png", &source),
+            "max_bytes": max_bytes,
+            "formats": ["JPEG", "PNG"],
+        }))
+        .unwrap();
+        let result = converter.optimize(&request).unwrap();
+        let content = converter.download(result.file.download_url.as_ref().unwrap()).unwrap();
+        (result, content)
+    };
+
+    for max_bytes in [20_000, 2_000] {
+        let (result, content) = optimize(max_bytes);
+        assert!(content.len() as u64 <= max_bytes, "{} bytes over a {} budget", content.len(), max_bytes);
+        assert_eq!(result.file.size, content.len() as u64);
+        assert_eq!(result.format, "JPEG");
+        assert!(result.quality.is_some_and(|quality| quality >= OPTIMIZE_MIN_QUALITY));
+        assert_eq!(image_dimensions(&content), Some((result.width, result.height)));
+        assert!((result.width as f64 - 200.0 * result.scale).abs() <= 1.0);
+        if max_bytes == 2_000 {
+            assert!(result.scale < 1.0);
+        }
+    }
+
+    let request: OptimizeRequest = serde_json::from_value(json!({
+        "file": file("photo.png", "image/png", &source),
+        "max_bytes": 10,
+        "formats": ["PNG"],
+    }))
+    .unwrap();
+    assert_eq!(converter.optimize(&request).err().unwrap().code, "SIZE_LIMIT_EXCEEDED");
+}
+
+#[test]
+fn exam_zip_places_outputs_in_role_folders() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "ssc": {
+            "name": "SSC",
+            "formats": ["JPEG", "PDF"],
+            "maxSizes": {},
+            "roles": {
+                "photo": {"formats": ["JPEG"], "matchNames": ["photo", "face"], "filename": "{base}_{index}.{ext}"},
+                "signature": {"formats": ["JPEG"], "matchNames": ["sign"]},
+                "documents": {"formats": ["PDF"]},
+            },
+        }
+    })));
+    let image = png(&solid(6, 6, [90, 90, 90]));
+    let upload = archive::pack(archive::BundleFormat::Zip, &[
+        ("photo.png".to_string(), &image[..]),
+        ("face.png".to_string(), &image[..]),
+        ("my_sign.png".to_string(), &image[..]),
+    ])
+    .unwrap();
+    let packed = converter
+        .convert_zip_for_exam(&upload, request(json!({"exam_type": "SSC", "target_formats": ["JPEG", "PDF"]})))
+        .unwrap();
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(packed)).unwrap();
+    let mut paths: Vec<String> = archive.file_names().map(str::to_string).collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            "documents/face.pdf",
+            "documents/my_sign.pdf",
+            "documents/photo.pdf",
+            "photo/face_2.jpg",
+            "photo/photo_1.jpg",
+            "signature/my_sign.jpg",
+        ]
+    );
+    let mut signature = Vec::new();
+    std::io::Read::read_to_end(&mut archive.by_name("signature/my_sign.jpg").unwrap(), &mut signature).unwrap();
+    assert_eq!(image_dimensions(&signature), Some((6, 6)));
+    // Outputs are stored only until they are packed
+    assert!(converter.temp_storage.is_empty());
+
+    // A replayed idempotent request finds its outputs again
+    let keyed = || request(json!({"exam_type": "SSC", "target_formats": ["JPEG", "PDF"], "idempotency_key": "ssc-1"}));
+    let first = converter.convert_zip_for_exam(&upload, keyed()).unwrap();
+    let replayed = converter.convert_zip_for_exam(&upload, keyed()).unwrap();
+    assert_eq!(zip::ZipArchive::new(Cursor::new(replayed)).unwrap().len(), 6);
+    assert_eq!(zip::ZipArchive::new(Cursor::new(first)).unwrap().len(), 6);
+
+    let response = converter.convert_zip_for_exam(&upload, request(json!({"target_formats": ["JPEG"]})));
+    assert_eq!(response.err().unwrap().code, "EXAM_ROLES_MISSING");
+}
+
+/// `(path, content)` of each regular file in a gzipped ustar archive.
+fn tar_gz_entries(packed: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut tar = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(packed), &mut tar).unwrap();
+    let field = |bytes: &[u8]| String::from_utf8(bytes.iter().copied().take_while(|&b| b != 0).collect()).unwrap();
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while tar[offset..offset + 512].iter().any(|&b| b != 0) {
+        let header = &tar[offset..offset + 512];
+        assert_eq!(&header[257..263], b"ustar\0");
+        let checksum: u64 = header.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { 32 } else { b as u64 }).sum();
+        assert_eq!(u64::from_str_radix(field(&header[148..154]).as_str(), 8).unwrap(), checksum);
+        let size = usize::from_str_radix(&field(&header[124..136]), 8).unwrap();
+        let (prefix, name) = (field(&header[345..500]), field(&header[..100]));
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        entries.push((path, tar[offset + 512..offset + 512 + size].to_vec()));
+        offset += 512 + size.div_ceil(512) * 512;
+    }
+    assert_eq!(tar.len(), offset + 1024);
+    entries
+}
+
+#[test]
+fn exam_bundles_can_be_packed_as_tar_gz() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "ssc": {
+            "name": "SSC",
+            "formats": ["JPEG"],
+            "maxSizes": {},
+            "roles": {"photo": {"formats": ["JPEG"]}},
+        }
+    })));
+    let image = png(&solid(6, 6, [90, 90, 90]));
+    let upload = archive::pack(archive::BundleFormat::Zip, &[
+        ("a.png".to_string(), &image[..]),
+        ("sub/a.png".to_string(), &image[..]),
+    ])
+    .unwrap();
+    let packed = converter
+        .convert_zip_for_exam(&upload, request(json!({"exam_type": "SSC", "target_formats": ["JPEG"], "bundle_format": "targz"})))
+        .unwrap();
+    let entries = tar_gz_entries(&packed);
+    let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+    // Same naming and collision handling as the ZIP bundle
+    assert_eq!(paths, ["photo/a.jpg", "photo/a_2.jpg"]);
+    assert_eq!(image_dimensions(&entries[1].1), Some((6, 6)));
+
+    // Paths over 100 bytes go through the prefix field
+    let long = format!("{}/{}.txt", "d".repeat(120), "n".repeat(90));
+    let packed = archive::pack(archive::BundleFormat::TarGz, &[(long.clone(), &b"hello"[..]), ("x".to_string(), &[][..])]).unwrap();
+    assert_eq!(tar_gz_entries(&packed), [(long, b"hello".to_vec()), ("x".to_string(), Vec::new())]);
+    assert!(archive::pack(archive::BundleFormat::TarGz, &[("n".repeat(101), &[][..])]).is_err());
+}
+
+#[test]
+fn disk_storage_serves_downloads_until_the_ttl_passes() {
+    let dir = std::env::temp_dir().join(format!("converter-disk-{}", std::process::id()));
+    let mut converter = DocumentConverter::new();
+    converter.set_storage_backend(StorageBackend::Disk { dir: dir.clone() }).unwrap();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&solid(8, 8, [30, 60, 90])))],
+            "target_formats": ["PNG"],
+            "inline": false,
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let url = stored_url(&response, 0);
+    let id = url.strip_prefix("blob:").unwrap();
+
+    // The bytes live in a file named by their hash, not in memory
+    let blob = dir.join(&response.files[0].sha256);
+    let on_disk = std::fs::read(&blob).unwrap();
+    assert!(matches!(*converter.temp_storage[id].content, Blob::Disk { .. }));
+    assert_eq!(converter.download(&url).unwrap(), on_disk);
+    assert_eq!(image::load_from_memory(&on_disk).unwrap().to_rgb8().get_pixel(4, 4).0, [30, 60, 90]);
+
+    converter.temp_storage.get_mut(id).unwrap().expires_at = 0.0;
+    assert_eq!(converter.download(&url).unwrap_err().code, "FILE_NOT_FOUND");
+    converter.cleanup_expired();
+    assert!(!blob.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn was_reencoded_tells_passthrough_from_a_rewrite() {
+    let source = jpeg(&photo(32, 32));
+    let mut converter = DocumentConverter::new();
+    let mut convert_with = |options: Value| {
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("photo.jpg", "image/jpeg", &source)], "target_formats": ["JPEG"], "options": options}),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        (response.files[0].was_reencoded, output(&response, 0))
+    };
+
+    let (was_reencoded, bytes) = convert_with(json!({}));
+    assert!(!was_reencoded);
+    assert_eq!(bytes, source);
+
+    let (was_reencoded, bytes) = convert_with(json!({"strip_metadata": true}));
+    assert!(was_reencoded);
+    assert_ne!(bytes, source);
+    assert_eq!(image_dimensions(&bytes), Some((32, 32)));
+}
+
+#[test]
+fn trim_borders_crops_to_the_content_region() {
+    let mut scan = solid(60, 40, [255, 255, 255]).to_rgb8();
+    for (x, y) in (10..30).flat_map(|x| (5..25).map(move |y| (x, y))) {
+        scan.put_pixel(x, y, image::Rgb([20, 20, 20]));
+    }
+    // Paper grain inside the default tolerance
+    scan.put_pixel(50, 35, image::Rgb([240, 240, 240]));
+    let scan = png(&DynamicImage::ImageRgb8(scan));
+    let mut converter = DocumentConverter::new();
+    let mut trimmed = |content: &[u8], options: Value| {
+        let response = convert(
+            &mut converter,
+            json!({"files": [file("scan.png", "image/png", content)], "target_formats": ["PNG"], "options": options}),
+        );
+        assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+        image::load_from_memory(&output(&response, 0)).unwrap().to_rgb8()
+    };
+
+    let content = trimmed(&scan, json!({"trim_borders": true}));
+    assert_eq!(content.dimensions(), (20, 20));
+    assert!(content.pixels().all(|pixel| pixel.0 == [20, 20, 20]));
+    // A tighter tolerance keeps the grain inside the crop
+    assert_eq!(trimmed(&scan, json!({"trim_borders": true, "trim_tolerance": 5})).dimensions(), (41, 31));
+    // A uniform image is left whole rather than cropped to nothing
+    let blank = png(&solid(16, 12, [255, 255, 255]));
+    assert_eq!(trimmed(&blank, json!({"trim_borders": true})).dimensions(), (16, 12));
+}
+
+#[test]
+fn variants_produce_one_sized_output_each_per_input() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [
+                file("hero.png", "image/png", &png(&photo(400, 300))),
+                file("logo.png", "image/png", &png(&solid(300, 400, [0, 90, 180]))),
+            ],
+            "target_formats": ["JPEG"],
+            "variants": [
+                {"suffix": "1x", "max_dimension": 100},
+                {"suffix": "2x", "max_dimension": 200, "max_size": 20000},
+            ],
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let outputs: Vec<(&str, Option<&str>, (u32, u32))> = response
+        .files
+        .iter()
+        .enumerate()
+        .map(|(index, file)| {
+            (file.converted_name.as_str(), file.variant.as_deref(), image_dimensions(&output(&response, index)).unwrap())
+        })
+        .collect();
+    assert_eq!(
+        outputs,
+        [
+            ("hero-1x.jpg", Some("1x"), (100, 75)),
+            ("hero-2x.jpg", Some("2x"), (200, 150)),
+            ("logo-1x.jpg", Some("1x"), (75, 100)),
+            ("logo-2x.jpg", Some("2x"), (150, 200)),
+        ]
+    );
+    assert!(response.files.iter().filter(|file| file.variant.as_deref() == Some("2x")).all(|file| file.size <= 20000));
+}
+
+#[test]
+fn every_wasm_export_reports_errors_in_one_shape() {
+    let mut wasm = WasmDocumentConverter::new();
+    let convert_failure = wasm.convert_documents(
+        &json!({"files": [file("a.png", "image/png", &png(&solid(2, 2, [0, 0, 0])))], "exam_type": "test", "target_formats": ["BMP"], "max_sizes": {}})
+            .to_string(),
+    );
+    let responses = [
+        ("convert_documents", convert_failure, "UNSUPPORTED_FORMAT"),
+        ("convert_documents", wasm.convert_documents("{"), "INVALID_JSON"),
+        ("inspect_document", wasm.inspect_document("[]"), "INVALID_REQUEST"),
+        ("file_metadata", wasm.file_metadata("blob:missing"), "FILE_NOT_FOUND"),
+        ("optimize_image", wasm.optimize_image(r#"{"file": null}"#), "INVALID_REQUEST"),
+    ];
+    for (export, json, code) in responses {
+        let response: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(response["success"], false, "{}: {}", export, json);
+        assert_eq!(response["error"]["code"], code, "{}: {}", export, json);
+        assert!(response["error"]["message"].as_str().is_some_and(|message| !message.is_empty()), "{}: {}", export, json);
+        assert!(response.get("error_code").is_none(), "{}: {}", export, json);
+    }
+}
+
+/// Dimensions, compression tag and first pixel of every page of a TIFF.
+fn tiff_pages(content: &[u8]) -> Vec<((u32, u32), u16, Vec<u8>)> {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(content)).unwrap();
+    let mut pages = Vec::new();
+    loop {
+        let dimensions = decoder.dimensions().unwrap();
+        let compression = decoder.get_tag_u32(tiff::tags::Tag::Compression).unwrap() as u16;
+        let tiff::decoder::DecodingResult::U8(pixels) = decoder.read_image().unwrap() else {
+            panic!("expected 8-bit samples");
+        };
+        pages.push((dimensions, compression, pixels[..3].to_vec()));
+        if !decoder.more_images() {
+            return pages;
+        }
+        decoder.next_image().unwrap();
+    }
+}
+
+#[test]
+fn merged_tiff_has_one_page_per_image() {
+    let files = json!([
+        file("front.png", "image/png", &png(&solid(8, 6, [200, 0, 0]))),
+        file("back.png", "image/png", &png(&solid(5, 9, [0, 0, 200]))),
+    ]);
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": files, "target_formats": ["TIFF"], "options": {"merge": true, "tiff_compression": "deflate"}}),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    assert_eq!(response.files.len(), 1);
+    let pages = tiff_pages(&output(&response, 0));
+    assert_eq!(pages.len(), 2);
+    assert_eq!((pages[0].0, pages[1].0), ((8, 6), (5, 9)));
+    // 8 is Adobe Deflate
+    assert!(pages.iter().all(|page| page.1 == 8));
+    assert_eq!((pages[0].2.as_slice(), pages[1].2.as_slice()), (&[200, 0, 0][..], &[0, 0, 200][..]));
+
+    let response = convert(&mut converter, json!({"files": [files[0].clone()], "target_formats": ["TIFF"]}));
+    let pages = tiff_pages(&output(&response, 0));
+    // A single input gives a single LZW page by default
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0].1, 5);
+}
+
+#[test]
+fn size_errors_read_in_units_and_keep_the_raw_numbers() {
+    assert_eq!(format_bytes(1), "1 byte");
+    assert_eq!(format_bytes(1000), "1000 bytes");
+    assert_eq!(format_bytes(123_456), "120.6 KiB");
+    assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+
+    let mut converter = DocumentConverter::new();
+    let source = png(&photo(64, 64));
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &source)], "target_formats": ["PNG"], "max_sizes": {"PNG": 2048}}),
+    );
+    assert_eq!(error_code(&response), "SIZE_LIMIT_EXCEEDED");
+    let error = response.error.unwrap();
+    let actual = error.actual_bytes.unwrap();
+    assert!(actual > 2048);
+    assert_eq!(error.limit_bytes, Some(2048));
+    assert!(error.message.contains(&format_bytes(actual)) && error.message.contains("2.0 KiB"), "{}", error.message);
+
+    let json = serde_json::to_value(ConvertResponse::failure(ConversionError::new("X", "y").with_sizes(10, 5))).unwrap();
+    assert_eq!((json["error"]["actual_bytes"].as_u64(), json["error"]["limit_bytes"].as_u64()), (Some(10), Some(5)));
+}
+
+#[test]
+fn downsampling_pdf_images_keeps_the_text_layer() {
+    let mut document = lopdf::Document::load_mem(&image_pdf(&[(noise(600, 600), [0.0, 0.0, 500.0, 500.0])])).unwrap();
+    let page_id = document.get_pages()[&1];
+    document.add_page_contents(page_id, b"BT /F1 12 Tf 72 720 Td (Hall ticket 2025) Tj ET".to_vec()).unwrap();
+    let mut source = Vec::new();
+    document.save_to(&mut source).unwrap();
+    let max_size = source.len() as u64 / 4;
+
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("ticket.pdf", "application/pdf", &source)],
+            "target_formats": ["PDF"],
+            "max_sizes": {"PDF": max_size},
+            "options": {"downsample_images": true},
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let smaller = output(&response, 0);
+    assert!(smaller.len() as u64 <= max_size, "{} of {}", smaller.len(), source.len());
+    assert!(pdf::has_text_layer(&smaller));
+    assert_eq!(pdf::extract_lines(&smaller), ["Hall ticket 2025"]);
+    assert_eq!(pdf::page_count(&smaller), 1);
+    let (_, images) = pdf::ImageRewriter::load(&smaller).unwrap();
+    assert_eq!(images.len(), 1);
+}
+
+#[test]
+fn query_parameters_describe_a_raw_upload() {
+    let mut converter = DocumentConverter::new();
+    let photo = png(&solid(12, 8, [10, 120, 10]));
+    let response = converter
+        .convert_file(photo.clone(), "image/png", "?exam_type=test&formats=JPEG,PDF&name=my%20photo.png&inline=true&quality=90")
+        .unwrap();
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let names: Vec<&str> = response.files.iter().map(|file| file.converted_name.as_str()).collect();
+    assert_eq!(names, ["my photo.jpg", "my photo.pdf"]);
+    assert_eq!(image_dimensions(&output(&response, 0)), Some((12, 8)));
+    assert_eq!(pdf::page_count(&output(&response, 1)), 1);
+
+    for query in ["exam_type=test", "formats=JPEG", "exam_type=test&formats=JPEG&qualty=90", "exam_type=test&formats=JPEG&quality=high"] {
+        let response = converter.convert_file(photo.clone(), "image/png", query).unwrap();
+        assert_eq!(error_code(&response), "INVALID_REQUEST", "{}", query);
+    }
+}
+
+#[test]
+fn options_are_checked_against_the_requested_formats() {
+    let mut converter = DocumentConverter::new();
+    let mut check = |formats: Value, fallbacks: Value, options: Value| {
+        let response = convert(
+            &mut converter,
+            json!({
+                "files": [file("a.png", "image/png", &png(&solid(4, 4, [0, 0, 0])))],
+                "target_formats": formats,
+                "format_fallbacks": fallbacks,
+                "options": options,
+            }),
+        );
+        response.error.map(|error| (error.code, error.message))
+    };
+
+    for (formats, fallbacks, options) in [
+        (json!(["JPEG"]), json!({}), json!({"quality": 80})),
+        (json!(["PNG"]), json!({}), json!({"interlace": true, "bit_depth": 8})),
+        (json!(["TIFF"]), json!({}), json!({"merge": true, "tiff_compression": "lzw"})),
+        (json!(["AUTO"]), json!({}), json!({"quality": 80, "interlace": true})),
+        (json!(["PNG"]), json!({"PNG": ["JPEG"]}), json!({"quality": 80})),
+    ] {
+        assert_eq!(check(formats.clone(), fallbacks, options.clone()), None, "{} with {}", formats, options);
+    }
+
+    let (code, message) = check(json!(["JPEG"]), json!({}), json!({"interlace": true})).unwrap();
+    assert_eq!(code, "INVALID_OPTION_FOR_FORMAT");
+    assert!(message.contains("interlace only applies to PNG"), "{}", message);
+    let (code, message) = check(json!(["PNG", "PDF"]), json!({}), json!({"merge": true, "bit_depth": 8})).unwrap();
+    assert_eq!(code, "INVALID_OPTION_FOR_FORMAT");
+    assert!(message.contains("merge only applies to TIFF") && !message.contains("bit_depth"), "{}", message);
+}
+
+#[test]
+fn compression_search_stops_at_max_attempts() {
+    let source = png(&noise(160, 160));
+    let mut converter = DocumentConverter::new();
+    let jpeg_under = |converter: &mut DocumentConverter, max_size: u64| {
+        convert(
+            converter,
+            json!({"files": [file("noise.png", "image/png", &source)], "target_formats": ["JPEG"], "max_sizes": {"JPEG": max_size}}),
+        )
+    };
+
+    let response = jpeg_under(&mut converter, 12_000);
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let attempts_used = response.files[0].attempts_used;
+    assert!((1..=DEFAULT_MAX_ATTEMPTS).contains(&attempts_used), "{}", attempts_used);
+
+    converter.set_max_attempts(2);
+    let response = jpeg_under(&mut converter, 12_000);
+    // The size reached above is out of reach with fewer attempts than it took
+    assert!(attempts_used > 2);
+    assert_eq!(error_code(&response), "CANNOT_MEET_SIZE");
+    // Unreachable sizes give up once the budget is spent, with the best size
+    let response = jpeg_under(&mut converter, 200);
+    assert_eq!(error_code(&response), "CANNOT_MEET_SIZE");
+    let error = response.error.unwrap();
+    assert!(error.actual_bytes.unwrap() > 200);
+    assert!(error.message.contains("within 2 encoding attempts"), "{}", error.message);
+}
+
+#[test]
+fn stored_output_reconverts_to_a_tighter_size() {
+    let mut converter = DocumentConverter::new();
+    let first = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&photo(120, 90)))], "target_formats": ["JPEG"], "inline": false}),
+    );
+    assert!(first.success, "{:?}", first.error.map(|e| e.to_string()));
+    let url = stored_url(&first, 0);
+
+    let tighter = first.files[0].size / 2;
+    let smaller = converter
+        .reconvert_output(&url, request(json!({"target_formats": ["JPEG"], "max_sizes": {"JPEG": tighter}})))
+        .unwrap();
+    assert!(smaller.success, "{:?}", smaller.error.map(|e| e.to_string()));
+    assert!(smaller.files[0].size <= tighter);
+    assert_eq!(image_dimensions(&output(&smaller, 0)), Some((120, 90)));
+    // The first output is still there to download
+    assert!(converter.download(&url).is_ok());
+
+    let id = url.strip_prefix("blob:").unwrap();
+    converter.temp_storage.get_mut(id).unwrap().expires_at = 0.0;
+    let expired = converter.reconvert_output(&url, request(json!({"target_formats": ["JPEG"]}))).unwrap();
+    assert_eq!(error_code(&expired), "FILE_NOT_FOUND");
+}
+
+#[test]
+fn make_transparent_keys_out_a_light_background() {
+    let signature = png(&signature_scan(60, 30, (40, 6)));
+    let mut converter = DocumentConverter::new();
+    let mut convert_with = |content: &[u8], format: &str| {
+        convert(
+            &mut converter,
+            json!({"files": [file("sign.png", "image/png", content)], "target_formats": [format], "options": {"make_transparent": true}}),
+        )
+    };
+
+    let response = convert_with(&signature, "PNG");
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let keyed = image::load_from_memory(&output(&response, 0)).unwrap();
+    assert!(keyed.color().has_alpha());
+    let keyed = keyed.to_rgba8();
+    assert_eq!(keyed.get_pixel(2, 2).0[3], 0);
+    assert_eq!(keyed.get_pixel(30, 15).0[3], 255);
+
+    assert_eq!(error_code(&convert_with(&signature, "JPEG")), "INVALID_OPTION_FOR_FORMAT");
+    let dark = png(&solid(20, 20, [30, 30, 30]));
+    assert_eq!(error_code(&convert_with(&dark, "PNG")), "BACKGROUND_NOT_LIGHT");
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn echo_reports_limits_merged_with_the_exam() {
+    let mut converter = DocumentConverter::new();
+    converter.set_exam_configs(exam_configs(json!({
+        "neet": {"name": "NEET", "formats": ["JPEG", "PDF"], "maxSizes": {"JPEG": 50000, "PDF": 300000}}
+    })));
+    converter.set_enforce_exam_limits(true);
+    let fields = json!({
+        "files": [file("photo.pdf", "application/pdf", &png(&solid(4, 4, [0, 0, 0])))],
+        "exam_type": "NEET",
+        "target_formats": ["JPEG", "PDF"],
+        "max_sizes": {"JPEG": 100000, "PDF": 200000},
+        "colour": "red",
+    });
+    let echo = converter.echo_request(&serde_json::from_value(fields.clone()).unwrap(), &fields);
+    assert!(echo.exam_config_found);
+    assert_eq!(echo.max_sizes.get("JPEG"), Some(&Some(50000)));
+    assert_eq!(echo.max_sizes.get("PDF"), Some(&Some(200000)));
+    assert_eq!(echo.files[0].detected_mime_type.as_deref(), Some("image/png"));
+    let codes: Vec<&str> = echo.warnings.iter().map(|warning| warning.code.as_str()).collect();
+    assert!(codes.contains(&"UNKNOWN_FIELD"), "{:?}", codes);
+    // Echoing converts nothing
+    assert!(converter.temp_storage.is_empty());
+}
+
+#[test]
+fn download_disposition_defaults_to_the_stored_name_and_takes_an_override() {
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&solid(4, 4, [0, 0, 0])))], "target_formats": ["JPEG"], "inline": false}),
+    );
+    let url = stored_url(&response, 0);
+
+    assert_eq!(
+        converter.download_disposition(&url, None).unwrap(),
+        r#"attachment; filename="photo.jpg"; filename*=UTF-8''photo.jpg"#
+    );
+    assert_eq!(
+        converter.download_disposition(&url, Some("Résumé \"final\".jpg")).unwrap(),
+        r#"attachment; filename="R_sum_ \"final\".jpg"; filename*=UTF-8''R%C3%A9sum%C3%A9%20%22final%22.jpg"#
+    );
+    for injected in ["a.jpg\r\nSet-Cookie: x=1", "a\n.jpg"] {
+        assert_eq!(converter.download_disposition(&url, Some(injected)).unwrap_err().code, "INVALID_FILENAME");
+    }
+    assert_eq!(converter.download_disposition("blob:missing", None).unwrap_err().code, "FILE_NOT_FOUND");
+}
+
+#[test]
+fn max_megapixels_clamps_the_pixel_count_keeping_the_aspect() {
+    let mut converter = DocumentConverter::new();
+    // A scaled-down stand-in for a 50 MP photo clamped to 12 MP
+    let source = png(&solid(1000, 500, [90, 120, 150]));
+    let mut convert_with = |max_megapixels: f32| {
+        convert(
+            &mut converter,
+            json!({"files": [file("big.png", "image/png", &source)], "target_formats": ["JPEG"], "options": {"max_megapixels": max_megapixels}}),
+        )
+    };
+
+    let response = convert_with(0.12);
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    let (width, height) = image_dimensions(&output(&response, 0)).unwrap();
+    let pixels = width * height;
+    assert!((114_000..=120_000).contains(&pixels), "{}x{}", width, height);
+    assert!((width as f64 / height as f64 - 2.0).abs() < 0.02, "{}x{}", width, height);
+
+    // A budget above the input's leaves it alone
+    let response = convert_with(1.0);
+    assert_eq!(image_dimensions(&output(&response, 0)), Some((1000, 500)));
+    assert_eq!(error_code(&convert_with(0.0)), "INVALID_OPTION");
+}
+
+#[test]
+fn log_lines_follow_the_configured_level_and_format() {
+    use logging::LogLevel::{Debug, Error, Info, Warn};
+    let configure = |settings: &str| -> Value { serde_json::from_str(&configure_logging(settings)).unwrap() };
+    assert_eq!(configure(r#"{"format": "pretty"}"#), json!({"success": true}));
+    assert_eq!(logging::format_line(Info, "converting").as_deref(), Some("converting"));
+    assert_eq!(logging::format_line(Debug, "encoding"), None);
+    {
+        let _span = logging::RequestSpan::enter("req-1".to_string());
+        assert_eq!(logging::format_line(Info, "converting").as_deref(), Some("[req-1] converting"));
+    }
+    assert_eq!(logging::format_line(Info, "converting").as_deref(), Some("converting"));
+
+    configure(r#"{"format": "json", "level": "debug"}"#);
+    let _span = logging::RequestSpan::enter("req-2".to_string());
+    let line: Value = serde_json::from_str(&logging::format_line(Info, "done \"ok\"").unwrap()).unwrap();
+    assert_eq!(line, json!({"level": "info", "message": "done \"ok\"", "request_id": "req-2"}));
+    let line: Value = serde_json::from_str(&logging::format_line(Warn, "rejected").unwrap()).unwrap();
+    assert_eq!(line["level"], "warn");
+    assert!(logging::format_line(Debug, "encoding").is_some());
+
+    // RUST_LOG syntax: this crate's directive wins, other crates' are ignored
+    configure(r#"{"level": "warn"}"#);
+    assert!(logging::format_line(Error, "broken").is_some());
+    assert!(logging::format_line(Warn, "rejected").is_some());
+    assert_eq!(logging::format_line(Info, "converting"), None);
+    configure(r#"{"level": "debug,actix_web=info,document_converter_wasm=error"}"#);
+    assert!(logging::format_line(Error, "broken").is_some());
+    assert_eq!(logging::format_line(Warn, "rejected"), None);
+    configure(r#"{"level": "error,document_converter_wasm::logging=TRACE"}"#);
+    assert!(logging::format_line(Debug, "encoding").is_some());
+
+    configure(r#"{"level": "off"}"#);
+    assert_eq!(logging::format_line(Error, "broken"), None);
+
+    let invalid = configure(r#"{"format": "xml"}"#);
+    assert_eq!(invalid["success"], false);
+    assert_eq!(invalid["error"]["code"], "INVALID_OPTION");
+    assert_eq!(configure(r#"{"level": "loud"}"#)["error"]["code"], "INVALID_OPTION");
+    assert_eq!(configure("{level")["error"]["code"], "INVALID_JSON");
+    assert_eq!(configure("{}"), json!({"success": true}));
+}
+
+#[test]
+fn reported_sha256_matches_the_downloaded_bytes() {
+    use sha2::{Digest, Sha256};
+    let hex = |bytes: &[u8]| Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let mut converter = DocumentConverter::new();
+    let response = convert(
+        &mut converter,
+        json!({
+            "files": [file("photo.png", "image/png", &png(&photo(24, 16)))],
+            "target_formats": ["JPEG", "PDF"],
+            "inline": false,
+        }),
+    );
+    assert!(response.success, "{:?}", response.error.map(|e| e.to_string()));
+    for (index, converted) in response.files.iter().enumerate() {
+        let downloaded = converter.download(&stored_url(&response, index)).unwrap();
+        assert_eq!(converted.sha256, hex(&downloaded), "{}", converted.converted_name);
+        assert_eq!(converted.sha256.len(), 64);
+    }
+    assert_ne!(response.files[0].sha256, response.files[1].sha256);
+
+    let inline = convert(
+        &mut converter,
+        json!({"files": [file("photo.png", "image/png", &png(&photo(24, 16)))], "target_formats": ["JPEG"]}),
+    );
+    assert_eq!(inline.files[0].sha256, hex(&output(&inline, 0)));
+}
+
+#[test]
+fn a_panicking_conversion_becomes_a_file_error() {
+    let crashed = catch_conversion_panic("bad.png", || -> Result<(), ConversionError> { panic!("index out of bounds") });
+    let error = crashed.unwrap_err();
+    assert_eq!(error.code, "CONVERSION_PANIC");
+    assert!(error.message.contains("bad.png") && error.message.contains("index out of bounds"), "{}", error.message);
+
+    let formatted = catch_conversion_panic("odd.tif", || -> Result<(), ConversionError> { panic!("row {} missing", 3) });
+    assert!(formatted.unwrap_err().message.contains("row 3 missing"));
+    assert_eq!(catch_conversion_panic("good.png", || Ok(7)).unwrap(), 7);
+}
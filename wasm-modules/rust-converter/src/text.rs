@@ -0,0 +1,61 @@
+// Decoding of plain-text inputs. A byte-order mark wins over the declared
+// charset; without either the bytes must be valid UTF-8.
+
+/// Returns the text with any byte-order mark removed.
+pub fn decode(content: &[u8], charset: Option<&str>) -> Result<String, String> {
+    if let Some(rest) = content.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return decode_utf8(rest);
+    }
+    if let Some(rest) = content.strip_prefix(&[0xFF, 0xFE]) {
+        return decode_utf16(rest, u16::from_le_bytes);
+    }
+    if let Some(rest) = content.strip_prefix(&[0xFE, 0xFF]) {
+        return decode_utf16(rest, u16::from_be_bytes);
+    }
+
+    let charset = charset.map(|charset| charset.trim().to_ascii_lowercase());
+    match charset.as_deref() {
+        None | Some("utf-8") | Some("utf8") | Some("us-ascii") | Some("ascii") => decode_utf8(content),
+        Some("utf-16le") | Some("utf-16") => decode_utf16(content, u16::from_le_bytes),
+        Some("utf-16be") => decode_utf16(content, u16::from_be_bytes),
+        // Latin-1 maps each byte straight to the code point of the same value
+        Some("iso-8859-1") | Some("latin1") | Some("latin-1") => Ok(content.iter().map(|&b| b as char).collect()),
+        Some(other) => Err(format!("Unsupported charset '{}'", other)),
+    }
+}
+
+fn decode_utf8(content: &[u8]) -> Result<String, String> {
+    String::from_utf8(content.to_vec()).map_err(|e| format!("Text is not valid UTF-8: {}", e))
+}
+
+fn decode_utf16(content: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, String> {
+    let pairs = content.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err("UTF-16 text has an odd number of bytes".to_string());
+    }
+    let units = pairs.map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| format!("Text is not valid UTF-16: {}", e))
+}
+
+/// Splits text into paragraphs at blank lines, joining the lines inside a
+/// paragraph so the page layout can re-wrap them.
+pub fn paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(current.join(" "));
+                current.clear();
+            }
+        } else {
+            current.push(line.trim());
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current.join(" "));
+    }
+    paragraphs
+}